@@ -0,0 +1,511 @@
+//! A compact, streaming archive format for values.
+//!
+//! Unlike the serde encoding, which writes every page byte-for-byte wherever it appears, an
+//! archive writes each distinct page once: pages are identified by their [`Digest`], and later
+//! occurrences refer back to the first by number.  All-zero pages are written as just their size,
+//! and a table lists only its non-null entries, so sparse address spaces stay small.  Null entries
+//! keep their sizes.
+//!
+//! An archive is `MAGIC` followed by one value, written depth-first:
+//!
+//! | value              | encoding                                                        |
+//! |--------------------|-----------------------------------------------------------------|
+//! | null               | `0`                                                             |
+//! | word               | `1`, the word                                                   |
+//! | blob               | `2`, its length, its bytes                                      |
+//! | tuple              | `3`, its length, its elements                                   |
+//! | page               | `4`, a page reference                                           |
+//! | table              | `5`, its size, the number of non-null entries, each as an index |
+//! |                    | and an entry                                                    |
+//! | function           | `6`, the value it reads as                                      |
+//!
+//! where an entry is its mode (`0` null, then as in [`Entry`]) followed by its size (null), page
+//! reference (pages) or table (tables), and a page reference is one of
+//!
+//! - `0`, the page's size, its bytes: a new page, which is given the next page number;
+//! - `1`, the page's size: an all-zero page;
+//! - `2`, a page number: a page seen before.
+//!
+//! Integers are LEB128 varints.  The encoder buffers its output and hands it to a [`Sink`] in
+//! large chunks, and the decoder pulls from a [`Source`], so neither needs the whole archive in
+//! memory.
+//!
+//! Archives may come from anywhere, so the decoder trusts nothing it reads: pages and tables must
+//! have one of the sizes the runtimes support, a table's entries must fit it, values may only be
+//! nested so deep, and blobs and tuples grow as their contents arrive rather than being allocated
+//! at the length the input claims.
+
+use alloc::collections::BTreeMap;
+use core::marker::PhantomData;
+
+use super::prelude::*;
+
+pub const MAGIC: &[u8; 8] = b"ARCAVAL1";
+
+/// How much output is buffered before it is handed to the sink.
+const BUFFER: usize = 1 << 16;
+
+const NULL: u8 = 0;
+const WORD: u8 = 1;
+const BLOB: u8 = 2;
+const TUPLE: u8 = 3;
+const PAGE: u8 = 4;
+const TABLE: u8 = 5;
+const FUNCTION: u8 = 6;
+
+const ENTRY_NULL: u8 = 0;
+const ENTRY_RO_PAGE: u8 = 1;
+const ENTRY_RW_PAGE: u8 = 2;
+const ENTRY_RO_TABLE: u8 = 3;
+const ENTRY_RW_TABLE: u8 = 4;
+
+const PAGE_NEW: u8 = 0;
+const PAGE_ZERO: u8 = 1;
+const PAGE_SEEN: u8 = 2;
+
+/// The sizes a page can have.
+const PAGE_SIZES: [usize; 3] = [1 << 12, 1 << 21, 1 << 30];
+/// The sizes a table can have.
+const TABLE_SIZES: [usize; 4] = [1 << 21, 1 << 30, 1 << 39, 1 << 48];
+/// The number of entries in a table.
+const TABLE_ENTRIES: usize = 512;
+/// How deeply values may be nested in tuples and functions.
+const MAX_DEPTH: usize = 256;
+
+/// Somewhere an archive can be written.
+pub trait Sink {
+    type Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Somewhere an archive can be read from.
+pub trait Source {
+    type Error;
+
+    /// Fills `buf`, or fails (e.g. at the end of the input).
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+impl Sink for Vec<u8> {
+    type Error = core::convert::Infallible;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// The input ended early.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Eof;
+
+impl Source for &[u8] {
+    type Error = Eof;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if self.len() < buf.len() {
+            return Err(Eof);
+        }
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Error<E> {
+    Io(E),
+    /// The input is not an archive, or is corrupt.
+    Invalid,
+    /// The runtime rejected a decoded value (e.g. a function or table entry).
+    Runtime,
+}
+
+/// What writing an archive did.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Pages whose bytes were written.
+    pub pages: u64,
+    /// Pages written as a reference to an identical earlier page.
+    pub duplicates: u64,
+    /// All-zero pages written as just their size.
+    pub zeroes: u64,
+    /// The length of the archive.
+    pub bytes: u64,
+}
+
+/// Writes `value` to `sink` as an archive.
+pub fn write<R: Runtime, S: Sink>(value: &Value<R>, sink: &mut S) -> Result<Stats, S::Error> {
+    let mut encoder = Encoder {
+        sink,
+        buffer: Vec::with_capacity(BUFFER),
+        pages: BTreeMap::new(),
+        stats: Stats::default(),
+        _runtime: PhantomData,
+    };
+    encoder.bytes(MAGIC)?;
+    encoder.value(value)?;
+    encoder.flush()?;
+    Ok(encoder.stats)
+}
+
+/// Reads an archive's value from `source`.
+pub fn read<R: Runtime, S: Source>(source: &mut S) -> Result<Value<R>, Error<S::Error>> {
+    let mut decoder = Decoder {
+        source,
+        pages: Vec::new(),
+        depth: 0,
+    };
+    let mut magic = [0; 8];
+    decoder.bytes(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::Invalid);
+    }
+    decoder.value()
+}
+
+struct Encoder<'a, R: Runtime, S: Sink> {
+    sink: &'a mut S,
+    buffer: Vec<u8>,
+    /// The number of each page written so far.
+    pages: BTreeMap<Digest, u64>,
+    stats: Stats,
+    _runtime: PhantomData<R>,
+}
+
+impl<R: Runtime, S: Sink> Encoder<'_, R, S> {
+    fn flush(&mut self) -> Result<(), S::Error> {
+        self.stats.bytes += self.buffer.len() as u64;
+        self.sink.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), S::Error> {
+        if self.buffer.len() + bytes.len() > BUFFER {
+            self.flush()?;
+        }
+        if bytes.len() > BUFFER {
+            self.stats.bytes += bytes.len() as u64;
+            return self.sink.write_all(bytes);
+        }
+        self.buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn byte(&mut self, x: u8) -> Result<(), S::Error> {
+        self.bytes(&[x])
+    }
+
+    fn int(&mut self, mut x: u64) -> Result<(), S::Error> {
+        let mut buf = [0; 10];
+        let mut len = 0;
+        loop {
+            let byte = (x & 0x7f) as u8;
+            x >>= 7;
+            if x == 0 {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+        self.bytes(&buf[..len])
+    }
+
+    /// Copies `len` bytes produced in chunks by `read(offset, buf)`.
+    fn stream(
+        &mut self,
+        len: usize,
+        mut read: impl FnMut(usize, &mut [u8]) -> usize,
+    ) -> Result<(), S::Error> {
+        let mut chunk = [0; 4096];
+        let mut offset = 0;
+        while offset < len {
+            let n = core::cmp::min(chunk.len(), len - offset);
+            let n = read(offset, &mut chunk[..n]);
+            if n == 0 {
+                break;
+            }
+            self.bytes(&chunk[..n])?;
+            offset += n;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, value: &Value<R>) -> Result<(), S::Error> {
+        match value {
+            Value::Null(_) => self.byte(NULL),
+            Value::Word(word) => {
+                self.byte(WORD)?;
+                self.int(word.read())
+            }
+            Value::Blob(blob) => {
+                self.byte(BLOB)?;
+                self.int(blob.len() as u64)?;
+                self.stream(blob.len(), |offset, buf| blob.read(offset, buf))
+            }
+            Value::Tuple(tuple) => {
+                self.byte(TUPLE)?;
+                self.int(tuple.len() as u64)?;
+                for x in tuple.iter() {
+                    self.value(&x)?;
+                }
+                Ok(())
+            }
+            Value::Page(page) => {
+                self.byte(PAGE)?;
+                self.page(page)
+            }
+            Value::Table(table) => {
+                self.byte(TABLE)?;
+                self.table(table)
+            }
+            Value::Function(function) => {
+                self.byte(FUNCTION)?;
+                self.value(&function.read_cloned())
+            }
+        }
+    }
+
+    fn page(&mut self, page: &Page<R>) -> Result<(), S::Error> {
+        if is_zero(page) {
+            self.stats.zeroes += 1;
+            self.byte(PAGE_ZERO)?;
+            return self.int(page.len() as u64);
+        }
+        let digest = R::page_digest(page);
+        if let Some(&number) = self.pages.get(&digest) {
+            self.stats.duplicates += 1;
+            self.byte(PAGE_SEEN)?;
+            return self.int(number);
+        }
+        self.pages.insert(digest, self.pages.len() as u64);
+        self.stats.pages += 1;
+        self.byte(PAGE_NEW)?;
+        self.int(page.len() as u64)?;
+        self.stream(page.len(), |offset, buf| page.read(offset, buf))
+    }
+
+    fn table(&mut self, table: &Table<R>) -> Result<(), S::Error> {
+        self.int(table.len() as u64)?;
+        let entries = table.iter().filter(|x| !x.is_null()).count();
+        self.int(entries as u64)?;
+        for (i, entry) in table.iter().enumerate() {
+            if entry.is_null() {
+                continue;
+            }
+            self.int(i as u64)?;
+            self.entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, entry: &Entry<R>) -> Result<(), S::Error> {
+        match entry {
+            Entry::Null(size) => {
+                self.byte(ENTRY_NULL)?;
+                self.int(*size as u64)
+            }
+            Entry::ROPage(page) => {
+                self.byte(ENTRY_RO_PAGE)?;
+                self.page(page)
+            }
+            Entry::RWPage(page) => {
+                self.byte(ENTRY_RW_PAGE)?;
+                self.page(page)
+            }
+            Entry::ROTable(table) => {
+                self.byte(ENTRY_RO_TABLE)?;
+                self.table(table)
+            }
+            Entry::RWTable(table) => {
+                self.byte(ENTRY_RW_TABLE)?;
+                self.table(table)
+            }
+        }
+    }
+}
+
+fn is_zero<R: Runtime>(page: &Page<R>) -> bool {
+    let mut chunk = [0; 4096];
+    let mut offset = 0;
+    while offset < page.len() {
+        let n = page.read(offset, &mut chunk);
+        if n == 0 {
+            break;
+        }
+        if chunk[..n].iter().any(|&x| x != 0) {
+            return false;
+        }
+        offset += n;
+    }
+    true
+}
+
+struct Decoder<'a, R: Runtime, S: Source> {
+    source: &'a mut S,
+    /// The pages read so far, by number.
+    pages: Vec<Page<R>>,
+    /// How many tuples and functions enclose the value being read.
+    depth: usize,
+}
+
+impl<R: Runtime, S: Source> Decoder<'_, R, S> {
+    fn bytes(&mut self, buf: &mut [u8]) -> Result<(), Error<S::Error>> {
+        self.source.read_exact(buf).map_err(Error::Io)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error<S::Error>> {
+        let mut buf = [0];
+        self.bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn int(&mut self) -> Result<u64, Error<S::Error>> {
+        let mut x = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            x |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(Error::Invalid)
+    }
+
+    fn len(&mut self) -> Result<usize, Error<S::Error>> {
+        usize::try_from(self.int()?).map_err(|_| Error::Invalid)
+    }
+
+    /// Copies `len` bytes into chunks written by `write(offset, buf)`.
+    fn stream(
+        &mut self,
+        len: usize,
+        mut write: impl FnMut(usize, &[u8]) -> usize,
+    ) -> Result<(), Error<S::Error>> {
+        let mut chunk = [0; 4096];
+        let mut offset = 0;
+        while offset < len {
+            let n = core::cmp::min(chunk.len(), len - offset);
+            self.bytes(&mut chunk[..n])?;
+            if write(offset, &chunk[..n]) != n {
+                return Err(Error::Invalid);
+            }
+            offset += n;
+        }
+        Ok(())
+    }
+
+    /// Reads a size, which must be one of `sizes` (and `expected`, if given).
+    fn size(&mut self, sizes: &[usize], expected: Option<usize>) -> Result<usize, Error<S::Error>> {
+        let size = self.len()?;
+        if !sizes.contains(&size) || expected.is_some_and(|x| x != size) {
+            return Err(Error::Invalid);
+        }
+        Ok(size)
+    }
+
+    fn value(&mut self) -> Result<Value<R>, Error<S::Error>> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::Invalid);
+        }
+        self.depth += 1;
+        let value = self.nested();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested(&mut self) -> Result<Value<R>, Error<S::Error>> {
+        Ok(match self.byte()? {
+            NULL => Value::Null(Null::new()),
+            WORD => Value::Word(Word::new(self.int()?)),
+            BLOB => {
+                let len = self.len()?;
+                let mut bytes = Vec::new();
+                self.stream(len, |_, buf| {
+                    bytes.extend_from_slice(buf);
+                    buf.len()
+                })?;
+                Value::Blob(Blob::new(bytes))
+            }
+            TUPLE => {
+                let len = self.len()?;
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(self.value()?);
+                }
+                Value::Tuple(values.into_iter().collect())
+            }
+            PAGE => Value::Page(self.page(None)?),
+            TABLE => Value::Table(self.table(None)?),
+            FUNCTION => {
+                let value = self.value()?;
+                Value::Function(Function::new(value).map_err(|_| Error::Runtime)?)
+            }
+            _ => return Err(Error::Invalid),
+        })
+    }
+
+    /// Reads a page, which must be `expected` bytes long if that is given.
+    fn page(&mut self, expected: Option<usize>) -> Result<Page<R>, Error<S::Error>> {
+        match self.byte()? {
+            PAGE_NEW => {
+                let len = self.size(&PAGE_SIZES, expected)?;
+                let mut page = Page::<R>::new(len);
+                self.stream(len, |offset, buf| page.write(offset, buf))?;
+                self.pages.push(page.clone());
+                Ok(page)
+            }
+            PAGE_ZERO => Ok(Page::new(self.size(&PAGE_SIZES, expected)?)),
+            PAGE_SEEN => {
+                let number = self.len()?;
+                let page = self.pages.get(number).cloned().ok_or(Error::Invalid)?;
+                if expected.is_some_and(|x| x != page.len()) {
+                    return Err(Error::Invalid);
+                }
+                Ok(page)
+            }
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    /// Reads a table, which must be `expected` bytes long if that is given.
+    fn table(&mut self, expected: Option<usize>) -> Result<Table<R>, Error<S::Error>> {
+        let len = self.size(&TABLE_SIZES, expected)?;
+        let mut table = Table::new(len);
+        let entries = self.len()?;
+        if entries > TABLE_ENTRIES {
+            return Err(Error::Invalid);
+        }
+        for _ in 0..entries {
+            let index = self.len()?;
+            if index >= TABLE_ENTRIES {
+                return Err(Error::Invalid);
+            }
+            let entry = self.entry(len / TABLE_ENTRIES)?;
+            table.set(index, entry).map_err(|_| Error::Runtime)?;
+        }
+        Ok(table)
+    }
+
+    /// Reads an entry of a table whose entries are each `size` bytes.
+    fn entry(&mut self, size: usize) -> Result<Entry<R>, Error<S::Error>> {
+        Ok(match self.byte()? {
+            ENTRY_NULL => {
+                if self.len()? != size {
+                    return Err(Error::Invalid);
+                }
+                Entry::Null(size)
+            }
+            ENTRY_RO_PAGE => Entry::ROPage(self.page(Some(size))?),
+            ENTRY_RW_PAGE => Entry::RWPage(self.page(Some(size))?),
+            ENTRY_RO_TABLE => Entry::ROTable(self.table(Some(size))?),
+            ENTRY_RW_TABLE => Entry::RWTable(self.table(Some(size))?),
+            _ => return Err(Error::Invalid),
+        })
+    }
+}
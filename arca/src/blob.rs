@@ -1,6 +1,8 @@
 #[cfg(feature = "alloc")]
 use alloc::string::String;
 
+use core::ops::Range;
+
 use super::prelude::*;
 
 impl<R: Runtime> Blob<R> {
@@ -18,6 +20,24 @@ impl<R: Runtime> Blob<R> {
     pub fn with_ref<T>(&self, f: impl FnOnce(&[u8]) -> T) -> T {
         R::with_blob_as_ref(self, f)
     }
+
+    /// The bytes in `range`, which a runtime may share rather than copy.
+    ///
+    /// # Panics
+    /// If `range` is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        R::slice_blob(self, range).unwrap()
+    }
+
+    /// This blob's bytes followed by `other`'s.
+    pub fn concat(&self, other: &Self) -> Self {
+        R::concat_blobs(self, other)
+    }
+
+    /// Truncates this blob, or extends it with zeroes, to `len` bytes.
+    pub fn resize(&mut self, len: usize) {
+        R::resize_blob(self, len)
+    }
 }
 
 impl<R: Runtime> From<&[u8]> for Blob<R> {
@@ -0,0 +1,173 @@
+//! Canonical digests of values.
+//!
+//! A value's digest is a BLAKE3 hash over its structure rather than its representation, so equal
+//! values have equal digests on every runtime.  Each value is hashed as a type tag followed by its
+//! contents; composite values hash their children's digests rather than their bytes, which keeps
+//! the encoding unambiguous and lets a runtime reuse the digest of a page it has seen before (see
+//! [`Runtime::page_digest`]).
+//!
+//! | value    | hashed as                                                        |
+//! |----------|------------------------------------------------------------------|
+//! | null     | `0`                                                              |
+//! | word     | `1`, the word                                                    |
+//! | blob     | `2`, its length, its bytes                                       |
+//! | tuple    | `3`, its length, each element's digest                           |
+//! | page     | `4`, its size, the digest of its bytes                           |
+//! | table    | `5`, its size, each entry's mode tag and digest (or null's size) |
+//! | function | `6`, the digest of the tuple it reads as                         |
+//!
+//! Integers are little-endian `u64`s.
+
+use core::fmt;
+
+use super::prelude::*;
+
+/// A 256-bit BLAKE3 digest.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Digest(pub [u8; 32]);
+
+impl Digest {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The digest of raw bytes (e.g. a page's contents).
+    pub fn of_bytes(bytes: &[u8]) -> Digest {
+        Digest(*blake3::hash(bytes).as_bytes())
+    }
+
+    /// The digest of `len` bytes produced in chunks by `read(offset, buf)`.
+    pub fn of_reader(len: usize, read: impl FnMut(usize, &mut [u8]) -> usize) -> Digest {
+        let mut hasher = blake3::Hasher::new();
+        absorb(&mut hasher, len, read);
+        Digest(*hasher.finalize().as_bytes())
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({self})")
+    }
+}
+
+const NULL: u8 = 0;
+const WORD: u8 = 1;
+const BLOB: u8 = 2;
+const TUPLE: u8 = 3;
+const PAGE: u8 = 4;
+const TABLE: u8 = 5;
+const FUNCTION: u8 = 6;
+
+// table entry modes
+const ENTRY_NULL: u8 = 0;
+const ENTRY_RO_PAGE: u8 = 1;
+const ENTRY_RW_PAGE: u8 = 2;
+const ENTRY_RO_TABLE: u8 = 3;
+const ENTRY_RW_TABLE: u8 = 4;
+
+fn absorb(
+    hasher: &mut blake3::Hasher,
+    len: usize,
+    mut read: impl FnMut(usize, &mut [u8]) -> usize,
+) {
+    let mut buf = [0; 4096];
+    let mut offset = 0;
+    while offset < len {
+        let chunk = core::cmp::min(buf.len(), len - offset);
+        let n = read(offset, &mut buf[..chunk]);
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        offset += n;
+    }
+}
+
+fn header(tag: u8, len: usize) -> blake3::Hasher {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[tag]);
+    hasher.update(&(len as u64).to_le_bytes());
+    hasher
+}
+
+fn finish(hasher: blake3::Hasher) -> Digest {
+    Digest(*hasher.finalize().as_bytes())
+}
+
+impl<R: Runtime> ValueRef<'_, R> {
+    pub fn digest(self) -> Digest {
+        match self {
+            ValueRef::Null(_) => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[NULL]);
+                finish(hasher)
+            }
+            ValueRef::Word(word) => finish(header(WORD, word.read() as usize)),
+            ValueRef::Blob(blob) => {
+                let mut hasher = header(BLOB, blob.len());
+                absorb(&mut hasher, blob.len(), |offset, buf| {
+                    blob.read(offset, buf)
+                });
+                finish(hasher)
+            }
+            ValueRef::Tuple(tuple) => {
+                let mut hasher = header(TUPLE, tuple.len());
+                for x in tuple.iter() {
+                    hasher.update(x.digest().as_bytes());
+                }
+                finish(hasher)
+            }
+            ValueRef::Page(page) => {
+                let mut hasher = header(PAGE, page.len());
+                hasher.update(R::page_digest(page).as_bytes());
+                finish(hasher)
+            }
+            ValueRef::Table(table) => {
+                let mut hasher = header(TABLE, table.len());
+                for entry in table.iter() {
+                    let (mode, digest) = match &entry {
+                        Entry::Null(size) => (ENTRY_NULL, finish(header(NULL, *size))),
+                        Entry::ROPage(page) => (ENTRY_RO_PAGE, ValueRef::Page(page).digest()),
+                        Entry::RWPage(page) => (ENTRY_RW_PAGE, ValueRef::Page(page).digest()),
+                        Entry::ROTable(table) => (ENTRY_RO_TABLE, ValueRef::Table(table).digest()),
+                        Entry::RWTable(table) => (ENTRY_RW_TABLE, ValueRef::Table(table).digest()),
+                    };
+                    hasher.update(&[mode]);
+                    hasher.update(digest.as_bytes());
+                }
+                finish(hasher)
+            }
+            ValueRef::Function(function) => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[FUNCTION]);
+                hasher.update(function.read_cloned().digest().as_bytes());
+                finish(hasher)
+            }
+        }
+    }
+}
+
+impl<R: Runtime> Value<R> {
+    /// The canonical digest of this value; see the [module documentation](crate::digest).
+    pub fn digest(&self) -> Digest {
+        match self {
+            Value::Null(x) => ValueRef::Null(x),
+            Value::Word(x) => ValueRef::Word(x),
+            Value::Blob(x) => ValueRef::Blob(x),
+            Value::Tuple(x) => ValueRef::Tuple(x),
+            Value::Page(x) => ValueRef::Page(x),
+            Value::Table(x) => ValueRef::Table(x),
+            Value::Function(x) => ValueRef::Function(x),
+        }
+        .digest()
+    }
+}
@@ -10,8 +10,11 @@ extern crate alloc;
 #[cfg(feature = "serde")]
 mod serde;
 
+#[cfg(feature = "alloc")]
+pub mod archive;
 pub mod blob;
 pub mod datatype;
+pub mod digest;
 pub mod entry;
 pub mod function;
 pub mod null;
@@ -24,9 +27,9 @@ pub mod word;
 
 pub mod prelude {
     pub use super::{
-        Blob, Function, Null, Page, Table, Tuple, Word, datatype::DataType, entry::Entry,
-        function::Continuation, runtime::Runtime, value::RawValue, value::RawValueRef,
-        value::Value, value::ValueRef,
+        Blob, Function, Null, Page, Table, Tuple, Word, datatype::DataType, digest::Digest,
+        entry::Entry, function::Continuation, runtime::Runtime, value::RawValue,
+        value::RawValueRef, value::Value, value::ValueRef,
     };
     #[cfg(feature = "serde")]
     pub(crate) use alloc::format;
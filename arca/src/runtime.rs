@@ -1,3 +1,5 @@
+use core::ops::Range;
+
 use super::prelude::*;
 
 pub trait Runtime: Sized + core::fmt::Debug + Eq + PartialEq + Clone {
@@ -36,6 +38,33 @@ pub trait Runtime: Sized + core::fmt::Debug + Eq + PartialEq + Clone {
         value: Value<Self>,
     ) -> Result<Value<Self>, Self::Error>;
 
+    /// A blob of the bytes of `blob` in `range`.
+    fn slice_blob(blob: &Blob<Self>, range: Range<usize>) -> Result<Blob<Self>, Self::Error>;
+    /// A blob of the bytes of `first` followed by those of `second`.
+    fn concat_blobs(first: &Blob<Self>, second: &Blob<Self>) -> Blob<Self>;
+    /// Truncates `blob`, or extends it with zeroes, to `len` bytes.
+    fn resize_blob(blob: &mut Blob<Self>, len: usize);
+
+    /// A tuple of the elements of `tuple` in `range`.
+    fn slice_tuple(tuple: &Tuple<Self>, range: Range<usize>) -> Result<Tuple<Self>, Self::Error>;
+    /// A tuple of the elements of `first` followed by those of `second`.
+    fn concat_tuples(first: &Tuple<Self>, second: &Tuple<Self>) -> Tuple<Self>;
+    /// Truncates `tuple`, or extends it with nulls, to `len` elements.
+    fn resize_tuple(tuple: &mut Tuple<Self>, len: usize);
+    /// Fills `values` with the elements of `tuple` from `start` on.
+    fn get_tuple_many(
+        tuple: &Tuple<Self>,
+        start: usize,
+        values: &mut [Value<Self>],
+    ) -> Result<(), Self::Error>;
+    /// Swaps `values` with the elements of `tuple` from `start` on, so `values` ends up holding
+    /// the elements they replaced.
+    fn set_tuple_many(
+        tuple: &mut Tuple<Self>,
+        start: usize,
+        values: &mut [Value<Self>],
+    ) -> Result<(), Self::Error>;
+
     fn get_table(table: &Table<Self>, index: usize) -> Result<Entry<Self>, Self::Error>;
     fn set_table(
         table: &mut Table<Self>,
@@ -48,6 +77,12 @@ pub trait Runtime: Sized + core::fmt::Debug + Eq + PartialEq + Clone {
     fn is_function_arcane(function: &Function<Self>) -> bool;
     fn call_with_current_continuation(function: Function<Self>) -> Value<Self>;
 
+    /// The digest of a page's bytes.  A runtime can override this to cache the digests of pages
+    /// it knows have not changed.
+    fn page_digest(page: &Page<Self>) -> Digest {
+        Digest::of_reader(page.len(), |offset, buf| Self::read_page(page, offset, buf))
+    }
+
     #[cfg(feature = "alloc")]
     fn with_blob_as_ref<T>(blob: &Blob<Self>, f: impl FnOnce(&[u8]) -> T) -> T {
         let mut buf = vec![0; blob.len()];
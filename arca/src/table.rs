@@ -83,6 +83,63 @@ impl<R: Runtime> Table<R> {
         }
     }
 
+    /// Copies the bytes mapped at `address` into `buf`, stopping at the first unmapped byte.
+    /// Returns the number of bytes read.
+    pub fn read_bytes(&self, address: usize, buf: &mut [u8]) -> Result<usize, R::Error> {
+        let mut total = 0;
+        while total < buf.len() {
+            let n = self.read_mapped(address + total, &mut buf[total..])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+
+    /// Copies `buf` into the pages mapped at `address`, regardless of whether they are mapped
+    /// read-only, stopping at the first unmapped byte.  Returns the number of bytes written.
+    pub fn write_bytes(&mut self, address: usize, buf: &[u8]) -> Result<usize, R::Error> {
+        let mut total = 0;
+        while total < buf.len() {
+            let n = self.write_mapped(address + total, &buf[total..])?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+
+    fn read_mapped(&self, address: usize, buf: &mut [u8]) -> Result<usize, R::Error> {
+        if address >= self.len() {
+            return Ok(0);
+        }
+        let size = self.len() / 512;
+        let (index, offset) = (address / size, address % size);
+        Ok(match self.get(index)? {
+            Entry::ROPage(page) | Entry::RWPage(page) => page.read(offset, buf),
+            Entry::ROTable(table) | Entry::RWTable(table) => table.read_mapped(offset, buf)?,
+            Entry::Null(_) => 0,
+        })
+    }
+
+    fn write_mapped(&mut self, address: usize, buf: &[u8]) -> Result<usize, R::Error> {
+        if address >= self.len() {
+            return Ok(0);
+        }
+        let size = self.len() / 512;
+        let (index, offset) = (address / size, address % size);
+        let mut entry = self.take(index)?;
+        let written = match &mut entry {
+            Entry::ROPage(page) | Entry::RWPage(page) => Ok(page.write(offset, buf)),
+            Entry::ROTable(table) | Entry::RWTable(table) => table.write_mapped(offset, buf),
+            Entry::Null(_) => Ok(0),
+        };
+        self.set(index, entry)?;
+        written
+    }
+
     pub fn iter(&self) -> TableIter<'_, R> {
         TableIter {
             table: self,
@@ -1,3 +1,5 @@
+use core::ops::Range;
+
 use super::prelude::*;
 
 impl<R: Runtime> Tuple<R> {
@@ -36,6 +38,42 @@ impl<R: Runtime> Tuple<R> {
     pub fn with_ref<T>(&self, f: impl FnOnce(&[Value<R>]) -> T) -> T {
         R::with_tuple_as_ref(self, f)
     }
+
+    /// The elements in `range`.
+    ///
+    /// # Panics
+    /// If `range` is out of bounds.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        R::slice_tuple(self, range).unwrap()
+    }
+
+    /// This tuple's elements followed by `other`'s.
+    pub fn concat(&self, other: &Self) -> Self {
+        R::concat_tuples(self, other)
+    }
+
+    /// Truncates this tuple, or extends it with nulls, to `len` elements.
+    pub fn resize(&mut self, len: usize) {
+        R::resize_tuple(self, len)
+    }
+
+    /// Fills `values` with the elements from `start` on, in one operation rather than one per
+    /// element.
+    ///
+    /// # Panics
+    /// If the elements are out of bounds.
+    pub fn get_many(&self, start: usize, values: &mut [Value<R>]) {
+        R::get_tuple_many(self, start, values).unwrap()
+    }
+
+    /// Swaps `values` with the elements from `start` on, in one operation rather than one per
+    /// element; `values` ends up holding the elements they replaced.
+    ///
+    /// # Panics
+    /// If the elements are out of bounds.
+    pub fn set_many(&mut self, start: usize, values: &mut [Value<R>]) {
+        R::set_tuple_many(self, start, values).unwrap()
+    }
 }
 
 impl<R: Runtime, A: Into<Value<R>>, B: Into<Value<R>>> From<(A, B)> for Tuple<R> {
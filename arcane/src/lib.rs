@@ -14,4 +14,199 @@ pub enum SyscallError {
     BadArgument = __ERR_bad_argument,
     OutOfMemory = __ERR_out_of_memory,
     Interrupted = __ERR_interrupted,
+    PermissionDenied = __ERR_permission_denied,
+}
+
+/// The descriptor does not grant a right (see [`rights`]) the system call needs.  Like the system
+/// calls below, it is numbered from 0x100 so it can never collide with the header's errors.
+pub const __ERR_permission_denied: u32 = 0x100;
+
+/// Bits of the flags word (index 5) in an Arcane function's data.
+pub mod flags {
+    /// Page faults are performed as `page_fault` effects, which can be resumed once the fault has
+    /// been fixed, rather than raised as exceptions.
+    pub const PAGE_FAULT_EFFECT: u64 = 1 << 0;
+    /// System calls use Linux x86-64 numbers and conventions, and are translated into arca
+    /// operations and effects, so unmodified static musl binaries can run as Arcane functions.  A
+    /// function with this flag keeps the state of its heap and mappings at index 9 of its data.
+    pub const LINUX: u64 = 1 << 1;
+}
+
+/// Rights a descriptor can grant over its value.  A new descriptor has them all; `restrict` takes
+/// some away, and a descriptor derived from another (e.g. an element of a tuple, or a clone) has
+/// no more rights than it.  A container grants no more rights over an element than the
+/// descriptor the element was put in with did, though its own rights are unchanged; a value
+/// taken elsewhere (e.g. put into another container, or mapped) has no more rights than any of
+/// its elements.
+///
+/// An Arcane function's data keeps the rights of its descriptors at index 7, and of the
+/// descriptors `get_argument` will make for its arguments at index 8, each as a tuple of words.
+/// An entry `mmap` displaces from the address space goes back with no more rights than it was
+/// mapped with, even if only part of it is displaced; index 10 keeps the rights of the ranges
+/// mapped with fewer than all, as a tuple of `(address, length, rights)` triples of words.
+pub mod rights {
+    /// Read the value's contents: `read`, and (with `CLONE`) `get`, `get_many`, `slice` and
+    /// `concat`.
+    pub const READ: u64 = 1 << 0;
+    /// Change the value's contents: `write`, `set`, `set_many` and `resize`, and map a page or
+    /// table read-write.
+    pub const WRITE: u64 = 1 << 1;
+    /// Map the value (a page or table) into an address space or table, or map into it (a table,
+    /// with `map` or `set`).  A page or table mapped read-write needs `WRITE` as well.
+    pub const MAP: u64 = 1 << 2;
+    /// Apply the value (a function) to an argument.
+    pub const APPLY: u64 = 1 << 3;
+    /// Make other descriptors of the value or its contents: `clone`, and (with `READ`) `get`,
+    /// `get_many`, `slice` and `concat`, whose results are copies of all or part of it.
+    pub const CLONE: u64 = 1 << 4;
+
+    pub const ALL: u64 = READ | WRITE | MAP | APPLY | CLONE;
+}
+
+// System calls the kernel implements beyond those declared in `arca/arca.h`.  They are numbered
+// from 0x100 so they can never collide with the header's.
+
+/// `create_blob_from_page(ptr, len, flags)`: makes a blob of the `len` bytes at `ptr`, which must
+/// lie within a single mapped page.  The bytes are copied, as with `create_blob`, unless `flags`
+/// has [`SHARE_PAGE`].
+pub const __NR_create_blob_from_page: u32 = 0x100;
+/// Shares the page with the blob made by `create_blob_from_page` instead of copying from it, and
+/// leaves the page mapped read-only (so the program must `mprotect` it before writing to it
+/// again).  Slices of less than half a page are copied anyway, rather than keep the whole page
+/// alive.
+pub const SHARE_PAGE: u64 = 1 << 0;
+/// `slice(descriptor, start, end)`: makes a blob or tuple of the bytes or elements of
+/// `descriptor` in `start..end`.
+pub const __NR_slice: u32 = 0x101;
+/// `concat(first, second)`: makes a blob or tuple of the contents of `first` followed by those of
+/// `second`, which must have the same type.
+pub const __NR_concat: u32 = 0x102;
+/// `resize(descriptor, len)`: truncates a blob or tuple, or extends it with zeroes or nulls.
+pub const __NR_resize: u32 = 0x103;
+/// `get_many(tuple, start, ptr, len)`: stores descriptors of the `len` elements of `tuple` from
+/// `start` on in the array of `i64`s at `ptr`.
+pub const __NR_get_many: u32 = 0x104;
+/// `set_many(tuple, start, ptr, len)`: replaces the `len` elements of `tuple` from `start` on with
+/// the values of the descriptors in the array of `i64`s at `ptr`, which are consumed, and stores
+/// descriptors of the replaced elements in their place.
+pub const __NR_set_many: u32 = 0x105;
+/// `restrict(descriptor, rights)`: takes away the [`rights`] of `descriptor` which are not in
+/// `rights`, e.g. before passing its value to another function.  Rights can never be added back.
+pub const __NR_restrict: u32 = 0x107;
+
+/// `batch(ptr, len)`: performs the system calls described by the array of `len` [`BatchEntry`]s
+/// at `ptr` in order, storing each one's result in its entry.  It stops at the first which fails,
+/// and returns how many succeeded.  System calls which end or capture the program (`exit`,
+/// `get_argument`, `call_with_current_continuation`, `get_continuation` and `batch`) fail with
+/// `bad_syscall`.  A batch of more than [`MAX_BATCH`] entries fails with `bad_argument`.
+pub const __NR_batch: u32 = 0x106;
+
+/// The most entries a [`__NR_batch`] may have.
+pub const MAX_BATCH: usize = 64;
+
+/// One system call in a [`__NR_batch`].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BatchEntry {
+    pub num: u64,
+    /// If bit `i` is set, `args[i]` is the index of an earlier entry whose result is passed in
+    /// its place (e.g. a descriptor it created).
+    pub refs: u64,
+    pub args: [u64; 6],
+    /// Set by the kernel, as the system call's return value.
+    pub result: i64,
+}
+
+impl BatchEntry {
+    pub const fn new(num: u32, args: [u64; 6]) -> Self {
+        BatchEntry {
+            num: num as u64,
+            refs: 0,
+            args,
+            result: 0,
+        }
+    }
+
+    /// Passes the result of entry `entry` as argument `arg`.
+    pub const fn with_ref(mut self, arg: usize, entry: usize) -> Self {
+        self.refs |= 1 << arg;
+        self.args[arg] = entry as u64;
+        self
+    }
+}
+
+/// # Safety
+/// The arguments must be valid for system call `num`.
+#[inline]
+pub unsafe fn syscall(num: u32, args: [u64; 4]) -> i64 {
+    let result: i64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            inlateout("rax") num as i64 => result,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+/// # Safety
+/// `ptr..ptr + len` must lie within a single mapped page; see [`__NR_create_blob_from_page`].
+pub unsafe fn arca_blob_create_from_page(ptr: *const u8, len: usize, flags: u64) -> i64 {
+    unsafe {
+        syscall(
+            __NR_create_blob_from_page,
+            [ptr as u64, len as u64, flags, 0],
+        )
+    }
+}
+
+/// # Safety
+/// See [`__NR_slice`].
+pub unsafe fn arca_slice(descriptor: i64, start: usize, end: usize) -> i64 {
+    unsafe { syscall(__NR_slice, [descriptor as u64, start as u64, end as u64, 0]) }
+}
+
+/// # Safety
+/// See [`__NR_concat`].
+pub unsafe fn arca_concat(first: i64, second: i64) -> i64 {
+    unsafe { syscall(__NR_concat, [first as u64, second as u64, 0, 0]) }
+}
+
+/// # Safety
+/// See [`__NR_resize`].
+pub unsafe fn arca_resize(descriptor: i64, len: usize) -> i64 {
+    unsafe { syscall(__NR_resize, [descriptor as u64, len as u64, 0, 0]) }
+}
+
+/// # Safety
+/// `ptr` must be valid for writing `len` `i64`s.
+pub unsafe fn arca_tuple_get_many(tuple: i64, start: usize, ptr: *mut i64, len: usize) -> i64 {
+    let args = [tuple as u64, start as u64, ptr as u64, len as u64];
+    unsafe { syscall(__NR_get_many, args) }
+}
+
+/// # Safety
+/// `ptr` must be valid for reading and writing `len` `i64`s.
+pub unsafe fn arca_tuple_set_many(tuple: i64, start: usize, ptr: *mut i64, len: usize) -> i64 {
+    let args = [tuple as u64, start as u64, ptr as u64, len as u64];
+    unsafe { syscall(__NR_set_many, args) }
+}
+
+/// # Safety
+/// `ptr` must be valid for reading and writing `len` entries, which must be valid system calls.
+pub unsafe fn arca_batch(ptr: *mut BatchEntry, len: usize) -> i64 {
+    unsafe { syscall(__NR_batch, [ptr as u64, len as u64, 0, 0]) }
+}
+
+/// # Safety
+/// See [`__NR_restrict`].
+pub unsafe fn arca_restrict(descriptor: i64, rights: u64) -> i64 {
+    unsafe { syscall(__NR_restrict, [descriptor as u64, rights, 0, 0]) }
 }
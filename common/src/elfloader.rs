@@ -1,19 +1,177 @@
+//! Loading ELF executables into Arcane functions.
+//!
+//! Read-only segments are mapped as read-only pages, so every clone of a loaded function shares
+//! them.  Pages of writable segments which hold no file data (`.bss`) are not allocated at all:
+//! they are left as null entries and listed as zero-fill regions in the function's data, and the
+//! kernel backs each with a fresh zeroed page when it is first touched.  A [`Loader`] additionally
+//! caches each executable by its contents, so loading the same ELF again only clones the function.
+//!
+//! The stack region is set up the way the System V ABI expects at process entry, with `argc`,
+//! `argv`, `envp` and the auxiliary vector on it and the initial TLS block above them; see
+//! [`load_elf_with`].  [`load_linux_elf`] loads an ordinary static Linux executable instead, whose
+//! system calls the kernel translates (see `arcane::flags::LINUX`).
+
 use arca::prelude::*;
 use arca::Entry;
 use elf::{endian::AnyEndian, segment::ProgramHeader, ElfBytes};
 
 extern crate alloc;
-use alloc::vec::Vec;
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::ops::Range;
 
 #[derive(derive_more::From, Debug)]
 pub enum Error {
     Parse(elf::ParseError),
     Runtime,
     InvalidElf,
+    /// An argument or environment entry was not a blob, or the stack was misplaced or too small.
+    InvalidArgument,
+}
+
+/// The index of the FS base (thread pointer) in an Arcane function's registers, just past the
+/// register file.
+pub const FSBASE: usize = 18;
+/// The number of registers an Arcane function's data keeps at index 0.
+pub const REGISTERS: usize = FSBASE + 1;
+
+/// Loads ELF executables, caching the pristine function for each.
+#[derive(Debug)]
+pub struct Loader<R: arca::Runtime> {
+    /// Loaded functions, keyed by the digest of their ELF.
+    cache: BTreeMap<Digest, Function<R>>,
+}
+
+impl<R: arca::Runtime> Default for Loader<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: arca::Runtime> Loader<R> {
+    pub fn new() -> Self {
+        Loader {
+            cache: BTreeMap::new(),
+        }
+    }
+
+    /// Loads `elf`, or clones the function it was loaded as before.
+    pub fn load(&mut self, elf: &[u8]) -> Result<Function<R>, Error> {
+        let key = Digest::of_bytes(elf);
+        if let Some(f) = self.cache.get(&key) {
+            return Ok(f.clone());
+        }
+        let f = load_elf(elf)?;
+        self.cache.insert(key, f.clone());
+        Ok(f)
+    }
+
+    /// The number of executables cached.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Adds `region` to a sorted list of disjoint regions, merging it with any it touches.
+fn add_region(regions: &mut Vec<Range<usize>>, region: Range<usize>) {
+    let i = regions.partition_point(|x| x.end < region.start);
+    let mut merged = region;
+    while i < regions.len() && regions[i].start <= merged.end {
+        let x = regions.remove(i);
+        merged = x.start.min(merged.start)..x.end.max(merged.end);
+    }
+    regions.insert(i, merged);
+}
+
+/// `arcane::flags::LINUX`, which this crate cannot depend on.
+const LINUX: u64 = 1 << 1;
+
+const PAGE_SIZE: usize = 4096;
+
+// auxiliary vector entry types (see `<elf.h>`)
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+/// How [`load_elf_with`] sets up a process.
+#[derive(Debug)]
+pub struct Options<R: arca::Runtime> {
+    /// The arguments, a tuple of blobs; `argv[0]` is conventionally the program's name.
+    pub argv: Tuple<R>,
+    /// The environment, a tuple of `NAME=value` blobs.
+    pub envp: Tuple<R>,
+    /// The (page-aligned) address just past the stack.
+    pub stack_top: usize,
+    /// The size of the stack region, of which only the pages holding the initial stack are
+    /// allocated; the rest are zero-filled when touched.
+    pub stack_size: usize,
+    /// Whether to load the executable with the Linux personality.
+    pub linux: bool,
+}
+
+impl<R: arca::Runtime> Default for Options<R> {
+    fn default() -> Self {
+        Options {
+            argv: R::create_tuple(0),
+            envp: R::create_tuple(0),
+            stack_top: 0x7f_ffff_f000,
+            stack_size: 8 << 20,
+            linux: false,
+        }
+    }
 }
 
 pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
+    load_elf_with(elf, Options::default())
+}
+
+/// Loads a static Linux executable (e.g. one built with `musl-gcc -static`), with the Linux
+/// personality and its heap starting after the last page of its image.
+///
+/// A C runtime's entry point needs the initial stack [`load_elf_with`] lays out, which this gives
+/// no arguments or environment; use [`load_elf_with`] with [`Options::linux`] to pass some.
+pub fn load_linux_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
+    load_elf_with(
+        elf,
+        Options {
+            linux: true,
+            ..Options::default()
+        },
+    )
+}
+
+/// Loads `elf` with a stack, arguments and initial TLS block set up as `options` says.
+///
+/// The stack is laid out as the System V ABI's process entry expects: `rsp` points at `argc`,
+/// followed by the `argv` and `envp` pointer arrays (each ending in null) and the auxiliary
+/// vector, with the strings and the `AT_RANDOM` bytes above them.  If the executable has a
+/// `PT_TLS` segment, the top of the stack region holds its initial TLS block followed by a TCB
+/// whose first word points to itself, and the FS base is set to the TCB.
+pub fn load_elf_with<R: arca::Runtime>(
+    elf: &[u8],
+    options: Options<R>,
+) -> Result<Function<R>, Error> {
     log::debug!("loading: {} byte ELF file", elf.len());
+    // loading is deterministic, so the "random" bytes are derived from the executable
+    let seed = hash(elf);
     let elf = ElfBytes::<AnyEndian>::minimal_parse(elf)?;
     let start_address = elf.ehdr.e_entry;
     let segments: Vec<ProgramHeader> = elf.segments().ok_or(Error::InvalidElf)?.iter().collect();
@@ -21,6 +179,10 @@ pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
     assert_eq!(elf.ehdr.e_type, elf::abi::ET_EXEC);
 
     let mut table = R::create_table(0);
+    let mut zero_fill = Vec::new();
+    let mut image_end = 0;
+    let mut phdr = None;
+    let mut tls = None;
 
     for (i, segment) in segments.iter().enumerate() {
         match segment.p_type {
@@ -33,17 +195,22 @@ pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
                 let offset = start - page_start_memory;
                 let filesz = segment.p_filesz as usize;
                 let memsz = segment.p_memsz as usize;
+                image_end = image_end.max(start + memsz);
 
                 let mut pages = (offset + memsz) / 4096;
                 if !(offset + memsz).is_multiple_of(4096) {
                     pages += 1;
                 }
 
+                let writable = segment.p_flags & elf::abi::PF_W != 0;
                 let mut memi = offset;
                 let mut filei = 0;
                 let data = elf.segment_data(segment)?;
                 for page in 0..pages {
                     let page_start = page * 4096;
+                    let page_end = page_start + 4096;
+                    let address = page_start_memory + page_start;
+                    let has_data = filei < filesz;
                     let unique_page =
                         table
                             .unmap(page_start_memory + page_start)
@@ -51,13 +218,18 @@ pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
                                 Entry::ROPage(page) | Entry::RWPage(page) => Some(page),
                                 _ => None,
                             });
+                    if writable && !has_data && unique_page.is_none() {
+                        // .bss: left unmapped until it is touched
+                        add_region(&mut zero_fill, address..address + 4096);
+                        memi = page_end;
+                        continue;
+                    }
                     let mut unique_page = if let Some(up) = unique_page {
                         up
                     } else {
                         R::create_page(1 << 12)
                     };
                     assert!(memi >= page_start);
-                    let page_end = page_start + 4096;
                     if memi >= page_start && memi < page_end {
                         if filei < filesz {
                             let mem_left = page_end - memi;
@@ -77,20 +249,26 @@ pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
                         }
                     }
 
-                    if segment.p_flags & elf::abi::PF_W != 0 {
+                    if writable {
                         table
-                            .map(page_start_memory + page_start, Entry::RWPage(unique_page))
+                            .map(address, Entry::RWPage(unique_page))
                             .map_err(|_| Error::Runtime)?;
                     } else {
                         table
-                            .map(page_start_memory + page_start, Entry::ROPage(unique_page))
+                            .map(address, Entry::ROPage(unique_page))
                             .map_err(|_| Error::Runtime)?;
                     }
                 }
             }
-            elf::abi::PT_TLS => {}
+            elf::abi::PT_TLS => {
+                tls = Some(Tls {
+                    image: elf.segment_data(segment)?,
+                    size: segment.p_memsz as usize,
+                    align: segment.p_align as usize,
+                });
+            }
             elf::abi::PT_PHDR => {
-                // program header
+                phdr = Some(segment.p_vaddr);
             }
             0x60000000..0x70000000 => {
                 // os-specific
@@ -102,20 +280,283 @@ pub fn load_elf<R: arca::Runtime>(elf: &[u8]) -> Result<Function<R>, Error> {
         }
     }
 
-    let mut registers = R::create_tuple(20);
+    // without PT_PHDR, find the program headers in the segment which loads them
+    let phdr = phdr.or_else(|| {
+        let offset = elf.ehdr.e_phoff;
+        segments
+            .iter()
+            .find(|x| {
+                x.p_type == elf::abi::PT_LOAD
+                    && (x.p_offset..x.p_offset + x.p_filesz).contains(&offset)
+            })
+            .map(|x| x.p_vaddr + (offset - x.p_offset))
+    });
+    let mut auxv = Vec::new();
+    if let Some(phdr) = phdr {
+        auxv.push((AT_PHDR, phdr));
+    }
+    auxv.extend([
+        (AT_PHENT, elf.ehdr.e_phentsize as u64),
+        (AT_PHNUM, elf.ehdr.e_phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE as u64),
+        (AT_ENTRY, start_address),
+    ]);
+
+    let argv = strings(&options.argv)?;
+    let envp = strings(&options.envp)?;
+    let mut random = [0; 16];
+    random[..8].copy_from_slice(&seed.to_le_bytes());
+    random[8..].copy_from_slice(&hash(&seed.to_le_bytes()).to_le_bytes());
+    let stack = initial_stack(
+        options.stack_top,
+        &argv,
+        &envp,
+        &random,
+        &auxv,
+        tls.as_ref(),
+    );
+
+    let stack_bottom = options
+        .stack_top
+        .checked_sub(options.stack_size)
+        .ok_or(Error::InvalidArgument)?;
+    if !options.stack_top.is_multiple_of(PAGE_SIZE) || stack.start < stack_bottom {
+        return Err(Error::InvalidArgument);
+    }
+    for (i, chunk) in stack.bytes.chunks(PAGE_SIZE).enumerate() {
+        let mut page = R::create_page(PAGE_SIZE);
+        page.write(0, chunk);
+        table
+            .map(stack.start + i * PAGE_SIZE, Entry::RWPage(page))
+            .map_err(|_| Error::Runtime)?;
+    }
+    if stack.start > stack_bottom {
+        add_region(&mut zero_fill, stack_bottom..stack.start);
+    }
+
+    let mut registers = R::create_tuple(REGISTERS);
+    registers.set(4, Word::from(stack.rsp as u64));
     registers.set(16, Word::from(start_address));
+    if let Some(tp) = stack.tp {
+        registers.set(FSBASE, Word::from(tp as u64));
+    }
 
     let descriptors = R::create_tuple(0);
 
     let mut rlimit = R::create_tuple(1);
     rlimit.set(0, Word::from(1 << 21));
 
-    let mut data = R::create_tuple(4);
+    // kept with the function so faults can be symbolized
+    let symbols = match crate::symbols::from_elf(&elf) {
+        Some(symbols) => Value::Blob(R::create_blob(&symbols)),
+        None => Value::default(),
+    };
+
+    let mut regions = R::create_tuple(zero_fill.len());
+    for (i, region) in zero_fill.iter().enumerate() {
+        let mut pair = R::create_tuple(2);
+        pair.set(0, Word::from(region.start as u64));
+        pair.set(1, Word::from(region.len() as u64));
+        regions.set(i, pair);
+    }
+
+    let linux = options.linux;
+    let mut data = R::create_tuple(12);
     data.set(0, Value::Tuple(registers));
     data.set(1, Value::Table(table));
     data.set(2, Value::Tuple(descriptors));
     data.set(3, Value::Tuple(rlimit));
+    data.set(4, symbols);
+    data.set(5, Word::from(if linux { LINUX } else { 0 }));
+    data.set(6, Value::Tuple(regions));
+    if linux {
+        // the heap starts out empty, at the end of the image
+        let heap = image_end.next_multiple_of(PAGE_SIZE) as u64;
+        data.set(9, Tuple::from((Word::from(heap), Word::from(heap))));
+    }
+    // identifies the program to the kernel's profiler, even if it is stripped
+    data.set(11, Word::from(seed));
 
     let args = R::create_tuple(0);
     R::create_function(Tuple::from(("Arcane", data, args)).into()).map_err(|_| Error::Runtime)
 }
+
+/// A `PT_TLS` segment: the initial contents of each thread's TLS block.
+struct Tls<'a> {
+    image: &'a [u8],
+    size: usize,
+    align: usize,
+}
+
+/// The pages at the top of the stack region which hold the initial stack.
+struct InitialStack {
+    /// The page-aligned address of `bytes`, which run to the top of the stack.
+    start: usize,
+    bytes: Vec<u8>,
+    /// The initial stack pointer, which points at `argc`.
+    rsp: usize,
+    /// The thread pointer, if the executable uses TLS.
+    tp: Option<usize>,
+}
+
+/// The size reserved for the TCB above the TLS block; only its first word, the TCB's own address,
+/// is set.
+const TCB_SIZE: usize = 16;
+
+/// Lays out the initial stack below `top`: the TLS block and TCB, the strings of `argv` and
+/// `envp` (which include their NULs), the `AT_RANDOM` bytes, and then `argc`, the pointer arrays
+/// and the auxiliary vector, which `rsp` points at.
+fn initial_stack(
+    top: usize,
+    argv: &[Vec<u8>],
+    envp: &[Vec<u8>],
+    random: &[u8; 16],
+    auxv: &[(u64, u64)],
+    tls: Option<&Tls>,
+) -> InitialStack {
+    let mut writes: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut cursor = top;
+
+    let tp = tls.map(|tls| {
+        let align = tls.align.max(8);
+        let tp = (top - TCB_SIZE) / align * align;
+        cursor = tp - tls.size.next_multiple_of(align);
+        // the rest of the block (.tbss) is already zero
+        writes.push((cursor, tls.image.to_vec()));
+        writes.push((tp, (tp as u64).to_le_bytes().to_vec()));
+        tp
+    });
+
+    cursor -= argv.iter().chain(envp).map(Vec::len).sum::<usize>();
+    let mut pointers = Vec::new();
+    let mut string = cursor;
+    for s in argv.iter().chain(envp) {
+        pointers.push(string as u64);
+        writes.push((string, s.clone()));
+        string += s.len();
+    }
+
+    cursor = (cursor - random.len()) & !0xf;
+    writes.push((cursor, random.to_vec()));
+
+    let mut vector = Vec::new();
+    vector.push(argv.len() as u64);
+    vector.extend_from_slice(&pointers[..argv.len()]);
+    vector.push(0);
+    vector.extend_from_slice(&pointers[argv.len()..]);
+    vector.push(0);
+    let random = (AT_RANDOM, cursor as u64);
+    for &(key, value) in auxv.iter().chain(&[random, (AT_NULL, 0)]) {
+        vector.extend([key, value]);
+    }
+    let rsp = (cursor - vector.len() * 8) & !0xf;
+    writes.push((rsp, vector.iter().flat_map(|x| x.to_le_bytes()).collect()));
+
+    let start = rsp / PAGE_SIZE * PAGE_SIZE;
+    let mut bytes = vec![0; top - start];
+    for (address, data) in writes {
+        let offset = address - start;
+        bytes[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    InitialStack {
+        start,
+        bytes,
+        rsp,
+        tp,
+    }
+}
+
+/// The NUL-terminated contents of a tuple of blobs.
+fn strings<R: arca::Runtime>(tuple: &Tuple<R>) -> Result<Vec<Vec<u8>>, Error> {
+    tuple
+        .iter()
+        .map(|x| {
+            let Value::Blob(blob) = x else {
+                return Err(Error::InvalidArgument);
+            };
+            let mut bytes = vec![0; blob.len() + 1];
+            blob.read(0, &mut bytes);
+            Ok(bytes)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_region() {
+        let mut regions = Vec::new();
+        add_region(&mut regions, 0x3000..0x4000);
+        add_region(&mut regions, 0x1000..0x2000);
+        add_region(&mut regions, 0x2000..0x3000);
+        add_region(&mut regions, 0x8000..0x9000);
+        assert_eq!(regions, [0x1000..0x4000, 0x8000..0x9000]);
+        add_region(&mut regions, 0x0..0x10000);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0], 0x0..0x10000);
+    }
+
+    fn word(stack: &InitialStack, address: usize) -> u64 {
+        let offset = address - stack.start;
+        u64::from_le_bytes(stack.bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn string(stack: &InitialStack, address: u64) -> &[u8] {
+        let offset = address as usize - stack.start;
+        let len = stack.bytes[offset..].iter().position(|&x| x == 0).unwrap();
+        &stack.bytes[offset..offset + len]
+    }
+
+    #[test]
+    fn test_initial_stack() {
+        let top = 0x10000;
+        let argv = [b"prog\0".to_vec(), b"-v\0".to_vec()];
+        let envp = [b"HOME=/\0".to_vec()];
+        let random = [7; 16];
+        let stack = initial_stack(top, &argv, &envp, &random, &[(AT_PAGESZ, 4096)], None);
+        assert_eq!(stack.rsp % 16, 0);
+        assert_eq!(stack.start % PAGE_SIZE, 0);
+        assert_eq!(stack.start + stack.bytes.len(), top);
+        assert_eq!(stack.tp, None);
+
+        let mut at = stack.rsp;
+        let mut next = || {
+            let x = word(&stack, at);
+            at += 8;
+            x
+        };
+        assert_eq!(next(), 2);
+        assert_eq!(string(&stack, next()), b"prog");
+        assert_eq!(string(&stack, next()), b"-v");
+        assert_eq!(next(), 0);
+        assert_eq!(string(&stack, next()), b"HOME=/");
+        assert_eq!(next(), 0);
+        assert_eq!((next(), next()), (AT_PAGESZ, 4096));
+        assert_eq!(next(), AT_RANDOM);
+        let random_at = next() as usize - stack.start;
+        assert_eq!(stack.bytes[random_at..random_at + 16], random);
+        assert_eq!((next(), next()), (AT_NULL, 0));
+    }
+
+    #[test]
+    fn test_initial_stack_tls() {
+        let top = 0x10000;
+        let tls = Tls {
+            image: &[1, 2, 3],
+            size: 40,
+            align: 64,
+        };
+        let stack = initial_stack(top, &[], &[], &[0; 16], &[], Some(&tls));
+        let tp = stack.tp.unwrap();
+        assert_eq!(tp % 64, 0);
+        assert!(tp + TCB_SIZE <= top);
+        assert_eq!(word(&stack, tp), tp as u64);
+        // the block ends at the thread pointer, with .tbss zeroed after the image
+        let block = tp - 64 - stack.start;
+        assert_eq!(stack.bytes[block..block + 4], [1, 2, 3, 0]);
+        assert!(stack.rsp < tp - 64);
+        assert_eq!(word(&stack, stack.rsp), 0);
+    }
+}
@@ -21,8 +21,10 @@ pub mod controlreg;
 pub mod elfloader;
 pub mod ipaddr;
 pub mod pipe;
+pub mod pprof;
 pub mod protocol;
 pub mod sendable;
+pub mod symbols;
 pub mod util;
 
 #[cfg(feature = "std")]
@@ -49,6 +51,36 @@ pub struct SymtabRecord {
     pub file_len: usize,
 }
 
+/// Number of distinct syscall numbers counted in a [`StatsRecord`]; higher numbers are lumped
+/// together in `syscalls_other`.
+pub const STATS_SYSCALLS: usize = 512;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct StatsRecord {
+    pub uptime_ns: u64,
+    pub threads_outstanding: u64,
+    pub arcas_forced: u64,
+    pub memory_used: u64,
+    pub memory_total: u64,
+    pub syscalls_other: u64,
+    pub syscalls: [u64; STATS_SYSCALLS],
+}
+
+impl Default for StatsRecord {
+    fn default() -> Self {
+        Self {
+            uptime_ns: 0,
+            threads_outstanding: 0,
+            arcas_forced: 0,
+            memory_used: 0,
+            memory_total: 0,
+            syscalls_other: 0,
+            syscalls: [0; STATS_SYSCALLS],
+        }
+    }
+}
+
 pub mod hypercall {
     use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 
@@ -57,10 +89,24 @@ pub mod hypercall {
     pub const SYMNAME: u64 = 2;
     pub const MEMSET: u64 = 3;
     pub const MEMCLR: u64 = 4;
+    /// Passes a clock reading (in nanoseconds) through the host's journal, returning the recorded
+    /// reading instead when replaying.
+    pub const CLOCK: u64 = 5;
+    pub const STATS: u64 = 6;
+    /// Returns the host's [`flags`].
+    pub const FLAGS: u64 = 7;
 
     pub const NOTIFY_READ: u64 = 16;
     pub const NOTIFY_WRITE: u64 = 17;
 
+    /// What the host has been configured to do, as returned by [`FLAGS`].
+    pub mod flags {
+        /// The host is recording or replaying the guest's nondeterministic inputs.
+        pub const JOURNAL: u64 = 1 << 0;
+        /// The host wants the guest's counters, published with [`STATS`](super::STATS).
+        pub const MONITOR: u64 = 1 << 1;
+    }
+
     #[derive(Debug, Default)]
     pub struct TcpInfo {
         pub ip: u32,
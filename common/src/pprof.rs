@@ -0,0 +1,225 @@
+//! A minimal encoder for the [pprof] profile format.
+//!
+//! Only what `go tool pprof` needs to show sampled call stacks is produced: one sample type,
+//! samples, locations with a single line each, and functions.  The output is the uncompressed
+//! protobuf, which pprof accepts as-is.
+//!
+//! [pprof]: https://github.com/google/pprof/blob/main/proto/profile.proto
+
+extern crate alloc;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+// field numbers from profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_ADDRESS: u32 = 3;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+const VARINT: u32 = 0;
+const LEN: u32 = 2;
+
+/// A profile under construction.
+#[derive(Debug)]
+pub struct Profile {
+    sample_type: (u64, u64),
+    strings: Vec<String>,
+    string_ids: BTreeMap<String, u64>,
+    /// The name of each function, indexed by its id minus one.
+    functions: Vec<u64>,
+    function_ids: BTreeMap<u64, u64>,
+    /// The address and function of each location, indexed by its id minus one.
+    locations: Vec<(u64, Option<u64>)>,
+    location_ids: BTreeMap<(u64, Option<u64>), u64>,
+    samples: Vec<(Vec<u64>, i64)>,
+}
+
+impl Profile {
+    /// Creates an empty profile whose samples are counts of type `kind`, measured in `unit` (e.g.
+    /// `"samples"` and `"count"`).
+    pub fn new(kind: &str, unit: &str) -> Profile {
+        let mut profile = Profile {
+            sample_type: (0, 0),
+            strings: Vec::new(),
+            string_ids: BTreeMap::new(),
+            functions: Vec::new(),
+            function_ids: BTreeMap::new(),
+            locations: Vec::new(),
+            location_ids: BTreeMap::new(),
+            samples: Vec::new(),
+        };
+        // the string table must start with the empty string
+        profile.string("");
+        profile.sample_type = (profile.string(kind), profile.string(unit));
+        profile
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(s.into());
+        self.string_ids.insert(s.into(), id);
+        id
+    }
+
+    fn function(&mut self, name: &str) -> u64 {
+        let name = self.string(name);
+        *self.function_ids.entry(name).or_insert_with(|| {
+            self.functions.push(name);
+            self.functions.len() as u64
+        })
+    }
+
+    fn location(&mut self, address: u64, function: Option<&str>) -> u64 {
+        let function = function.map(|x| self.function(x));
+        *self
+            .location_ids
+            .entry((address, function))
+            .or_insert_with(|| {
+                self.locations.push((address, function));
+                self.locations.len() as u64
+            })
+    }
+
+    /// Adds `count` samples of a stack, given leaf first as `(address, function name)` pairs.
+    pub fn sample<'a>(
+        &mut self,
+        stack: impl IntoIterator<Item = (u64, Option<&'a str>)>,
+        count: i64,
+    ) {
+        let locations = stack
+            .into_iter()
+            .map(|(address, function)| self.location(address, function))
+            .collect();
+        self.samples.push((locations, count));
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sample_type = Vec::new();
+        uint(&mut sample_type, VALUE_TYPE_TYPE, self.sample_type.0);
+        uint(&mut sample_type, VALUE_TYPE_UNIT, self.sample_type.1);
+        bytes(&mut out, PROFILE_SAMPLE_TYPE, &sample_type);
+        for (locations, count) in self.samples.iter() {
+            let mut sample = Vec::new();
+            packed(&mut sample, SAMPLE_LOCATION_ID, locations.iter().copied());
+            packed(&mut sample, SAMPLE_VALUE, [*count as u64]);
+            bytes(&mut out, PROFILE_SAMPLE, &sample);
+        }
+        for (i, &(address, function)) in self.locations.iter().enumerate() {
+            let mut location = Vec::new();
+            uint(&mut location, LOCATION_ID, i as u64 + 1);
+            uint(&mut location, LOCATION_ADDRESS, address);
+            if let Some(function) = function {
+                let mut line = Vec::new();
+                uint(&mut line, LINE_FUNCTION_ID, function);
+                bytes(&mut location, LOCATION_LINE, &line);
+            }
+            bytes(&mut out, PROFILE_LOCATION, &location);
+        }
+        for (i, &name) in self.functions.iter().enumerate() {
+            let mut function = Vec::new();
+            uint(&mut function, FUNCTION_ID, i as u64 + 1);
+            uint(&mut function, FUNCTION_NAME, name);
+            uint(&mut function, FUNCTION_SYSTEM_NAME, name);
+            bytes(&mut out, PROFILE_FUNCTION, &function);
+        }
+        for string in self.strings.iter() {
+            bytes(&mut out, PROFILE_STRING_TABLE, string.as_bytes());
+        }
+        out
+    }
+}
+
+fn varint(out: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+fn key(out: &mut Vec<u8>, field: u32, wire: u32) {
+    varint(out, ((field << 3) | wire) as u64);
+}
+
+fn uint(out: &mut Vec<u8>, field: u32, x: u64) {
+    if x != 0 {
+        key(out, field, VARINT);
+        varint(out, x);
+    }
+}
+
+fn bytes(out: &mut Vec<u8>, field: u32, x: &[u8]) {
+    key(out, field, LEN);
+    varint(out, x.len() as u64);
+    out.extend_from_slice(x);
+}
+
+fn packed(out: &mut Vec<u8>, field: u32, xs: impl IntoIterator<Item = u64>) {
+    let mut body = Vec::new();
+    for x in xs {
+        varint(&mut body, x);
+    }
+    if !body.is_empty() {
+        bytes(out, field, &body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let mut out = Vec::new();
+        varint(&mut out, 1);
+        varint(&mut out, 300);
+        varint(&mut out, u64::MAX);
+        assert_eq!(out[..3], [0x01, 0xac, 0x02]);
+        assert_eq!(out.len(), 3 + 10);
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::new("samples", "count");
+        profile.sample([(0x10, Some("leaf")), (0x20, Some("main"))], 3);
+        profile.sample([(0x30, Some("leaf")), (0x20, Some("main"))], 1);
+        profile.sample([(0x40, None)], 2);
+        assert_eq!(profile.strings, ["", "samples", "count", "leaf", "main"]);
+        assert_eq!(profile.functions, [3, 4]);
+        assert_eq!(
+            profile.locations,
+            [
+                (0x10, Some(1)),
+                (0x20, Some(2)),
+                (0x30, Some(1)),
+                (0x40, None)
+            ]
+        );
+        assert_eq!(profile.samples[1], (vec![3, 2], 1));
+
+        let encoded = profile.encode();
+        // sample_type { type: 1, unit: 2 }
+        assert_eq!(encoded[..6], [0x0a, 0x04, 0x08, 0x01, 0x10, 0x02]);
+        // sample { location_id: [1, 2], value: [3] }
+        assert_eq!(
+            encoded[6..15],
+            [0x12, 0x07, 0x0a, 0x02, 0x01, 0x02, 0x12, 0x01, 0x03]
+        );
+        assert!(encoded.ends_with(&[0x32, 0x04, b'm', b'a', b'i', b'n']));
+    }
+}
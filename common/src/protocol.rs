@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 pub mod control;
+pub mod debug;
 pub mod file;
 pub mod listener;
 pub mod stream;
@@ -12,8 +12,16 @@ pub enum Request {
     Exit(i32),
     Open(String, FileMode),
     Mkdir(String),
-    Listen { ip: [u8; 4], port: u16 },
-    Connect { host: String, port: u16 },
+    Listen {
+        ip: [u8; 4],
+        port: u16,
+    },
+    Connect {
+        host: String,
+        port: u16,
+    },
+    /// Opens a pipe to the host's debugger stub, if it has one.
+    Debugger,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -73,7 +81,7 @@ impl From<std::io::ErrorKind> for IoErrorKind {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PipeData {
     pub rx_ptr: usize,
     pub rx_len: usize,
@@ -0,0 +1,58 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+/// The number of registers in a [`Event::Registers`] (RAX through R15 in encoding order, then RIP
+/// and RFLAGS).
+pub const REGISTERS: usize = 18;
+
+/// Why a function being debugged stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Stop {
+    /// Before its first instruction.
+    Entry,
+    /// On a software breakpoint; RIP points at the breakpoint's address.
+    Breakpoint,
+    /// After a single step.
+    Step,
+    /// On an exception with this vector; continuing delivers it.
+    Exception(u8),
+    /// The function returned or performed an effect; the only valid reply is
+    /// [`Command::Detach`].
+    Exited,
+}
+
+/// Sent by the kernel while a function is stopped; the host answers each with a [`Command`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Event {
+    Stopped(Stop),
+    Registers(Vec<u64>),
+    /// The bytes read, which may be fewer than requested if the range is not fully mapped.
+    Memory(Vec<u8>),
+    Ack,
+    Err,
+}
+
+/// Sent by the host's debugger stub in reply to an [`Event`].
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Command {
+    ReadRegisters,
+    WriteRegisters(Vec<u64>),
+    ReadMemory {
+        address: usize,
+        len: usize,
+    },
+    WriteMemory {
+        address: usize,
+        bytes: Vec<u8>,
+    },
+    InsertBreakpoint(usize),
+    RemoveBreakpoint(usize),
+    Continue,
+    Step,
+    /// Runs the function to completion without the debugger.
+    Detach,
+    /// Abandons the function, as if it raised an exception.
+    Kill,
+}
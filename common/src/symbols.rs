@@ -0,0 +1,143 @@
+//! A compact symbol table for symbolizing addresses in user programs.
+//!
+//! [`elfloader::load_elf`](crate::elfloader::load_elf) extracts the function symbols from a
+//! program's ELF symbol table into a blob that travels with the function, so the kernel can name
+//! frames without the host or the original ELF.  The blob is a little-endian `u32` count, then one
+//! 24-byte record per symbol (`u64` address, `u64` size, `u32` name offset, `u32` name length)
+//! sorted by address, then the names.
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use elf::{endian::AnyEndian, ElfBytes};
+
+const HEADER: usize = 4;
+const RECORD: usize = 24;
+
+/// Builds a symbol blob from `(address, size, name)` triples, in any order.
+pub fn encode<'a>(symbols: impl IntoIterator<Item = (u64, u64, &'a str)>) -> Vec<u8> {
+    let mut symbols: Vec<_> = symbols.into_iter().collect();
+    symbols.sort_by_key(|&(address, _, _)| address);
+    let mut records = Vec::with_capacity(symbols.len() * RECORD);
+    let mut names = Vec::new();
+    for (address, size, name) in symbols.iter() {
+        records.extend_from_slice(&address.to_le_bytes());
+        records.extend_from_slice(&size.to_le_bytes());
+        records.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        records.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+    }
+    let mut blob = Vec::with_capacity(HEADER + records.len() + names.len());
+    blob.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    blob.extend_from_slice(&records);
+    blob.extend_from_slice(&names);
+    blob
+}
+
+/// Builds a symbol blob from the function symbols in an ELF file, if it has a symbol table.
+pub fn from_elf(elf: &ElfBytes<AnyEndian>) -> Option<Vec<u8>> {
+    let (symtab, strtab) = elf.symbol_table().ok()??;
+    let symbols = symtab
+        .iter()
+        .filter(|sym| sym.st_symtype() == elf::abi::STT_FUNC && sym.st_value != 0)
+        .filter_map(|sym| {
+            let name = strtab.get(sym.st_name as usize).ok()?;
+            Some((sym.st_value, sym.st_size, name))
+        });
+    Some(encode(symbols))
+}
+
+/// A view of a symbol blob.
+#[derive(Copy, Clone, Debug)]
+pub struct Symbols<'a> {
+    records: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> Symbols<'a> {
+    pub fn new(blob: &'a [u8]) -> Option<Self> {
+        let count = u32::from_le_bytes(blob.get(..HEADER)?.try_into().ok()?) as usize;
+        let end = HEADER.checked_add(count.checked_mul(RECORD)?)?;
+        Some(Symbols {
+            records: blob.get(HEADER..end)?,
+            names: &blob[end..],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() / RECORD
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn address(&self, i: usize) -> u64 {
+        self.field(i, 0)
+    }
+
+    fn field(&self, i: usize, offset: usize) -> u64 {
+        let start = i * RECORD + offset;
+        u64::from_le_bytes(self.records[start..start + 8].try_into().unwrap())
+    }
+
+    fn name(&self, i: usize) -> Option<&'a str> {
+        let start = i * RECORD + 16;
+        let offset = u32::from_le_bytes(self.records[start..start + 4].try_into().unwrap());
+        let len = u32::from_le_bytes(self.records[start + 4..start + 8].try_into().unwrap());
+        let bytes = self
+            .names
+            .get(offset as usize..offset as usize + len as usize)?;
+        core::str::from_utf8(bytes).ok()
+    }
+
+    /// Finds the symbol containing `address`, returning its name and the offset into it.
+    ///
+    /// Symbols without a size are taken to extend to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<(&'a str, u64)> {
+        // the last symbol starting at or before `address`
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.address(mid) <= address {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let i = lo.checked_sub(1)?;
+        let start = self.address(i);
+        let size = self.field(i, 8);
+        let offset = address - start;
+        if size != 0 && offset >= size {
+            return None;
+        }
+        Some((self.name(i)?, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let blob = encode([(0x2000, 0x10, "b"), (0x1000, 0x100, "a"), (0x3000, 0, "c")]);
+        let symbols = Symbols::new(&blob).unwrap();
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.lookup(0xfff), None);
+        assert_eq!(symbols.lookup(0x1000), Some(("a", 0)));
+        assert_eq!(symbols.lookup(0x1042), Some(("a", 0x42)));
+        assert_eq!(symbols.lookup(0x1100), None);
+        assert_eq!(symbols.lookup(0x200f), Some(("b", 0xf)));
+        assert_eq!(symbols.lookup(0x3abc), Some(("c", 0xabc)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let blob = encode([(0x1000, 0x100, "a")]);
+        assert!(Symbols::new(&blob[..HEADER + RECORD - 1]).is_none());
+        assert!(Symbols::new(&[]).is_none());
+        assert!(Symbols::new(&encode([])).unwrap().is_empty());
+    }
+}
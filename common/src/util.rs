@@ -6,6 +6,7 @@ pub mod initcell;
 // pub mod mutex;
 pub mod concurrent_trie;
 pub mod oneshot;
+pub mod rangemap;
 pub mod router;
 pub mod rwlock;
 pub mod semaphore;
@@ -2,38 +2,59 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 
-#[derive(Clone)]
+/// A table of items indexed by small integers, like a file descriptor table.
+///
+/// Freed slots are kept on a free list, so [`insert`](Self::insert) takes constant time rather
+/// than scanning for a hole.  The table never shrinks, so an emptied slot stays readable (as
+/// empty) rather than going out of bounds.  The list may hold slots which have since been filled
+/// by [`set`](Self::set); `insert` skips over those.
+#[derive(Clone, Debug)]
 pub struct Descriptors<T> {
     table: Vec<Option<T>>,
+    free: Vec<usize>,
 }
 
 impl<T> Default for Descriptors<T> {
     fn default() -> Self {
         Descriptors {
-            table: Vec::with_capacity(512),
+            table: Vec::new(),
+            free: Vec::new(),
         }
     }
 }
 
+impl<T: PartialEq> PartialEq for Descriptors<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.table == other.table
+    }
+}
+
+impl<T: Eq> Eq for Descriptors<T> {}
+
 impl<T> Descriptors<T> {
     pub fn new() -> Self {
         Self::default()
     }
 
     pub fn insert(&mut self, item: T) -> usize {
-        if let Some(i) =
-            self.table
-                .iter()
-                .enumerate()
-                .find_map(|(i, x)| if x.is_none() { Some(i) } else { None })
-        {
-            self.table[i] = Some(item);
-            i
-        } else {
-            let i = self.table.len();
-            self.table.push(Some(item));
-            i
+        while let Some(i) = self.free.pop() {
+            if let Some(slot @ None) = self.table.get_mut(i) {
+                *slot = Some(item);
+                return i;
+            }
         }
+        let i = self.table.len();
+        self.table.push(Some(item));
+        i
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
     }
 
     pub fn get(&self, i: usize) -> Option<&T> {
@@ -46,27 +67,30 @@ impl<T> Descriptors<T> {
 
     pub fn set(&mut self, i: usize, item: T) -> Option<T> {
         while i >= self.table.len() {
+            self.free.push(self.table.len());
             self.table.push(None);
         }
         self.table[i].replace(item)
     }
 
+    /// Empties slot `i`, which stays in the table (so it can still be read, as empty) until
+    /// [`insert`](Self::insert) reuses it.
     pub fn remove(&mut self, i: usize) -> Option<T> {
         let result = self.table.get_mut(i).and_then(|x| x.take());
-        self.shrinkwrap();
+        if result.is_some() {
+            self.free.push(i);
+            self.compact();
+        }
         result
     }
 
-    fn shrinkwrap(&mut self) {
-        if let Some((last, _)) = self
-            .table
-            .iter()
-            .enumerate()
-            .rev()
-            .take_while(|(_, x)| x.is_none())
-            .last()
-        {
-            self.table.truncate(last + 1);
+    /// Drops stale entries from the free list once they outnumber the slots.
+    fn compact(&mut self) {
+        if self.free.len() > 2 * self.table.len() {
+            let table = &self.table;
+            self.free.retain(|&i| matches!(table.get(i), Some(None)));
+            self.free.sort_unstable();
+            self.free.dedup();
         }
     }
 
@@ -97,3 +121,63 @@ impl<'a, T> Iterator for Iter<'a, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies freed slots are reused before the table grows.
+    #[test]
+    fn test_reuse() {
+        let mut d = Descriptors::new();
+        assert_eq!(d.insert('a'), 0);
+        assert_eq!(d.insert('b'), 1);
+        assert_eq!(d.insert('c'), 2);
+        assert_eq!(d.remove(0), Some('a'));
+        assert_eq!(d.insert('d'), 0);
+        assert_eq!(d.insert('e'), 3);
+        assert_eq!(d.get(0), Some(&'d'));
+        assert_eq!(d.remove(0), Some('d'));
+        assert_eq!(d.remove(0), None);
+        assert_eq!(d.insert('f'), 0);
+        assert_eq!(d.insert('g'), 4);
+    }
+
+    /// Verifies stale free-list entries, left by set(), are skipped.
+    #[test]
+    fn test_stale_free_slots() {
+        let mut d = Descriptors::new();
+        d.set(3, 'a');
+        assert_eq!(d.len(), 4);
+        d.set(1, 'b');
+        let inserted = [d.insert('c'), d.insert('d'), d.insert('e')];
+        assert!(inserted.contains(&0) && inserted.contains(&2) && inserted.contains(&4));
+
+        let mut d = Descriptors::new();
+        for c in ['a', 'b', 'c'] {
+            d.insert(c);
+        }
+        d.remove(1);
+        d.remove(2);
+        d.set(2, 'd');
+        assert_eq!(d.insert('e'), 1);
+        assert_eq!(d.insert('f'), 3);
+        assert_eq!(d.iter().count(), 4);
+    }
+
+    /// Verifies removing the last slot keeps it in the table, empty.
+    #[test]
+    fn test_remove_keeps_slot() {
+        let mut d = Descriptors::new();
+        for c in ['a', 'b', 'c'] {
+            d.insert(c);
+        }
+        assert_eq!(d.remove(2), Some('c'));
+        assert_eq!(d.remove(1), Some('b'));
+        assert_eq!(d.len(), 3);
+        assert_eq!(d.get(2), None);
+        assert_eq!(d.insert('d'), 1);
+        assert_eq!(d.insert('e'), 2);
+        assert_eq!(d.insert('f'), 3);
+    }
+}
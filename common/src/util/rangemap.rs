@@ -0,0 +1,177 @@
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ops::Range;
+
+/// A map from disjoint ranges (e.g. of addresses) to values.
+///
+/// Inserting or removing a range overrides whatever it overlaps: ranges straddling its ends are
+/// trimmed, and one containing it is split in two.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RangeMap<V> {
+    /// The end and value of each range, keyed by its start.
+    ranges: BTreeMap<usize, (usize, V)>,
+}
+
+impl<V> Default for RangeMap<V> {
+    fn default() -> Self {
+        RangeMap {
+            ranges: BTreeMap::new(),
+        }
+    }
+}
+
+impl<V: Clone> RangeMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The ranges which overlap `range`, in order, with their values.
+    pub fn overlapping(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, &V)> {
+        let before = self
+            .ranges
+            .range(..range.start)
+            .next_back()
+            .filter(|(_, (end, _))| *end > range.start);
+        let within = self.ranges.range(range.start..range.end.max(range.start));
+        before
+            .into_iter()
+            .chain(within)
+            .filter(move |_| !range.is_empty())
+            .map(|(&start, (end, value))| (start..*end, value))
+    }
+
+    /// Maps `range` to `value`, replacing whatever it overlapped.
+    pub fn insert(&mut self, range: Range<usize>, value: V) {
+        if range.is_empty() {
+            return;
+        }
+        self.remove(range.clone());
+        self.ranges.insert(range.start, (range.end, value));
+    }
+
+    /// Unmaps `range`.
+    pub fn remove(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        if let Some((&start, (end, value))) = self.ranges.range(..range.start).next_back() {
+            let (end, value) = (*end, value.clone());
+            if end > range.start {
+                self.ranges.insert(start, (range.start, value.clone()));
+                if end > range.end {
+                    self.ranges.insert(range.end, (end, value));
+                }
+            }
+        }
+        let within: Vec<usize> = self
+            .ranges
+            .range(range.start..range.end)
+            .map(|(&start, _)| start)
+            .collect();
+        for start in within {
+            let (end, value) = self.ranges.remove(&start).unwrap();
+            if end > range.end {
+                self.ranges.insert(range.end, (end, value));
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Range<usize>, &V)> {
+        self.ranges
+            .iter()
+            .map(|(&start, (end, value))| (start..*end, value))
+    }
+
+    /// The number of ranges mapped.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
+impl<V: Clone> FromIterator<(Range<usize>, V)> for RangeMap<V> {
+    fn from_iter<T: IntoIterator<Item = (Range<usize>, V)>>(iter: T) -> Self {
+        let mut map = RangeMap::new();
+        for (range, value) in iter {
+            map.insert(range, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(map: &RangeMap<char>) -> Vec<(Range<usize>, char)> {
+        map.iter().map(|(range, &value)| (range, value)).collect()
+    }
+
+    /// Verifies lookups find the ranges containing or straddling the query, not just those
+    /// starting inside it.
+    #[test]
+    fn test_overlapping() {
+        let map: RangeMap<char> = [(0x1000..0x5000, 'a'), (0x6000..0x7000, 'b')]
+            .into_iter()
+            .collect();
+        let found = |range| map.overlapping(range).map(|(_, &x)| x).collect::<Vec<_>>();
+        assert_eq!(found(0x2000..0x3000), ['a']);
+        assert_eq!(found(0x4000..0x6001), ['a', 'b']);
+        assert_eq!(found(0x5000..0x6000), []);
+        assert_eq!(found(0x2000..0x2000), []);
+    }
+
+    /// Verifies removing part of a range trims or splits it.
+    #[test]
+    fn test_remove_splits() {
+        let mut map = RangeMap::new();
+        map.insert(0x1000..0x5000, 'a');
+        map.insert(0x6000..0x8000, 'b');
+        map.remove(0x2000..0x3000);
+        assert_eq!(
+            ranges(&map),
+            [
+                (0x1000..0x2000, 'a'),
+                (0x3000..0x5000, 'a'),
+                (0x6000..0x8000, 'b')
+            ]
+        );
+        map.remove(0x4000..0x7000);
+        assert_eq!(
+            ranges(&map),
+            [
+                (0x1000..0x2000, 'a'),
+                (0x3000..0x4000, 'a'),
+                (0x7000..0x8000, 'b')
+            ]
+        );
+        map.remove(0..0x10000);
+        assert!(map.is_empty());
+    }
+
+    /// Verifies inserting a range replaces the parts of others it overlaps.
+    #[test]
+    fn test_insert_overrides() {
+        let mut map = RangeMap::new();
+        map.insert(0x1000..0x4000, 'a');
+        map.insert(0x2000..0x3000, 'b');
+        map.insert(0x3800..0x5000, 'c');
+        assert_eq!(
+            ranges(&map),
+            [
+                (0x1000..0x2000, 'a'),
+                (0x2000..0x3000, 'b'),
+                (0x3000..0x3800, 'a'),
+                (0x3800..0x5000, 'c')
+            ]
+        );
+    }
+}
@@ -3,6 +3,7 @@ extern crate alloc;
 use super::*;
 
 pub mod arca;
+pub mod cache;
 
 /// A Fix runtime. A Runtime is effectively a data store that can additionally execute procedures.
 pub trait Runtime {
@@ -1,14 +1,17 @@
 use crate::handle::*;
 use crate::runtime::Runtime;
+use crate::runtime::cache::{ProcedureCache, Stats};
 use crate::storage::Storage;
 use crate::storage::memory::MemoryStorage;
 use common::bitpack::BitPack;
+use kernel::kthread::KMutex;
 use kernel::prelude::{Blob as ArcaBlob, Function, Tuple, Value, Vec};
 use kernel::println;
 
 #[derive(Debug, Default)]
 pub struct FixOnArca {
     storage: MemoryStorage,
+    procedures: KMutex<ProcedureCache<Function>>,
 }
 
 impl Runtime for FixOnArca {
@@ -20,11 +23,7 @@ impl Runtime for FixOnArca {
         println!("applying   {}", Handle::from(combination));
         let contents = self.storage().get_tree(combination).unwrap();
         let procedure = contents.get(0).expect("empty combination");
-        let elf = self
-            .storage()
-            .get_blob(procedure.unwrap_object().unwrap_blob())
-            .unwrap();
-        let f: Function = common::elfloader::load_elf(&elf).unwrap();
+        let f = self.procedure(procedure.unwrap_object().unwrap_blob());
         let blob = pack_handle(combination);
         let f = f.apply(blob);
         let result = self.run(f);
@@ -33,6 +32,23 @@ impl Runtime for FixOnArca {
 }
 
 impl FixOnArca {
+    /// The loaded function for a procedure, from the cache if it has been loaded before.
+    ///
+    /// The cache stays locked while a missing procedure loads, so concurrent misses on the same
+    /// procedure load its ELF only once.
+    fn procedure(&self, procedure: Blob) -> Function {
+        self.procedures.lock().get_or_insert_with(procedure, || {
+            let elf = self.storage().get_blob(procedure).unwrap();
+            let f: Function = common::elfloader::load_elf(&elf).unwrap();
+            (f, elf.len())
+        })
+    }
+
+    /// Hit, miss and eviction counts for the procedure cache.
+    pub fn procedure_stats(&self) -> Stats {
+        self.procedures.lock().stats()
+    }
+
     fn run(&self, mut f: Function) -> Handle {
         loop {
             let result = f.force();
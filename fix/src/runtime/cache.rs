@@ -0,0 +1,221 @@
+//! A cache of loaded procedures.
+//!
+//! Entries are keyed by the handle of the procedure's ELF blob and hold the pristine loaded value,
+//! which is cloned for each application.  The cache is bounded by the total size of the ELFs it
+//! holds; when it is full, the least recently used entries are evicted.
+//!
+//! An ELF's size stands in for the memory its loaded function keeps alive, which cannot be measured
+//! without walking the function's page tables.  The two are close: the loader allocates pages only
+//! for the segments' file contents (plus the initial stack), leaving `.bss` to be filled on first
+//! touch, and the clones handed out share the read-only pages rather than copying them.
+
+extern crate alloc;
+
+use super::*;
+use alloc::collections::BTreeMap;
+use common::bitpack::BitPack;
+
+/// The default bound on the total size of cached ELFs.
+pub const DEFAULT_CAPACITY: usize = 256 << 20;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: T,
+    size: usize,
+    /// The cache's clock when this entry was last used.
+    used: u64,
+}
+
+#[derive(Debug)]
+pub struct ProcedureCache<T> {
+    capacity: usize,
+    size: usize,
+    clock: u64,
+    entries: BTreeMap<[u8; 32], Entry<T>>,
+    /// The key of each entry, by when it was last used, so the least recently used is first.
+    order: BTreeMap<u64, [u8; 32]>,
+    stats: Stats,
+}
+
+impl<T: Clone> Default for ProcedureCache<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<T: Clone> ProcedureCache<T> {
+    /// Creates a cache holding procedures whose ELFs total at most `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        ProcedureCache {
+            capacity,
+            size: 0,
+            clock: 0,
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    fn key(procedure: Blob) -> [u8; 32] {
+        Handle::from(procedure).pack()
+    }
+
+    /// A copy of the cached procedure, if there is one.
+    pub fn get(&mut self, procedure: Blob) -> Option<T> {
+        self.clock += 1;
+        let key = Self::key(procedure);
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                self.order.remove(&entry.used);
+                entry.used = self.clock;
+                self.order.insert(entry.used, key);
+                self.stats.hits += 1;
+                Some(entry.value.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// A copy of the cached procedure, or of the one `load` returns (with the size of its ELF),
+    /// which is cached first.
+    pub fn get_or_insert_with(&mut self, procedure: Blob, load: impl FnOnce() -> (T, usize)) -> T {
+        if let Some(value) = self.get(procedure) {
+            return value;
+        }
+        let (value, size) = load();
+        self.insert(procedure, value.clone(), size);
+        value
+    }
+
+    /// Caches `value`, loaded from an ELF of `size` bytes, evicting the least recently used
+    /// procedures to make room.  Procedures larger than the whole cache are not kept.
+    pub fn insert(&mut self, procedure: Blob, value: T, size: usize) {
+        if size > self.capacity {
+            return;
+        }
+        let key = Self::key(procedure);
+        if let Some(old) = self.entries.remove(&key) {
+            self.order.remove(&old.used);
+            self.size -= old.size;
+        }
+        while self.size + size > self.capacity {
+            let Some((_, lru)) = self.order.pop_first() else {
+                break;
+            };
+            let evicted = self.entries.remove(&lru).unwrap();
+            self.size -= evicted.size;
+            self.stats.evictions += 1;
+        }
+        self.clock += 1;
+        self.size += size;
+        self.order.insert(self.clock, key);
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                used: self.clock,
+            },
+        );
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The number of procedures cached.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The total size of the cached ELFs.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn procedure(name: &str) -> Blob {
+        Blob::Literal(LiteralName::new(name.as_bytes()))
+    }
+
+    /// Verifies lookups count hits and misses, and a miss loads and caches the procedure once.
+    #[test]
+    fn test_hits_and_misses() {
+        let mut cache = ProcedureCache::new(100);
+        assert_eq!(cache.get(procedure("a")), None);
+        let mut loads = 0;
+        for _ in 0..3 {
+            let value = cache.get_or_insert_with(procedure("a"), || {
+                loads += 1;
+                (1, 10)
+            });
+            assert_eq!(value, 1);
+        }
+        assert_eq!(loads, 1);
+        assert_eq!(
+            cache.stats(),
+            Stats {
+                hits: 2,
+                misses: 2,
+                evictions: 0
+            }
+        );
+    }
+
+    /// Verifies the least recently used procedures are evicted to keep within the size bound.
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = ProcedureCache::new(30);
+        cache.insert(procedure("a"), 1, 10);
+        cache.insert(procedure("b"), 2, 10);
+        cache.insert(procedure("c"), 3, 10);
+        assert_eq!(cache.get(procedure("a")), Some(1));
+        cache.insert(procedure("d"), 4, 15);
+        assert_eq!(cache.get(procedure("b")), None);
+        assert_eq!(cache.get(procedure("c")), None);
+        assert_eq!(cache.get(procedure("a")), Some(1));
+        assert_eq!(cache.get(procedure("d")), Some(4));
+        assert_eq!(cache.stats().evictions, 2);
+        assert_eq!((cache.len(), cache.size()), (2, 25));
+    }
+
+    /// Verifies the size bound counts a replaced procedure once and rejects ones too large to fit.
+    #[test]
+    fn test_size_bound() {
+        let mut cache = ProcedureCache::new(30);
+        cache.insert(procedure("a"), 1, 10);
+        cache.insert(procedure("a"), 2, 20);
+        assert_eq!((cache.len(), cache.size()), (1, 20));
+        assert_eq!(cache.get(procedure("a")), Some(2));
+        cache.insert(procedure("b"), 3, 31);
+        assert_eq!(cache.get(procedure("b")), None);
+        assert_eq!((cache.len(), cache.size()), (1, 20));
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+}
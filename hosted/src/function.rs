@@ -0,0 +1,94 @@
+//! Functions: symbolic values or Arcane definitions, with the arguments applied to them so far.
+
+use crate::{Runtime, Value};
+
+type Tuple = arca::Tuple<Runtime>;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Definition {
+    Symbolic(Box<Value>),
+    /// The Arcane data tuple `(registers, memory, descriptors, rlimit, ...)`, kept as given.
+    Arcane(Box<Value>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Function {
+    defn: Definition,
+    args: Vec<Value>,
+}
+
+impl Function {
+    /// Parses `("Symbolic" | "Arcane", data, [args])`, checking an Arcane definition has the
+    /// registers, memory, descriptors and rlimit the kernel requires.
+    pub fn new(value: Value) -> Option<Function> {
+        let Value::Tuple(x) = value else {
+            return None;
+        };
+        let mut x = x.into_inner();
+        if x.len() < 2 {
+            return None;
+        }
+        let args = match x.len() {
+            2 => Vec::new(),
+            _ => match core::mem::take(&mut x[2]) {
+                Value::Tuple(args) => args.into_inner(),
+                _ => return None,
+            },
+        };
+        let data = core::mem::take(&mut x[1]);
+        let Value::Blob(kind) = &x[0] else {
+            return None;
+        };
+        let defn = match kind.inner().as_slice() {
+            b"Symbolic" => Definition::Symbolic(Box::new(data)),
+            b"Arcane" => {
+                let Value::Tuple(fields) = &data else {
+                    return None;
+                };
+                let valid = fields.len() >= 4
+                    && matches!(fields.get(0), Value::Tuple(_))
+                    && matches!(fields.get(1), Value::Table(_))
+                    && matches!(fields.get(2), Value::Tuple(_))
+                    && matches!(fields.get(3), Value::Tuple(_));
+                if !valid {
+                    return None;
+                }
+                Definition::Arcane(Box::new(data))
+            }
+            _ => return None,
+        };
+        Some(Function { defn, args })
+    }
+
+    pub fn symbolic(value: Value, args: Vec<Value>) -> Function {
+        Function {
+            defn: Definition::Symbolic(Box::new(value)),
+            args,
+        }
+    }
+
+    pub fn definition(&self) -> &Definition {
+        &self.defn
+    }
+
+    pub fn args(&self) -> &[Value] {
+        &self.args
+    }
+
+    pub fn is_arcane(&self) -> bool {
+        matches!(self.defn, Definition::Arcane(_))
+    }
+
+    pub fn apply(&mut self, arg: Value) {
+        self.args.push(arg);
+    }
+
+    pub fn read(self) -> Value {
+        let (kind, data) = match self.defn {
+            Definition::Symbolic(value) => ("Symbolic", *value),
+            Definition::Arcane(data) => ("Arcane", *data),
+        };
+        let args = Tuple::from_inner(self.args);
+        Value::Tuple(Tuple::from((kind, data, args)))
+    }
+}
@@ -0,0 +1,542 @@
+//! A reference implementation of [`arca::Runtime`] over ordinary heap-allocated Rust data.
+//!
+//! This runtime needs neither KVM nor the Arcane system call ABI, so code written against `arca`
+//! values can be unit-tested with a plain `cargo test` on any host.  Values follow the same rules
+//! as the in-kernel runtime: pages are 4 KiB, 2 MiB or 1 GiB; a table holds 512 entries which are
+//! each a page or a table one level down; and forcing a symbolic function returns it unchanged.
+//!
+//! Arcane functions can be created, applied and read, but this runtime cannot run machine code.
+//! Forcing one calls the evaluator installed on the current thread with [`set_evaluator`] (e.g. an
+//! interpreter, or a stub which answers for the program under test); with no evaluator installed,
+//! forcing it returns the effect `("", "unsupported", function)`.
+
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+pub mod function;
+pub mod page;
+pub mod table;
+
+pub use function::Function;
+pub use page::Page;
+pub use table::Table;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Runtime;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    InvalidTableEntry(arca::Entry<Runtime>),
+    InvalidIndex(usize),
+    InvalidValue,
+}
+
+pub type Value = arca::Value<Runtime>;
+pub type Entry = arca::Entry<Runtime>;
+
+type Evaluator = Rc<dyn Fn(arca::Function<Runtime>) -> Value>;
+
+thread_local! {
+    static EVALUATOR: RefCell<Option<Evaluator>> = const { RefCell::new(None) };
+}
+
+/// Installs `f` to force the Arcane functions forced on this thread.
+pub fn set_evaluator(f: impl Fn(arca::Function<Runtime>) -> Value + 'static) {
+    EVALUATOR.with_borrow_mut(|x| *x = Some(Rc::new(f)));
+}
+
+/// Removes this thread's evaluator, so forcing an Arcane function is unsupported again.
+pub fn clear_evaluator() {
+    EVALUATOR.with_borrow_mut(|x| *x = None);
+}
+
+/// The effect returned when an Arcane function is forced with no evaluator installed.
+pub fn unsupported(function: arca::Function<Runtime>) -> Value {
+    let args = vec![Value::Blob("unsupported".into()), Value::Function(function)];
+    Value::Function(arca::Function::from_inner(Function::symbolic(
+        Value::Blob("".into()),
+        args,
+    )))
+}
+
+impl arca::Runtime for Runtime {
+    type Null = ();
+    type Word = u64;
+    type Blob = Vec<u8>;
+    type Tuple = Vec<Value>;
+    type Page = Page;
+    type Table = Table;
+    type Function = Function;
+
+    type Error = Error;
+
+    fn create_null() -> arca::Null<Self> {
+        arca::Null::from_inner(())
+    }
+
+    fn create_word(word: u64) -> arca::Word<Self> {
+        arca::Word::from_inner(word)
+    }
+
+    fn create_blob(bytes: &[u8]) -> arca::Blob<Self> {
+        arca::Blob::from_inner(bytes.to_vec())
+    }
+
+    fn create_tuple(len: usize) -> arca::Tuple<Self> {
+        arca::Tuple::from_inner(vec![Value::default(); len])
+    }
+
+    fn create_page(len: usize) -> arca::Page<Self> {
+        arca::Page::from_inner(Page::new(len))
+    }
+
+    fn create_table(len: usize) -> arca::Table<Self> {
+        arca::Table::from_inner(Table::new(len))
+    }
+
+    fn create_function(data: Value) -> Result<arca::Function<Self>, Self::Error> {
+        Ok(arca::Function::from_inner(
+            Function::new(data).ok_or(Error::InvalidValue)?,
+        ))
+    }
+
+    fn value_len(value: arca::ValueRef<Self>) -> usize {
+        match value.inner() {
+            arca::RawValueRef::Null(_) => 0,
+            arca::RawValueRef::Word(x) => core::mem::size_of_val(x),
+            arca::RawValueRef::Blob(x) => x.len(),
+            arca::RawValueRef::Tuple(x) => x.len(),
+            arca::RawValueRef::Page(x) => x.size(),
+            arca::RawValueRef::Table(x) => x.size(),
+            arca::RawValueRef::Function(_) => 3,
+        }
+    }
+
+    fn read_word(word: &arca::Word<Self>) -> u64 {
+        *word.inner()
+    }
+
+    fn read_blob(blob: &arca::Blob<Self>, offset: usize, buf: &mut [u8]) -> usize {
+        let blob = blob.inner();
+        let len = core::cmp::min(buf.len(), blob.len().saturating_sub(offset));
+        buf[..len].copy_from_slice(&blob[offset..offset + len]);
+        len
+    }
+
+    fn read_page(page: &arca::Page<Self>, offset: usize, buf: &mut [u8]) -> usize {
+        page.inner().read(offset, buf)
+    }
+
+    fn read_function(function: arca::Function<Self>) -> Value {
+        function.into_inner().read()
+    }
+
+    fn write_blob(blob: &mut arca::Blob<Self>, offset: usize, buf: &[u8]) -> usize {
+        let blob = blob.inner_mut();
+        let len = core::cmp::min(buf.len(), blob.len().saturating_sub(offset));
+        blob[offset..offset + len].copy_from_slice(&buf[..len]);
+        len
+    }
+
+    fn write_page(page: &mut arca::Page<Self>, offset: usize, buf: &[u8]) -> usize {
+        page.inner_mut().write(offset, buf)
+    }
+
+    fn get_tuple(tuple: &arca::Tuple<Self>, index: usize) -> Result<Value, Self::Error> {
+        tuple
+            .inner()
+            .get(index)
+            .cloned()
+            .ok_or(Error::InvalidIndex(index))
+    }
+
+    fn set_tuple(
+        tuple: &mut arca::Tuple<Self>,
+        index: usize,
+        value: Value,
+    ) -> Result<Value, Self::Error> {
+        let slot = tuple
+            .inner_mut()
+            .get_mut(index)
+            .ok_or(Error::InvalidIndex(index))?;
+        Ok(core::mem::replace(slot, value))
+    }
+
+    fn slice_blob(
+        blob: &arca::Blob<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Blob<Self>, Self::Error> {
+        let bytes = blob.inner().get(range.clone());
+        let bytes = bytes.ok_or(Error::InvalidIndex(range.end))?;
+        Ok(arca::Blob::from_inner(bytes.to_vec()))
+    }
+
+    fn concat_blobs(first: &arca::Blob<Self>, second: &arca::Blob<Self>) -> arca::Blob<Self> {
+        arca::Blob::from_inner([&first.inner()[..], &second.inner()[..]].concat())
+    }
+
+    fn resize_blob(blob: &mut arca::Blob<Self>, len: usize) {
+        blob.inner_mut().resize(len, 0);
+    }
+
+    fn slice_tuple(
+        tuple: &arca::Tuple<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Tuple<Self>, Self::Error> {
+        let values = tuple.inner().get(range.clone());
+        let values = values.ok_or(Error::InvalidIndex(range.end))?;
+        Ok(arca::Tuple::from_inner(values.to_vec()))
+    }
+
+    fn concat_tuples(first: &arca::Tuple<Self>, second: &arca::Tuple<Self>) -> arca::Tuple<Self> {
+        arca::Tuple::from_inner([&first.inner()[..], &second.inner()[..]].concat())
+    }
+
+    fn resize_tuple(tuple: &mut arca::Tuple<Self>, len: usize) {
+        tuple.inner_mut().resize(len, Value::default());
+    }
+
+    fn get_tuple_many(
+        tuple: &arca::Tuple<Self>,
+        start: usize,
+        values: &mut [Value],
+    ) -> Result<(), Self::Error> {
+        let end = start + values.len();
+        let elements = tuple.inner().get(start..end);
+        values.clone_from_slice(elements.ok_or(Error::InvalidIndex(end))?);
+        Ok(())
+    }
+
+    fn set_tuple_many(
+        tuple: &mut arca::Tuple<Self>,
+        start: usize,
+        values: &mut [Value],
+    ) -> Result<(), Self::Error> {
+        let end = start + values.len();
+        let elements = tuple.inner_mut().get_mut(start..end);
+        values.swap_with_slice(elements.ok_or(Error::InvalidIndex(end))?);
+        Ok(())
+    }
+
+    fn get_table(table: &arca::Table<Self>, index: usize) -> Result<Entry, Self::Error> {
+        table.inner().get(index)
+    }
+
+    fn set_table(
+        table: &mut arca::Table<Self>,
+        index: usize,
+        entry: Entry,
+    ) -> Result<Entry, Self::Error> {
+        table.inner_mut().set(index, entry)
+    }
+
+    fn apply_function(mut function: arca::Function<Self>, argument: Value) -> arca::Function<Self> {
+        function.inner_mut().apply(argument);
+        function
+    }
+
+    fn force_function(function: arca::Function<Self>) -> Value {
+        if !function.inner().is_arcane() {
+            return Value::Function(function);
+        }
+        match EVALUATOR.with_borrow(|x| x.clone()) {
+            Some(evaluator) => evaluator(function),
+            None => unsupported(function),
+        }
+    }
+
+    fn is_function_arcane(function: &arca::Function<Self>) -> bool {
+        function.inner().is_arcane()
+    }
+
+    fn call_with_current_continuation(_: arca::Function<Self>) -> Value {
+        panic!("call/cc is not supported on the hosted runtime!");
+    }
+
+    fn page_digest(page: &arca::Page<Self>) -> arca::Digest {
+        page.inner().digest()
+    }
+
+    fn with_blob_as_ref<T>(blob: &arca::Blob<Self>, f: impl FnOnce(&[u8]) -> T) -> T {
+        f(blob.inner())
+    }
+
+    fn with_tuple_as_ref<T>(tuple: &arca::Tuple<Self>, f: impl FnOnce(&[Value]) -> T) -> T {
+        f(tuple.inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Blob = arca::Blob<Runtime>;
+    type Tuple = arca::Tuple<Runtime>;
+    type Word = arca::Word<Runtime>;
+
+    #[test]
+    fn test_tuple() {
+        let mut tuple = Tuple::from((1u64, "two"));
+        assert_eq!(tuple.len(), 2);
+        assert_eq!(tuple.get(0), Value::Word(Word::new(1)));
+        assert_eq!(tuple.take(1), Value::Blob(Blob::from("two")));
+        assert_eq!(tuple.get(1), Value::default());
+        assert!(<Runtime as arca::Runtime>::get_tuple(&tuple, 2).is_err());
+    }
+
+    #[test]
+    fn test_blob() {
+        let mut blob = Blob::from("hello");
+        let mut buf = [0; 8];
+        assert_eq!(blob.read(1, &mut buf), 4);
+        assert_eq!(&buf[..4], b"ello");
+        assert_eq!(
+            <Runtime as arca::Runtime>::write_blob(&mut blob, 3, b"p!!"),
+            2
+        );
+        assert_eq!(blob.with_ref(|x| x.to_vec()), b"help!");
+    }
+
+    /// Verifies the symbolic-function protocol: applying collects arguments, forcing is the
+    /// identity, and reading gives back `("Symbolic", value, args)`.
+    #[test]
+    fn test_symbolic() {
+        let f = arca::Function::<Runtime>::symbolic("effect");
+        let f = f.apply(1u64).apply("x");
+        assert!(f.is_symbolic());
+        let Value::Function(f) = f.force() else {
+            panic!("forcing a symbolic function did not return it");
+        };
+        let Value::Tuple(read) = f.read() else {
+            panic!("function did not read as a tuple");
+        };
+        assert_eq!(read.get(0), Value::Blob("Symbolic".into()));
+        assert_eq!(read.get(1), Value::Blob("effect".into()));
+        assert_eq!(read.get(2), Value::Tuple(Tuple::from((1u64, "x"))));
+    }
+
+    fn arcane() -> arca::Function<Runtime> {
+        let mut data = Tuple::new(4);
+        data.set(0, Tuple::new(18));
+        data.set(1, arca::Table::<Runtime>::new(1 << 21));
+        data.set(2, Tuple::new(0));
+        data.set(3, Tuple::new(0));
+        arca::Function::arcane(data)
+    }
+
+    #[test]
+    fn test_arcane_evaluator() {
+        let f = arcane().apply(7u64);
+        assert!(f.is_arcane());
+
+        clear_evaluator();
+        let Value::Function(effect) = f.clone().force() else {
+            panic!("unsupported forcing did not return an effect");
+        };
+        let Value::Tuple(read) = effect.read() else {
+            panic!("effect did not read as a tuple");
+        };
+        let Value::Tuple(args) = read.get(2) else {
+            panic!("effect arguments are not a tuple");
+        };
+        assert_eq!(args.get(0), Value::Blob("unsupported".into()));
+
+        set_evaluator(|f| {
+            let Value::Tuple(read) = f.read() else {
+                unreachable!()
+            };
+            let Value::Tuple(args) = read.get(2) else {
+                unreachable!()
+            };
+            args.get(0)
+        });
+        assert_eq!(f.force(), Value::Word(Word::new(7)));
+        clear_evaluator();
+    }
+
+    /// Verifies digests depend on structure, not representation: equal values hash equally, and
+    /// changing a word, a page byte or an entry's mode changes the digest.
+    #[test]
+    fn test_digest() {
+        let a = Value::Tuple(Tuple::from((1u64, "two")));
+        let b = Value::Tuple(Tuple::from((1u64, "two")));
+        assert_eq!(a.digest(), b.digest());
+        assert_ne!(
+            a.digest(),
+            Value::Tuple(Tuple::from((2u64, "two"))).digest()
+        );
+        assert_ne!(
+            Value::Word(Word::new(0)).digest(),
+            Value::Null(arca::Null::new()).digest()
+        );
+        // a blob is not the tuple of its bytes' words
+        assert_ne!(
+            Value::Blob(Blob::from("ab")).digest(),
+            Value::Tuple(Tuple::from((b'a' as u64, b'b' as u64))).digest()
+        );
+
+        let mut page = arca::Page::<Runtime>::new(1 << 12);
+        let mut table = arca::Table::<Runtime>::new(1 << 21);
+        table.set(1, Entry::ROPage(page.clone())).unwrap();
+        let before = Value::Table(table.clone()).digest();
+        assert_eq!(before, Value::Table(table.clone()).digest());
+
+        page.write(0, b"x");
+        table.set(1, Entry::ROPage(page.clone())).unwrap();
+        let written = Value::Table(table.clone()).digest();
+        assert_ne!(before, written);
+
+        table.set(1, Entry::RWPage(page)).unwrap();
+        assert_ne!(written, Value::Table(table).digest());
+
+        let f = arca::Function::<Runtime>::symbolic("f");
+        assert_eq!(
+            Value::Function(f.clone().apply(1u64)).digest(),
+            Value::Function(f.clone().apply(1u64)).digest()
+        );
+        assert_ne!(
+            Value::Function(f.clone()).digest(),
+            Value::Function(f.apply(1u64)).digest()
+        );
+    }
+
+    /// Round-trips a sparse address space through an archive: repeated pages are written once,
+    /// zero pages and null entries keep their sizes, and the result hashes like the original.
+    #[test]
+    fn test_archive() {
+        let mut page = arca::Page::<Runtime>::new(1 << 12);
+        page.write(0, &[0xaa; 1 << 12]);
+        let mut table = arca::Table::<Runtime>::new(1 << 21);
+        for i in 0..16 {
+            table.set(i, Entry::ROPage(page.clone())).unwrap();
+        }
+        table
+            .set(16, Entry::RWPage(arca::Page::new(1 << 12)))
+            .unwrap();
+        let mut space = arca::Table::<Runtime>::new(1 << 30);
+        space.set(0, Entry::RWTable(table)).unwrap();
+        let value = Value::Tuple(Tuple::from((
+            7u64,
+            "seven",
+            Value::Table(space),
+            arca::Function::<Runtime>::symbolic("f").apply(1u64),
+        )));
+
+        let mut bytes = Vec::new();
+        let stats = arca::archive::write(&value, &mut bytes).unwrap();
+        assert_eq!(stats.pages, 1);
+        assert_eq!(stats.duplicates, 15);
+        assert_eq!(stats.zeroes, 1);
+        assert_eq!(stats.bytes, bytes.len() as u64);
+        assert!(bytes.len() < 2 << 12);
+
+        let decoded: Value = arca::archive::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(decoded.digest(), value.digest());
+        let Value::Tuple(tuple) = decoded else {
+            panic!("expected a tuple");
+        };
+        let Value::Table(space) = tuple.get(2) else {
+            panic!("expected a table");
+        };
+        assert_eq!(space.len(), 1 << 30);
+        assert_eq!(space.get(1).unwrap(), Entry::Null(1 << 21));
+        let Entry::RWTable(table) = space.get(0).unwrap() else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.get(16).unwrap().len(), 1 << 12);
+        assert_eq!(table.get(17).unwrap(), Entry::Null(1 << 12));
+
+        assert_eq!(
+            arca::archive::read::<Runtime, _>(&mut &bytes[..bytes.len() - 1]),
+            Err(arca::archive::Error::Io(arca::archive::Eof))
+        );
+        assert_eq!(
+            arca::archive::read::<Runtime, _>(&mut &b"not an archive"[..]),
+            Err(arca::archive::Error::Invalid)
+        );
+    }
+
+    /// Rejects archives whose sizes, indices or nesting could not have come from the encoder,
+    /// without allocating what they claim.
+    #[test]
+    fn test_archive_crafted() {
+        fn archive(parts: &[u64]) -> Vec<u8> {
+            let mut bytes = arca::archive::MAGIC.to_vec();
+            for &(mut x) in parts {
+                while x >= 0x80 {
+                    bytes.push(x as u8 | 0x80);
+                    x >>= 7;
+                }
+                bytes.push(x as u8);
+            }
+            bytes
+        }
+        let read = |bytes: Vec<u8>| arca::archive::read::<Runtime, _>(&mut bytes.as_slice());
+        let invalid = Err(arca::archive::Error::Invalid);
+        let eof = Err(arca::archive::Error::Io(arca::archive::Eof));
+
+        // a zero page of a size no runtime has, and a table far too big
+        assert_eq!(read(archive(&[4, 1, 5])), invalid);
+        assert_eq!(read(archive(&[5, 1 << 60, 0])), invalid);
+        // a table with more entries than it can hold, or an entry past its end
+        assert_eq!(read(archive(&[5, 1 << 21, 513])), invalid);
+        assert_eq!(read(archive(&[5, 1 << 21, 1, 512, 1, 1, 1 << 12])), invalid);
+        // a table nested in a table of the same size
+        assert_eq!(read(archive(&[5, 1 << 48, 1, 0, 3, 1 << 48, 0])), invalid);
+        // a blob and a tuple claiming to be enormous
+        assert_eq!(read(archive(&[2, 1 << 62])), eof);
+        assert_eq!(read(archive(&[3, 1 << 62, 0])), eof);
+        // tuples nested too deeply
+        let mut deep = [3, 1].repeat(1 << 12);
+        deep.push(0);
+        assert_eq!(read(archive(&deep)), invalid);
+        // but not too deeply
+        let mut shallow = [3, 1].repeat(1 << 6);
+        shallow.push(0);
+        assert!(read(archive(&shallow)).is_ok());
+    }
+
+    /// Exercises the bulk blob and tuple operations through the `arca` wrappers.
+    #[test]
+    fn test_slice_concat_resize() {
+        let hello = Blob::from("hello, world");
+        assert_eq!(hello.slice(7..12), Blob::from("world"));
+        assert!(<Runtime as arca::Runtime>::slice_blob(&hello, 7..13).is_err());
+        let mut joined = hello.slice(0..5).concat(&Blob::from("!"));
+        assert_eq!(joined, Blob::from("hello!"));
+        joined.resize(8);
+        assert_eq!(joined, Blob::from(&b"hello!\0\0"[..]));
+        joined.resize(4);
+        assert_eq!(joined, Blob::from("hell"));
+
+        let tuple = Tuple::from((1u64, 2u64, 3u64));
+        let mut tuple = tuple.slice(1..3).concat(&tuple);
+        assert_eq!(tuple.len(), 5);
+        tuple.resize(6);
+        assert_eq!(tuple.get(5), Value::default());
+
+        let mut values = vec![Value::default(); 3];
+        tuple.get_many(1, &mut values);
+        assert_eq!(
+            values,
+            vec![Value::from(3u64), Value::from(1u64), Value::from(2u64)]
+        );
+        let mut values = vec![Value::from(7u64), Value::from(8u64)];
+        tuple.set_many(4, &mut values);
+        assert_eq!(values, vec![Value::from(3u64), Value::default()]);
+        assert_eq!(tuple.get(5), Value::from(8u64));
+
+        let mut values = vec![Value::default(); 3];
+        assert!(<Runtime as arca::Runtime>::get_tuple_many(&tuple, 4, &mut values).is_err());
+        assert!(<Runtime as arca::Runtime>::set_tuple_many(&mut tuple, 4, &mut values).is_err());
+        assert_eq!(values, vec![Value::default(); 3]);
+    }
+
+    #[test]
+    fn test_invalid_function() {
+        assert!(arca::Function::<Runtime>::new(Value::Word(Word::new(0))).is_err());
+        let bad = Tuple::from(("Arcane", Tuple::new(1)));
+        assert!(arca::Function::<Runtime>::new(bad).is_err());
+    }
+}
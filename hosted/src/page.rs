@@ -0,0 +1,99 @@
+//! Pages, whose memory is allocated when they are first written.
+
+use std::sync::{Arc, OnceLock};
+
+use arca::Digest;
+
+pub const SIZES: [usize; 3] = [1 << 12, 1 << 21, 1 << 30];
+
+/// A page of zeroes until it is written.  Clones share their memory until one of them writes.
+#[derive(Clone, Debug)]
+pub struct Page {
+    size: usize,
+    data: Option<Arc<Vec<u8>>>,
+    /// The digest of the page's bytes, once computed; cleared when the page is written.
+    digest: OnceLock<Digest>,
+}
+
+impl Page {
+    /// Creates a zeroed page of the smallest size which holds `len` bytes.
+    pub fn new(len: usize) -> Page {
+        let Some(&size) = SIZES.iter().find(|&&size| len <= size) else {
+            panic!("attempted to create page with size {len}");
+        };
+        Page {
+            size,
+            data: None,
+            digest: OnceLock::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let len = core::cmp::min(buf.len(), self.size.saturating_sub(offset));
+        match &self.data {
+            Some(data) => buf[..len].copy_from_slice(&data[offset..offset + len]),
+            None => buf[..len].fill(0),
+        }
+        len
+    }
+
+    pub fn write(&mut self, offset: usize, buf: &[u8]) -> usize {
+        let len = core::cmp::min(buf.len(), self.size.saturating_sub(offset));
+        let size = self.size;
+        self.digest = OnceLock::new();
+        let data = self.data.get_or_insert_with(|| Arc::new(vec![0; size]));
+        Arc::make_mut(data)[offset..offset + len].copy_from_slice(&buf[..len]);
+        len
+    }
+}
+
+impl Page {
+    /// The digest of the page's bytes, computed once per write.
+    pub fn digest(&self) -> Digest {
+        *self.digest.get_or_init(|| match &self.data {
+            Some(data) => Digest::of_bytes(data),
+            None => Digest::of_reader(self.size, |_, buf| {
+                buf.fill(0);
+                buf.len()
+            }),
+        })
+    }
+}
+
+impl PartialEq for Page {
+    fn eq(&self, other: &Self) -> bool {
+        if self.size != other.size {
+            return false;
+        }
+        match (&self.data, &other.data) {
+            (Some(x), Some(y)) => Arc::ptr_eq(x, y) || x == y,
+            (Some(x), None) | (None, Some(x)) => x.iter().all(|&b| b == 0),
+            (None, None) => true,
+        }
+    }
+}
+
+impl Eq for Page {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page() {
+        let mut page = Page::new(100);
+        assert_eq!(page.size(), 1 << 12);
+        assert_eq!(Page::new((1 << 12) + 1).size(), 1 << 21);
+        let pristine = page.clone();
+        assert_eq!(page.write(4094, b"abc"), 2);
+        let mut buf = [0xff; 4];
+        assert_eq!(page.read(4092, &mut buf), 4);
+        assert_eq!(buf, [0, 0, b'a', b'b']);
+        assert_ne!(page, pristine);
+        assert_eq!(pristine, Page::new(1));
+    }
+}
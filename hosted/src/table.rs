@@ -0,0 +1,95 @@
+//! Page tables of 512 entries, each a page or a table one level down.
+
+use crate::{Entry, Error};
+
+pub const SIZES: [usize; 4] = [1 << 21, 1 << 30, 1 << 39, 1 << 48];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Table {
+    size: usize,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    /// Creates an empty table of the smallest size which spans `len` bytes.
+    pub fn new(len: usize) -> Table {
+        let Some(&size) = SIZES.iter().find(|&&size| len <= size) else {
+            panic!("attempted to create table with size {len}");
+        };
+        Table {
+            size,
+            entries: vec![Entry::Null(size / 512); 512],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bytes each entry spans.
+    pub fn entry_size(&self) -> usize {
+        self.size / 512
+    }
+
+    pub fn get(&self, index: usize) -> Result<Entry, Error> {
+        self.entries
+            .get(index)
+            .cloned()
+            .ok_or(Error::InvalidIndex(index))
+    }
+
+    pub fn set(&mut self, index: usize, entry: Entry) -> Result<Entry, Error> {
+        if index >= self.entries.len() {
+            return Err(Error::InvalidIndex(index));
+        }
+        let entry = match entry {
+            Entry::Null(_) => Entry::Null(self.entry_size()),
+            x if x.len() == self.entry_size() => x,
+            x => return Err(Error::InvalidTableEntry(x)),
+        };
+        Ok(core::mem::replace(&mut self.entries[index], entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    type Page = arca::Page<Runtime>;
+
+    #[test]
+    fn test_set() {
+        let mut table = Table::new(0);
+        assert_eq!(table.size(), 1 << 21);
+        assert_eq!(table.get(3), Ok(Entry::Null(1 << 12)));
+        let page = Entry::RWPage(Page::new(1 << 12));
+        assert_eq!(table.set(3, page.clone()), Ok(Entry::Null(1 << 12)));
+        assert_eq!(table.get(3), Ok(page));
+        assert_eq!(
+            table.set(512, Entry::Null(0)),
+            Err(Error::InvalidIndex(512))
+        );
+        let huge = Entry::ROPage(Page::new(1 << 21));
+        assert_eq!(
+            table.set(0, huge.clone()),
+            Err(Error::InvalidTableEntry(huge))
+        );
+    }
+
+    /// Verifies `arca::Table::map` grows the table and nests tables down to the page.
+    #[test]
+    fn test_map() {
+        let mut table = arca::Table::<Runtime>::new(0);
+        let mut page = Page::new(1 << 12);
+        page.write(0, b"hello");
+        table.map(0x4000_0000, Entry::RWPage(page)).unwrap();
+        assert_eq!(table.len(), 1 << 39);
+        let mut buf = [0; 5];
+        assert_eq!(table.read_bytes(0x4000_0000, &mut buf), Ok(5));
+        assert_eq!(&buf, b"hello");
+        assert_eq!(table.read_bytes(0x4000_1000, &mut buf), Ok(0));
+        assert!(matches!(table.unmap(0x4000_0000), Some(Entry::RWPage(_))));
+        assert_eq!(table.read_bytes(0x4000_0000, &mut buf), Ok(0));
+    }
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use kernel::host::net::TcpListener;
+use kernel::kthread::executor;
+use kernel::prelude::*;
+
+#[kmain]
+fn main() {
+    executor::block_on(async {
+        let listener = TcpListener::bind(&[0, 0, 0, 0], 8080);
+        log::info!("listening on port 8080");
+        loop {
+            let mut stream = listener.accept_async().await;
+            executor::spawn(async move {
+                let mut buf = vec![0; 4096];
+                loop {
+                    let n = stream.recv_async(&mut buf).await;
+                    if n == 0 {
+                        break;
+                    }
+                    let mut data = &buf[..n];
+                    while !data.is_empty() {
+                        let sent = stream.send_async(data).await;
+                        data = &data[sent..];
+                    }
+                }
+            });
+        }
+    });
+}
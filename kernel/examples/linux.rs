@@ -0,0 +1,135 @@
+#![no_std]
+#![no_main]
+
+//! Runs a static Linux executable (e.g. `apps/hello.c` built with `musl-gcc -static`), performing
+//! its file effects on the host's files.
+
+use common::elfloader;
+use kernel::debugger::Debugger;
+use kernel::host::{fs::File, os};
+use kernel::prelude::*;
+
+const O_ACCMODE: u64 = 0o3;
+const O_WRONLY: u64 = 0o1;
+const O_RDWR: u64 = 0o2;
+const O_CREAT: u64 = 0o100;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+const EBADF: i64 = 9;
+const ENOENT: i64 = 2;
+
+fn open(path: &str, flags: u64) -> Option<File> {
+    let mode = flags & O_ACCMODE;
+    File::open(
+        path,
+        mode != O_WRONLY,
+        mode == O_WRONLY || mode == O_RDWR,
+        flags & O_CREAT != 0,
+        flags & O_APPEND != 0,
+        flags & O_TRUNC != 0,
+    )
+    .ok()
+}
+
+#[kmain]
+fn main() {
+    let args = os::argv();
+    let Some(path) = args.get(1) else {
+        log::error!("usage: linux <static Linux executable> [arguments...]");
+        return;
+    };
+    let mut file = open(path, 0).expect("could not open executable");
+    let mut elf = Vec::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = file.read(&mut buf);
+        if n == 0 {
+            break;
+        }
+        elf.extend_from_slice(&buf[..n]);
+    }
+    // the program's arguments are ours from its path on
+    let argv: Tuple = args[1..].iter().map(|x| Blob::from(x.as_str())).collect();
+    let options = elfloader::Options {
+        argv,
+        linux: true,
+        ..Default::default()
+    };
+    let mut f: Function =
+        elfloader::load_elf_with(&elf, options).expect("could not load executable");
+
+    let mut files: Vec<Option<File>> = vec![
+        open("/dev/stdin", 0),
+        open("/dev/stdout", O_WRONLY),
+        open("/dev/stderr", O_WRONLY),
+    ];
+    // run the program under gdb if the host serves it (with `--gdb`)
+    let mut debugger = Debugger::attach();
+    loop {
+        let effect = match &mut debugger {
+            Some(debugger) => debugger.force(f),
+            None => f.force(),
+        };
+        let effect: Function = effect.try_into().expect("program returned non-effect");
+        let mut data: Tuple = effect.read().try_into().expect("could not read effect");
+        let effect: Blob = data.take(1).try_into().expect("could not find effect name");
+        let args: Tuple = data
+            .take(2)
+            .try_into()
+            .expect("could not find effect arguments");
+        let mut args: Vec<Value> = args.into_iter().collect();
+        let k: Function = args
+            .pop()
+            .and_then(|x| x.try_into().ok())
+            .expect("could not find continuation");
+        let file = |files: &mut Vec<Option<File>>, fd: Word| {
+            files.get_mut(fd.read() as usize).and_then(Option::as_mut)
+        };
+        f = match (&*effect, &*args) {
+            (b"read", &[Value::Word(fd), Value::Word(len)]) => match file(&mut files, fd) {
+                Some(file) => {
+                    let mut v = vec![0; len.read() as usize];
+                    let n = file.read(&mut v);
+                    v.truncate(n);
+                    k.apply(Blob::new(v))
+                }
+                None => k.apply(Word::new(-EBADF as u64)),
+            },
+            (b"write", &[Value::Word(fd), Value::Blob(ref data)]) => {
+                let n = match file(&mut files, fd) {
+                    Some(file) => file.write_exact(&**data) as i64,
+                    None => -EBADF,
+                };
+                k.apply(Word::new(n as u64))
+            }
+            (b"open", &[Value::Blob(ref path), Value::Word(flags), Value::Word(_)]) => {
+                let path = str::from_utf8(&**path).unwrap_or_default();
+                let fd = match open(path, flags.read()) {
+                    Some(file) => {
+                        files.push(Some(file));
+                        files.len() as i64 - 1
+                    }
+                    None => -ENOENT,
+                };
+                k.apply(Word::new(fd as u64))
+            }
+            (b"close", &[Value::Word(fd)]) => {
+                if let Some(file) = files.get_mut(fd.read() as usize) {
+                    file.take();
+                }
+                k.apply(Word::new(0))
+            }
+            (b"exit", &[Value::Word(code)]) => {
+                log::info!("exited with {}", code.read());
+                if let Some(debugger) = &mut debugger {
+                    debugger.exited();
+                }
+                break;
+            }
+            (e, _) => {
+                let e = str::from_utf8(e).unwrap_or_default();
+                panic!("unhandled effect: {e}");
+            }
+        };
+    }
+}
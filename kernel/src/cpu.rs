@@ -7,7 +7,7 @@ use alloc::format;
 
 use crate::{initcell::LazyLock, prelude::*, vm::ka2pa};
 
-use crate::types::table::Table;
+use crate::types::table::{Table, USER_ENTRIES};
 
 #[core_local]
 pub static CPU: LazyLock<RefCell<Cpu>> = LazyLock::new(|| {
@@ -25,6 +25,7 @@ pub static CPU: LazyLock<RefCell<Cpu>> = LazyLock::new(|| {
         pml4,
         pdpt: None,
         pd: None,
+        user: None,
     })
 });
 
@@ -33,6 +34,8 @@ pub struct Cpu {
     pml4: UniquePage<AugmentedPageTable<PageTable256TB>>,
     pdpt: Option<UniquePage<AugmentedPageTable<PageTable512GB>>>,
     pd: Option<UniquePage<AugmentedPageTable<PageTable1GB>>>,
+    /// The (emptied) 256 TiB table whose entries form the user half of the PML4, if one is active.
+    user: Option<UniquePage<AugmentedPageTable<PageTable256TB>>>,
 }
 
 impl !Sync for Cpu {}
@@ -218,6 +221,57 @@ impl From<ExitStatus> for ExitReason {
     }
 }
 
+impl ExitReason {
+    /// The interrupt vector this exit was caused by, if it was not a system call.
+    pub fn vector(&self) -> Option<u8> {
+        Some(match self {
+            ExitReason::DivisionByZero => 0,
+            ExitReason::Debug => 1,
+            ExitReason::Interrupted(x) => *x as u8,
+            ExitReason::Breakpoint => 3,
+            ExitReason::InvalidInstruction => 6,
+            ExitReason::DeviceNotAvailable => 7,
+            ExitReason::DoubleFault => 8,
+            ExitReason::InvalidTSS { .. } => 10,
+            ExitReason::SegmentNotPresent { .. } => 11,
+            ExitReason::StackSegmentFault { .. } => 12,
+            ExitReason::GeneralProtectionFault { .. } => 13,
+            ExitReason::PageFault { .. } => 14,
+            ExitReason::FloatingPointException => 16,
+            ExitReason::AlignmentCheck { .. } => 17,
+            ExitReason::MachineCheck => 18,
+            ExitReason::SIMDException => 19,
+            ExitReason::VirtualizationException => 20,
+            ExitReason::ControlProtectionException => 21,
+            ExitReason::HypervisorInjectionException => 28,
+            ExitReason::VMMCommunicationException => 29,
+            ExitReason::SecurityException => 30,
+            ExitReason::SystemCall => return None,
+        })
+    }
+
+    /// The error code the CPU pushed for this exception, or zero if it has none.
+    pub fn error_code(&self) -> u64 {
+        match *self {
+            ExitReason::InvalidTSS { selector }
+            | ExitReason::SegmentNotPresent { selector }
+            | ExitReason::StackSegmentFault { selector }
+            | ExitReason::GeneralProtectionFault { selector } => selector as u64,
+            ExitReason::PageFault { error, .. } => error,
+            ExitReason::AlignmentCheck { code } => code,
+            _ => 0,
+        }
+    }
+
+    /// The address a page fault was caused by (as read from CR2).
+    pub fn fault_address(&self) -> Option<usize> {
+        match *self {
+            ExitReason::PageFault { addr, .. } => Some(addr),
+            _ => None,
+        }
+    }
+}
+
 extern "C" {
     fn set_pt(page_map: usize);
     // fn flush_tlb() -> usize;
@@ -245,6 +299,19 @@ impl Cpu {
             Table::Table512GB(table) => {
                 self.pml4.entry_mut(0).chain_unique(table.unique());
             }
+            Table::Table256TB(table) => {
+                let mut table = table.unique();
+                let entries = table.offset()..table.offset() + table.len();
+                for i in entries {
+                    match table.unmap(i) {
+                        AugmentedUnmappedPage::None => {}
+                        entry => {
+                            self.pml4.entry_mut(i).replace(entry);
+                        }
+                    }
+                }
+                self.user = Some(table);
+            }
         }
         unsafe {
             set_pt(ka2pa(Box::as_ptr(&self.pml4)));
@@ -261,6 +328,17 @@ impl Cpu {
     }
 
     pub fn deactivate_address_space(&mut self) -> Table {
+        if let Some(mut table) = self.user.take() {
+            for i in 0..USER_ENTRIES {
+                match self.pml4.unmap(i) {
+                    AugmentedUnmappedPage::None => {}
+                    entry => {
+                        table.entry_mut(i).replace(entry);
+                    }
+                }
+            }
+            return Table::Table256TB(table.into());
+        }
         let AugmentedUnmappedPage::UniqueTable(mut pdpt) = self.pml4.unmap(0) else {
             todo!();
         };
@@ -294,11 +372,22 @@ impl Cpu {
         assert!(crate::vm::is_user(address));
 
         let i_512gb = (address >> 39) & 0x1ff;
-        assert_eq!(i_512gb, 0);
+        let user = self.user.is_some();
+        if (i_512gb != 0 && !user) || entry.len() >= PageTable512GB::SIZE {
+            // slow path: the address space has to grow into a 256 TiB table, or a whole 512 GiB
+            // entry of one is being replaced
+            let mut table = arca::Table::from_inner(self.deactivate_address_space());
+            let result = table.map(address, entry);
+            self.activate_address_space(table.into_inner());
+            return result;
+        }
 
+        // the 512 GiB table holding the address is updated in place in the live page table (a
+        // 256 TiB address space keeps its tables there while it is active)
         let pml4 = &mut self.pml4;
         // TODO: check behavior when inserting unique into shared
         let pdpt = match pml4.entry_mut(i_512gb).unmap() {
+            AugmentedUnmappedPage::None if user => AugmentedPageTable::new(),
             AugmentedUnmappedPage::None => {
                 todo!("inserting into larger-than-1GB address space");
                 // AugmentedPageTable::new()
@@ -309,13 +398,21 @@ impl Cpu {
         };
         let table = Table::from(CowPage::Unique(pdpt));
         let mut table = arca::Table::from_inner(table);
-        let result = table.map(address, entry)?;
+        let size = entry.len();
+        let result = table.map(address & (PageTable512GB::SIZE - 1), entry)?;
         match table.into_inner() {
             Table::Table512GB(page) => pml4.entry_mut(i_512gb).chain_unique(page.unique()),
             _ => todo!(),
         };
-        unsafe {
-            core::arch::asm!("invlpg [{pg}]", pg=in(reg)address);
+        if size > Page4KB::SIZE {
+            // the replaced entry may have been a table with many cached translations
+            unsafe {
+                set_pt(ka2pa(Box::as_ptr(&self.pml4)));
+            }
+        } else {
+            unsafe {
+                core::arch::asm!("invlpg [{pg}]", pg=in(reg)address);
+            }
         }
         Ok(result)
     }
@@ -334,9 +431,20 @@ impl Cpu {
     }
 }
 
+/// An exception as `(vector, error code, fault address, description)`; see
+/// [`Function::exception`](crate::types::internal::Function::exception).
 impl From<ExitReason> for Value {
     fn from(value: ExitReason) -> Self {
-        let result = format!("{value:x?}");
-        Value::Blob(Blob::from_inner(result.into()))
+        let vector = match value.vector() {
+            Some(vector) => Value::Word(Word::new(vector as u64)),
+            None => Value::default(),
+        };
+        let description = Blob::from_inner(format!("{value:x?}").into());
+        Value::Tuple(Tuple::from((
+            vector,
+            Word::new(value.error_code()),
+            Word::new(value.fault_address().unwrap_or(0) as u64),
+            description,
+        )))
     }
 }
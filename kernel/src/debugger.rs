@@ -0,0 +1,236 @@
+//! Running Arcane functions under the host's gdb stub.
+//!
+//! [`Debugger::attach`] opens a debug pipe to the host, and the first function forced with
+//! [`Debugger::force`] stops before its first instruction.  The debugger stays attached until
+//! [`Debugger::exited`], so a program's continuations can be forced under the same session.
+//!
+//! While a function is stopped, the host can read and write its registers and its memory (through
+//! its page table), set software breakpoints, and then continue or single-step it.  Breakpoints
+//! are only written into memory while the function runs, so the host always sees the original
+//! bytes.
+
+use alloc::collections::BTreeMap;
+use core::ops::ControlFlow;
+
+use common::protocol::debug::{Command, Event, Stop, REGISTERS};
+
+use crate::{cpu::ExitReason, pipe::DebugPipe, prelude::*, types::LoadedArca};
+
+const INT3: u8 = 0xcc;
+/// The RFLAGS bits the debugger may change (the arithmetic flags and DF).
+const USER_FLAGS: u64 = 0xcd5;
+/// The RFLAGS bits which are always set in user mode (IF and the reserved bit 1).
+const FIXED_FLAGS: u64 = 0x202;
+
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+    Kill,
+}
+
+pub struct Debugger {
+    pipe: DebugPipe,
+    /// Software breakpoints, with the original byte at each while they are inserted.
+    breakpoints: BTreeMap<usize, Option<u8>>,
+    pending: Option<Stop>,
+    stepping: bool,
+    attached: bool,
+}
+
+/// The result of a function the host killed, which cannot be resumed.
+fn killed(arca: &mut LoadedArca) -> Value {
+    let backtrace = crate::types::backtrace(arca);
+    let info = Tuple::from((
+        Value::default(),
+        Word::new(0),
+        Word::new(0),
+        Blob::from("killed"),
+    ));
+    crate::types::internal::Function::exception(info, backtrace, Value::default())
+}
+
+impl Debugger {
+    pub fn attach() -> Option<Debugger> {
+        Some(Debugger {
+            pipe: crate::host::debugger()?,
+            breakpoints: BTreeMap::new(),
+            pending: Some(Stop::Entry),
+            stepping: false,
+            attached: true,
+        })
+    }
+
+    /// Forces `f`, stopping it wherever the host asks.
+    pub fn force(&mut self, f: Function) -> Value {
+        let mut cpu = CPU.borrow_mut();
+        f.into_inner().force_with(&mut cpu, |arca| self.run(arca))
+    }
+
+    /// Runs `arca` until it needs the kernel's attention, stopping for the debugger as needed.
+    pub fn run(&mut self, arca: &mut LoadedArca) -> ControlFlow<Value, ExitReason> {
+        loop {
+            if let Some(stop) = self.pending.take() {
+                match self.stopped(arca, stop) {
+                    Resume::Continue => self.stepping = false,
+                    Resume::Step => self.stepping = true,
+                    Resume::Detach => self.detach(),
+                    Resume::Kill => {
+                        self.detach();
+                        return ControlFlow::Break(killed(arca));
+                    }
+                }
+            }
+            if !self.attached {
+                return ControlFlow::Continue(arca.run());
+            }
+
+            let rip = arca.registers()[Register::RIP] as usize;
+            let result = if self.stepping || self.breakpoints.contains_key(&rip) {
+                // a breakpoint at RIP is stepped over while it is not inserted
+                let result = arca.single_step();
+                if result == ExitReason::Debug {
+                    if self.stepping {
+                        self.pending = Some(Stop::Step);
+                    }
+                    continue;
+                }
+                result
+            } else {
+                self.insert_breakpoints(arca);
+                let result = arca.run();
+                self.remove_breakpoints(arca);
+                if result == ExitReason::Breakpoint {
+                    let rip = arca.registers()[Register::RIP] as usize - 1;
+                    if self.breakpoints.contains_key(&rip) {
+                        arca.registers_mut()[Register::RIP] = rip as u64;
+                        self.pending = Some(Stop::Breakpoint);
+                        continue;
+                    }
+                }
+                result
+            };
+
+            match result {
+                ExitReason::SystemCall => {
+                    if self.stepping {
+                        // stop once the kernel has handled the system call
+                        self.pending = Some(Stop::Step);
+                    }
+                }
+                ExitReason::Interrupted(_) => {}
+                ref x => {
+                    let vector = x.vector().unwrap_or_default();
+                    match self.stopped(arca, Stop::Exception(vector)) {
+                        Resume::Continue | Resume::Step => {}
+                        Resume::Detach => self.detach(),
+                        Resume::Kill => {
+                            self.detach();
+                            return ControlFlow::Break(killed(arca));
+                        }
+                    }
+                }
+            }
+            return ControlFlow::Continue(result);
+        }
+    }
+
+    /// Tells the host the function has finished.
+    pub fn exited(&mut self) {
+        if self.attached {
+            self.pipe.request(&Event::Stopped(Stop::Exited));
+            self.detach();
+        }
+    }
+
+    fn detach(&mut self) {
+        self.attached = false;
+        self.breakpoints.clear();
+    }
+
+    /// Serves the host's commands until it resumes the function.
+    fn stopped(&mut self, arca: &mut LoadedArca, stop: Stop) -> Resume {
+        let mut event = Event::Stopped(stop);
+        loop {
+            event = match self.pipe.request(&event) {
+                Command::ReadRegisters => {
+                    let registers = arca.registers();
+                    Event::Registers((0..REGISTERS).map(|i| registers[i]).collect())
+                }
+                Command::WriteRegisters(values) => {
+                    if values.len() == REGISTERS {
+                        let registers = arca.registers_mut();
+                        for (i, x) in values.into_iter().enumerate() {
+                            registers[i] = x;
+                        }
+                        let flags = &mut registers[Register::RFLAGS];
+                        *flags = (*flags & USER_FLAGS) | FIXED_FLAGS;
+                        Event::Ack
+                    } else {
+                        Event::Err
+                    }
+                }
+                Command::ReadMemory { address, len } => {
+                    let mut bytes = vec![0; len];
+                    let read = arca.with_mappings(|table| table.read_bytes(address, &mut bytes));
+                    bytes.truncate(read.unwrap_or(0));
+                    Event::Memory(bytes)
+                }
+                Command::WriteMemory { address, bytes } => {
+                    let written = arca.with_mappings(|table| table.write_bytes(address, &bytes));
+                    match written {
+                        Ok(n) if n == bytes.len() => Event::Ack,
+                        _ => Event::Err,
+                    }
+                }
+                Command::InsertBreakpoint(address) => {
+                    let mut byte = [0];
+                    let read = arca.with_mappings(|table| table.read_bytes(address, &mut byte));
+                    if matches!(read, Ok(1)) {
+                        self.breakpoints.insert(address, None);
+                        Event::Ack
+                    } else {
+                        Event::Err
+                    }
+                }
+                Command::RemoveBreakpoint(address) => {
+                    self.breakpoints.remove(&address);
+                    Event::Ack
+                }
+                Command::Continue => return Resume::Continue,
+                Command::Step => return Resume::Step,
+                Command::Detach => return Resume::Detach,
+                Command::Kill => return Resume::Kill,
+            }
+        }
+    }
+
+    fn insert_breakpoints(&mut self, arca: &mut LoadedArca) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+        arca.with_mappings(|table| {
+            for (&address, original) in self.breakpoints.iter_mut() {
+                let mut byte = [0];
+                if matches!(table.read_bytes(address, &mut byte), Ok(1))
+                    && matches!(table.write_bytes(address, &[INT3]), Ok(1))
+                {
+                    *original = Some(byte[0]);
+                }
+            }
+        });
+    }
+
+    fn remove_breakpoints(&mut self, arca: &mut LoadedArca) {
+        if self.breakpoints.is_empty() {
+            return;
+        }
+        arca.with_mappings(|table| {
+            for (&address, original) in self.breakpoints.iter_mut() {
+                if let Some(byte) = original.take() {
+                    let _ = table.write_bytes(address, &[byte]);
+                }
+            }
+        });
+    }
+}
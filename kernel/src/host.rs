@@ -2,6 +2,10 @@ use crate::prelude::*;
 
 use alloc::format;
 use common::hypercall;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The host's [`hypercall::flags`], as fetched at boot.
+static FLAGS: AtomicU64 = AtomicU64::new(0);
 
 pub struct HostLogger;
 
@@ -50,6 +54,22 @@ impl log::Log for HostLogger {
 
 pub static HOST: HostLogger = HostLogger;
 
+/// Asks the host what it has been configured to do.
+pub(crate) fn init() {
+    let flags = unsafe { crate::io::hypercall0(hypercall::FLAGS) };
+    FLAGS.store(flags, Ordering::Relaxed);
+}
+
+/// Whether the host is recording or replaying the guest's nondeterministic inputs.
+pub fn journaled() -> bool {
+    FLAGS.load(Ordering::Relaxed) & hypercall::flags::JOURNAL != 0
+}
+
+/// Whether the host wants the kernel's counters (see [`crate::stats`]).
+pub fn monitored() -> bool {
+    FLAGS.load(Ordering::Relaxed) & hypercall::flags::MONITOR != 0
+}
+
 pub fn symname(addr: *const ()) -> Option<(String, usize)> {
     unsafe {
         let mut buffer: Box<[u8]> = Box::new_uninit_slice(1024).assume_init();
@@ -119,6 +139,17 @@ unsafe fn get_pipe(data: common::protocol::control::PipeData) -> HostPipe {
     HostPipe::new(pipe)
 }
 
+/// Opens a pipe to the host's debugger stub, if the host has one.
+pub(crate) fn debugger() -> Option<crate::pipe::DebugPipe> {
+    use common::protocol::control;
+    let mut binding = crate::pipe::HOST.lock();
+    let host = binding.get_mut().unwrap();
+    match host.request(&control::Request::Debugger) {
+        control::Response::Pipe(id) => unsafe { Some(crate::pipe::DebugPipe::new(get_pipe(id))) },
+        _ => None,
+    }
+}
+
 pub mod net {
     use super::get_pipe;
     use crate::pipe::*;
@@ -151,8 +182,17 @@ pub mod net {
 
         pub fn accept(&self) -> TcpStream {
             let mut pipe = self.pipe.lock();
-            let listener::Response::Pipe(id) = pipe.request(&listener::Request::Accept) else {
-                todo!();
+            Self::stream(pipe.request(&listener::Request::Accept))
+        }
+
+        pub async fn accept_async(&self) -> TcpStream {
+            let mut pipe = self.pipe.lock_async().await;
+            Self::stream(pipe.request_async(&listener::Request::Accept).await)
+        }
+
+        fn stream(response: listener::Response) -> TcpStream {
+            let listener::Response::Pipe(id) = response else {
+                panic!("bad response");
             };
             unsafe {
                 TcpStream {
@@ -207,6 +247,29 @@ pub mod net {
             bytes.len()
         }
 
+        pub async fn send_async(&mut self, bytes: &[u8]) -> usize {
+            let stream::Response::Length(len) = self
+                .pipe
+                .request_async(&stream::Request::Send(bytes.into()))
+                .await
+            else {
+                panic!("bad response");
+            };
+            len
+        }
+
+        pub async fn recv_async(&mut self, bytes: &mut [u8]) -> usize {
+            let stream::Response::Bytes(buf) = self
+                .pipe
+                .request_async(&stream::Request::Receive(bytes.len()))
+                .await
+            else {
+                panic!("bad response");
+            };
+            bytes[..buf.len()].copy_from_slice(&buf);
+            buf.len()
+        }
+
         pub fn close(self) {
             let _ = self;
         }
@@ -237,6 +300,7 @@ pub mod net {
 pub mod fs {
     use super::get_pipe;
     use crate::pipe::*;
+    use crate::prelude::*;
     pub use common::protocol::file::Whence;
     use common::{
         protocol::control::{ErrorKind, FileMode},
@@ -302,6 +366,18 @@ pub mod fs {
             bytes.len()
         }
 
+        pub async fn read_async(&mut self, buf: &mut [u8]) -> usize {
+            let file::Response::Bytes(bytes) = self
+                .pipe
+                .request_async(&file::Request::Read(buf.len()))
+                .await
+            else {
+                panic!("bad response");
+            };
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            bytes.len()
+        }
+
         pub fn read_exact(&mut self, mut buf: &mut [u8]) -> usize {
             let mut total = 0;
             while !buf.is_empty() {
@@ -323,6 +399,17 @@ pub mod fs {
             len
         }
 
+        pub async fn write_async(&mut self, buf: &[u8]) -> usize {
+            let file::Response::Length(len) = self
+                .pipe
+                .request_async(&file::Request::Write(buf.into()))
+                .await
+            else {
+                panic!("bad response");
+            };
+            len
+        }
+
         pub fn write_exact(&mut self, mut buf: &[u8]) -> usize {
             let mut total = 0;
             while !buf.is_empty() {
@@ -364,5 +451,69 @@ pub mod fs {
         }
     }
 
+    impl arca::archive::Sink for File {
+        type Error = ErrorKind;
+
+        fn write_all(&mut self, bytes: &[u8]) -> Result<(), ErrorKind> {
+            if self.write_exact(bytes) == bytes.len() {
+                Ok(())
+            } else {
+                Err(ErrorKind::WriteZero)
+            }
+        }
+    }
+
+    /// How much of a file a [`BufReader`] asks the host for at once.
+    const BUFFER: usize = 1 << 16;
+
+    /// Reads a [`File`] in large chunks, so that small reads (like the archive decoder's) do not
+    /// each cost a round trip to the host.
+    pub struct BufReader {
+        file: File,
+        buffer: Box<[u8]>,
+        start: usize,
+        end: usize,
+    }
+
+    impl BufReader {
+        pub fn new(file: File) -> Self {
+            BufReader {
+                file,
+                buffer: vec![0; BUFFER].into_boxed_slice(),
+                start: 0,
+                end: 0,
+            }
+        }
+    }
+
+    impl arca::archive::Source for BufReader {
+        type Error = ErrorKind;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), ErrorKind> {
+            while !buf.is_empty() {
+                if self.start == self.end {
+                    if buf.len() >= self.buffer.len() {
+                        // too big to be worth buffering
+                        return if self.file.read_exact(buf) == buf.len() {
+                            Ok(())
+                        } else {
+                            Err(ErrorKind::Other)
+                        };
+                    }
+                    self.start = 0;
+                    self.end = self.file.read(&mut self.buffer);
+                    if self.end == 0 {
+                        return Err(ErrorKind::Other);
+                    }
+                }
+                let n = core::cmp::min(buf.len(), self.end - self.start);
+                buf[..n].copy_from_slice(&self.buffer[self.start..self.start + n]);
+                self.start += n;
+                buf = &mut buf[n..];
+            }
+            Ok(())
+        }
+    }
+
     impl !Sync for File {}
 }
@@ -123,6 +123,7 @@ unsafe extern "C" fn isr_entry(registers: &mut IsrRegisterFile) {
         if registers.isr == 0x20 {
             INTERRUPTED.store(true, Ordering::Relaxed);
             crate::iprofile::tick(registers);
+            crate::stats::tick();
             crate::lapic::LAPIC.borrow_mut().clear_interrupt();
         }
         // return to user mode
@@ -188,6 +189,7 @@ unsafe extern "C" fn isr_entry(registers: &mut IsrRegisterFile) {
     if registers.isr == 0x20 {
         INTERRUPTED.store(true, Ordering::Relaxed);
         crate::iprofile::tick(registers);
+        crate::stats::tick();
         crate::lapic::LAPIC.borrow_mut().clear_interrupt();
     } else {
         panic!("unhandled system ISR: {:x?}", registers);
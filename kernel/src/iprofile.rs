@@ -4,6 +4,7 @@ use core::{
 };
 
 use alloc::collections::btree_map::BTreeMap;
+use common::protocol::control::ErrorKind;
 
 use crate::{
     debugcon::CONSOLE,
@@ -12,6 +13,12 @@ use crate::{
     prelude::*,
 };
 
+pub mod stacks;
+pub mod user;
+
+/// The furthest apart two consecutive frame pointers may be before a stack walk gives up.
+const MAX_FRAME: usize = 1 << 20;
+
 static PROFILING: AtomicBool = AtomicBool::new(false);
 static ACTIVE: AtomicUsize = AtomicUsize::new(0);
 static COUNTS: OnceLock<&'static [AtomicUsize]> = OnceLock::new();
@@ -31,12 +38,17 @@ pub(crate) unsafe fn init() {
         let buffer = Box::new_zeroed_slice(size).assume_init();
         Box::leak(buffer)
     });
+    stacks::init();
 }
 
 pub fn begin() {
     PROFILING.store(true, Ordering::SeqCst);
 }
 
+pub fn profiling() -> bool {
+    PROFILING.load(Ordering::SeqCst)
+}
+
 pub fn end() {
     PROFILING.store(false, Ordering::SeqCst);
     while ACTIVE.load(Ordering::SeqCst) != 0 {
@@ -74,6 +86,8 @@ pub fn reset() {
         count.store(0, Ordering::SeqCst);
     }
     USER_COUNT.store(0, Ordering::SeqCst);
+    stacks::reset();
+    user::reset();
 }
 
 pub(crate) fn tick(registers: &IsrRegisterFile) {
@@ -85,6 +99,7 @@ pub(crate) fn tick(registers: &IsrRegisterFile) {
     if registers.cs & 0b11 == 0b11 {
         // user mode
         USER_COUNT.fetch_add(1, Ordering::SeqCst);
+        user::tick(registers.rip);
     } else {
         let start = &raw const _stext;
         let end = &raw const _etext;
@@ -99,6 +114,7 @@ pub(crate) fn tick(registers: &IsrRegisterFile) {
         unsafe {
             let offset = rip.offset_from_unsigned(start);
             COUNTS[offset].fetch_add(1, Ordering::SeqCst);
+            stacks::tick(registers.rip, registers.registers[Register::RBP as usize]);
         }
     }
     ACTIVE.fetch_sub(1, Ordering::SeqCst);
@@ -174,6 +190,14 @@ pub fn log(count: usize) {
         }
         writeln!(console, "{i}.\t{n:5}\t{symname}").unwrap();
     }
+    let mut user: Vec<_> = user::entries().into_iter().collect();
+    if !user.is_empty() {
+        user.sort_by_key(|(_, n)| core::cmp::Reverse(*n));
+        writeln!(console, "----- USER -----").unwrap();
+        for (i, (stack, n)) in user.iter().take(count).enumerate() {
+            writeln!(console, "{i}.\t{n:5}\t{stack}").unwrap();
+        }
+    }
     writeln!(console, "###################").unwrap();
 }
 
@@ -192,19 +216,165 @@ pub fn backtrace(f: impl FnMut(*const (), Option<(String, usize)>)) {
 ///
 /// The value of `rbp` must be a valid base/frame pointer from which to backtrace.
 pub unsafe fn backtrace_from(
-    mut rbp: *const usize,
+    rbp: *const usize,
     mut f: impl FnMut(*const (), Option<(String, usize)>),
 ) {
-    if rbp.is_null() {
-        return;
-    }
-    let mut rip: *const ();
+    frames_from(rbp, |rip| {
+        f(rip, crate::host::symname(rip));
+        true
+    });
+}
+
+/// Walks the frame-pointer chain from `rbp`, calling `f` with each return address until it
+/// returns false.
+///
+/// Unlike [`backtrace_from`], this neither allocates nor calls the host, so it can be used from an
+/// ISR; it also stops at any frame pointer that does not look like it is on a kernel stack.
+///
+/// # Safety
+///
+/// The value of `rbp` must be a frame pointer of kernel code.
+pub(crate) unsafe fn frames_from(mut rbp: *const usize, mut f: impl FnMut(*const ()) -> bool) {
     loop {
-        rip = rbp.add(1).read() as *const ();
-        rbp = rbp.read() as *const usize;
-        if rbp.is_null() {
-            break;
+        if rbp.is_null() || !rbp.is_aligned() || crate::vm::is_user(rbp as usize) {
+            return;
+        }
+        let rip = rbp.add(1).read() as *const ();
+        let next = rbp.read() as *const usize;
+        if next.is_null() || next <= rbp || next.addr() - rbp.addr() > MAX_FRAME {
+            return;
+        }
+        if !f(rip) {
+            return;
+        }
+        rbp = next;
+    }
+}
+
+/// A file format for [`export`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// One `frame;frame;...;frame count` line per stack, for `flamegraph.pl` and `inferno`.
+    Folded,
+    /// An uncompressed pprof protobuf, for `go tool pprof`.
+    Pprof,
+}
+
+impl Format {
+    /// Picks the format from a file name: pprof for `.pb` or `.pprof`, folded stacks otherwise.
+    pub fn from_path(path: &str) -> Format {
+        if path.ends_with(".pb") || path.ends_with(".pprof") {
+            Format::Pprof
+        } else {
+            Format::Folded
+        }
+    }
+}
+
+/// Symbolizes kernel addresses, asking the host about each address only once.
+#[derive(Default)]
+struct Symbolizer(BTreeMap<u64, Option<String>>);
+
+impl Symbolizer {
+    fn name(&mut self, rip: u64) -> Option<&str> {
+        self.0
+            .entry(rip)
+            .or_insert_with(|| host::symname(rip as *const ()).map(|(name, _)| name))
+            .as_deref()
+    }
+}
+
+/// Writes the recorded kernel stacks (under a `kernel` root) and user samples as folded stacks.
+pub fn write_folded(out: &mut impl core::fmt::Write) -> core::fmt::Result {
+    let mut symbolizer = Symbolizer::default();
+    let mut folded = BTreeMap::new();
+    for (frames, count) in stacks::entries() {
+        let mut stack = String::from("kernel");
+        for &rip in frames.iter().rev() {
+            stack.push(';');
+            match symbolizer.name(rip) {
+                // `;` separates frames
+                Some(name) => stack.push_str(&name.replace(';', ",")),
+                None => stack.push_str(&format!("{rip:#x}")),
+            }
+        }
+        *folded.entry(stack).or_insert(0) += count;
+    }
+    for (stack, count) in folded {
+        writeln!(out, "{stack} {count}")?;
+    }
+    user::write_folded(out)
+}
+
+/// Encodes the recorded kernel stacks and user samples as a pprof profile.
+///
+/// User samples appear as a two-frame stack, with the program as the root.
+pub fn pprof() -> Vec<u8> {
+    let mut symbolizer = Symbolizer::default();
+    let mut profile = common::pprof::Profile::new("samples", "count");
+    for (frames, count) in stacks::entries() {
+        let names: Vec<_> = frames
+            .iter()
+            .map(|&rip| symbolizer.name(rip).map(String::from))
+            .collect();
+        let stack = frames
+            .iter()
+            .zip(names.iter())
+            .map(|(&rip, name)| (rip, name.as_deref()));
+        profile.sample(stack, count as i64);
+    }
+    for sample in user::samples() {
+        let stack = [
+            (sample.rip, sample.function.as_deref()),
+            (0, Some(sample.program.as_str())),
+        ];
+        profile.sample(stack, sample.count as i64);
+    }
+    profile.encode()
+}
+
+/// Writes the recorded samples to a file on the host.
+pub fn export(path: &str, format: Format) -> Result<(), ErrorKind> {
+    let bytes = match format {
+        Format::Folded => {
+            let mut folded = String::new();
+            write_folded(&mut folded).unwrap();
+            folded.into_bytes()
+        }
+        Format::Pprof => pprof(),
+    };
+    let mut file = host::fs::File::open(path, false, true, true, false, true)?;
+    file.write_exact(&bytes);
+    Ok(())
+}
+
+/// A profiling run around a function annotated with `#[profile]`.
+///
+/// Sessions do not nest: beginning one discards everything recorded so far.
+pub struct Session {
+    name: &'static str,
+    path: &'static str,
+    start: core::time::Duration,
+}
+
+impl Session {
+    pub fn begin(name: &'static str, path: &'static str) -> Session {
+        reset();
+        begin();
+        Session {
+            name,
+            path,
+            start: crate::kvmclock::time_since_boot(),
+        }
+    }
+
+    /// Stops profiling and exports the samples to the session's file.
+    pub fn finish(self) {
+        let elapsed = crate::kvmclock::time_since_boot() - self.start;
+        end();
+        log::info!("{} took {elapsed:?}; profile in {}", self.name, self.path);
+        if let Err(e) = export(self.path, Format::from_path(self.path)) {
+            log::error!("could not write profile to {}: {e:?}", self.path);
         }
-        f(rip, crate::host::symname(rip));
     }
 }
@@ -0,0 +1,138 @@
+//! Kernel call stacks captured by the sampling timer.
+//!
+//! Each kernel-mode sample walks the interrupted frame-pointer chain (without symbolizing it,
+//! since the tick runs in an ISR) and counts the resulting stack in a fixed-size lock-free table
+//! keyed by a hash of its frames.  Stacks deeper than [`DEPTH`] are truncated at the root.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::prelude::*;
+
+/// The maximum number of frames recorded per sample, including the interrupted RIP.
+pub const DEPTH: usize = 32;
+const SLOTS: usize = 1 << 12;
+
+struct Slot {
+    /// A hash of the stack, or zero if the slot is empty.
+    key: AtomicU64,
+    count: AtomicU64,
+    depth: AtomicUsize,
+    frames: [AtomicU64; DEPTH],
+}
+
+static STACKS: OnceLock<&'static [Slot]> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) unsafe fn init() {
+    STACKS.get_or_init(|| {
+        // all-zero atomics are empty slots
+        let buffer = Box::<[Slot]>::new_zeroed_slice(SLOTS).assume_init();
+        Box::leak(buffer)
+    });
+}
+
+fn hash(frames: &[u64]) -> u64 {
+    // FNV-1a
+    let hash = frames
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    hash.max(1)
+}
+
+/// Records a sample of the kernel stack interrupted at `rip` with frame pointer `rbp`; safe to
+/// call from an ISR.
+///
+/// # Safety
+///
+/// `rbp` must be the interrupted code's frame pointer.
+pub(crate) unsafe fn tick(rip: u64, rbp: u64) {
+    let mut frames = [0; DEPTH];
+    frames[0] = rip;
+    let mut depth = 1;
+    super::frames_from(rbp as *const usize, |rip| {
+        frames[depth] = rip as u64;
+        depth += 1;
+        depth < DEPTH
+    });
+    record(&frames[..depth]);
+}
+
+fn record(frames: &[u64]) {
+    let key = hash(frames);
+    let start = key as usize % SLOTS;
+    for i in 0..SLOTS {
+        let slot = &STACKS[(start + i) % SLOTS];
+        match slot
+            .key
+            .compare_exchange(0, key, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => {
+                for (x, &frame) in slot.frames.iter().zip(frames) {
+                    x.store(frame, Ordering::Relaxed);
+                }
+                slot.depth.store(frames.len(), Ordering::SeqCst);
+            }
+            Err(existing) if existing == key => {}
+            Err(_) => continue,
+        }
+        slot.count.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn reset() {
+    for slot in STACKS.iter() {
+        slot.key.store(0, Ordering::SeqCst);
+        slot.count.store(0, Ordering::SeqCst);
+        slot.depth.store(0, Ordering::SeqCst);
+    }
+    DROPPED.store(0, Ordering::SeqCst);
+}
+
+/// The number of samples that did not fit in the table.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// The recorded stacks (leaf first) and how many times each was sampled.
+pub fn entries() -> Vec<(Vec<u64>, u64)> {
+    let mut entries = Vec::new();
+    for slot in STACKS.iter() {
+        let count = slot.count.load(Ordering::SeqCst);
+        let depth = slot.depth.load(Ordering::SeqCst);
+        if slot.key.load(Ordering::SeqCst) == 0 || count == 0 || depth == 0 {
+            continue;
+        }
+        let frames = slot.frames[..depth]
+            .iter()
+            .map(|x| x.load(Ordering::Relaxed))
+            .collect();
+        entries.push((frames, count));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies identical stacks share a slot and distinct ones do not.
+    #[test]
+    fn test_record() {
+        reset();
+        record(&[0x10, 0x20, 0x30]);
+        record(&[0x10, 0x20, 0x30]);
+        record(&[0x10, 0x20]);
+        let mut entries = entries();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [(vec![0x10, 0x20], 1), (vec![0x10, 0x20, 0x30], 2)]
+        );
+        reset();
+    }
+}
@@ -0,0 +1,205 @@
+//! Attributing user-mode samples to the programs and functions they hit.
+//!
+//! Each Arca is assigned a program the first time it is loaded while profiling; programs are
+//! identified by a hash of the ELF they were loaded from, so every instance of the same ELF is
+//! aggregated together (and stripped ELFs are still told apart).  Arcas not loaded from an ELF
+//! fall back to a hash of their symbol table.  The timer tick records `(program, rip)` into a fixed-size lock-free table, which is
+//! symbolized against the program's symbols when it is reported.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::collections::btree_map::BTreeMap;
+
+use crate::prelude::*;
+
+const SLOTS: usize = 1 << 14;
+const RIP_BITS: u32 = 48;
+const RIP_MASK: u64 = (1 << RIP_BITS) - 1;
+
+struct Slot {
+    /// `(program << RIP_BITS) | rip`, or zero if the slot is empty.
+    key: AtomicU64,
+    count: AtomicU64,
+}
+
+static SAMPLES: [Slot; SLOTS] = [const {
+    Slot {
+        key: AtomicU64::new(0),
+        count: AtomicU64::new(0),
+    }
+}; SLOTS];
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[core_local]
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+struct Program {
+    hash: u64,
+    symbols: Option<Blob>,
+}
+
+/// Registered programs; a program's number is its index plus one (zero means unknown).
+static PROGRAMS: SpinLock<Vec<Program>> = SpinLock::new(Vec::new());
+
+fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Finds (or assigns) the program number for an Arca loaded from the ELF with hash `image`, or
+/// failing that with the given symbol table.
+pub fn register(image: Option<u64>, symbols: Option<&Blob>) -> usize {
+    let hash = image
+        .or_else(|| symbols.map(|x| self::hash(x.inner())))
+        .unwrap_or(0);
+    let mut programs = PROGRAMS.lock();
+    if let Some(i) = programs.iter().position(|x| x.hash == hash) {
+        return i + 1;
+    }
+    programs.push(Program {
+        hash,
+        symbols: symbols.cloned(),
+    });
+    programs.len()
+}
+
+/// Marks `program` as running on this core.
+pub fn enter(program: usize) {
+    CURRENT.store(program, Ordering::Relaxed);
+}
+
+pub fn leave() {
+    CURRENT.store(0, Ordering::Relaxed);
+}
+
+/// Records a sample at `rip` in the program running on this core; safe to call from an ISR.
+pub(crate) fn tick(rip: u64) {
+    record(CURRENT.load(Ordering::Relaxed), rip);
+}
+
+fn record(program: usize, rip: u64) {
+    let key = ((program as u64) << RIP_BITS) | (rip & RIP_MASK);
+    let key = if key == 0 { 1 << RIP_BITS } else { key };
+    let start = (hash(&key.to_le_bytes()) as usize) % SLOTS;
+    for i in 0..SLOTS {
+        let slot = &SAMPLES[(start + i) % SLOTS];
+        match slot
+            .key
+            .compare_exchange(0, key, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => {}
+            Err(existing) if existing == key => {}
+            Err(_) => continue,
+        }
+        slot.count.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn reset() {
+    for slot in SAMPLES.iter() {
+        slot.key.store(0, Ordering::SeqCst);
+        slot.count.store(0, Ordering::SeqCst);
+    }
+    DROPPED.store(0, Ordering::SeqCst);
+}
+
+/// The number of samples that did not fit in the table.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// A symbolized user-mode sample.
+#[derive(Debug)]
+pub struct Sample {
+    /// The program, as `arca-<hash of its ELF>`.
+    pub program: String,
+    pub rip: u64,
+    pub function: Option<String>,
+    pub count: u64,
+}
+
+/// The recorded samples, symbolized against their programs' symbols.
+pub fn samples() -> Vec<Sample> {
+    let programs = PROGRAMS.lock();
+    let mut samples = Vec::new();
+    for slot in SAMPLES.iter() {
+        let key = slot.key.load(Ordering::SeqCst);
+        let count = slot.count.load(Ordering::SeqCst);
+        if key == 0 || count == 0 {
+            continue;
+        }
+        let program = (key >> RIP_BITS) as usize;
+        let rip = key & RIP_MASK;
+        let program = program.checked_sub(1).and_then(|i| programs.get(i));
+        let name = match program {
+            Some(program) => format!("arca-{:016x}", program.hash),
+            None => "arca-unknown".into(),
+        };
+        let symbols = program
+            .and_then(|x| x.symbols.as_ref())
+            .and_then(|x| common::symbols::Symbols::new(x.inner()));
+        let function = symbols.and_then(|x| x.lookup(rip)).map(|(x, _)| x.into());
+        samples.push(Sample {
+            program: name,
+            rip,
+            function,
+            count,
+        });
+    }
+    samples
+}
+
+/// The samples as counts per `program;function` stack.
+pub fn entries() -> BTreeMap<String, u64> {
+    let mut entries = BTreeMap::new();
+    for sample in samples() {
+        let stack = match sample.function {
+            Some(function) => format!("{};{function}", sample.program),
+            None => format!("{};{:#x}", sample.program, sample.rip),
+        };
+        *entries.entry(stack).or_default() += sample.count;
+    }
+    entries
+}
+
+/// Writes the samples in the folded-stack format read by `flamegraph.pl` and `inferno`.
+pub fn write_folded(out: &mut impl core::fmt::Write) -> core::fmt::Result {
+    for (stack, count) in entries() {
+        writeln!(out, "{stack} {count}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies samples are aggregated per program and symbolized against its symbol table.
+    #[test]
+    fn test_folded() {
+        reset();
+        let symbols = Blob::from(&common::symbols::encode([(0x1000, 0x100, "main")])[..]);
+        let program = register(None, Some(&symbols));
+        assert_eq!(register(None, Some(&symbols.clone())), program);
+        // stripped programs are told apart by their ELFs
+        let stripped = register(Some(1), None);
+        assert_ne!(stripped, program);
+        assert_ne!(register(Some(2), None), stripped);
+        assert_eq!(register(Some(1), None), stripped);
+        record(program, 0x1010);
+        record(program, 0x1020);
+        record(program, 0x2000);
+
+        let mut folded = String::new();
+        write_folded(&mut folded).unwrap();
+        let hash = hash(symbols.inner());
+        assert!(folded.contains(&format!("arca-{hash:016x};main 2\n")));
+        assert!(folded.contains(&format!("arca-{hash:016x};0x2000 1\n")));
+        reset();
+    }
+}
@@ -4,11 +4,12 @@ use core::{
     sync::atomic::{AtomicUsize, Ordering},
 };
 
-use alloc::{boxed::Box, collections::vec_deque::VecDeque};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
 use common::util::{initcell::LazyLock, spinlock::SpinLock};
 
 use crate::{interrupts::INTERRUPTED, page::Page2MB};
 
+pub mod executor;
 pub mod kmutex;
 pub use kmutex::{KMutex, KMutexGuard};
 
@@ -20,6 +21,7 @@ pub struct KThread {
     scheduler: bool,
     exited: bool,
     wfi: bool,
+    executor: Option<Arc<executor::Shared>>,
     next: Option<Box<KThread>>,
 }
 
@@ -48,6 +50,7 @@ pub static SCHEDULER_THREAD: LazyLock<RefCell<KThread>> = LazyLock::new(|| {
         scheduler: true,
         exited: false,
         wfi: false,
+        executor: None,
         next: None,
     })
 });
@@ -59,6 +62,11 @@ pub fn tid() -> usize {
     CURRENT_THREAD.borrow().tid
 }
 
+/// Returns the number of kthreads which have been spawned but not yet exited.
+pub fn outstanding() -> usize {
+    OUTSTANDING.load(Ordering::SeqCst)
+}
+
 pub fn scheduler() -> bool {
     CURRENT_THREAD.borrow().scheduler
 }
@@ -67,6 +75,16 @@ pub fn exited() -> bool {
     CURRENT_THREAD.borrow().exited
 }
 
+pub(crate) fn executor() -> Option<Arc<executor::Shared>> {
+    CURRENT_THREAD.borrow().executor.clone()
+}
+
+pub(crate) fn set_executor(
+    executor: Option<Arc<executor::Shared>>,
+) -> Option<Arc<executor::Shared>> {
+    core::mem::replace(&mut CURRENT_THREAD.borrow_mut().executor, executor)
+}
+
 pub fn init() {
     LazyLock::force(&SCHEDULER_THREAD);
     LazyLock::force(&CURRENT_THREAD);
@@ -104,6 +122,7 @@ pub fn spawn(f: impl FnOnce()) {
         scheduler: false,
         exited: false,
         wfi: false,
+        executor: None,
         next: None,
     };
     let mut q = THREAD_QUEUE.lock();
@@ -124,6 +143,7 @@ pub(crate) unsafe fn run_scheduler() {
         core::hint::spin_loop();
     }
     while OUTSTANDING.load(Ordering::SeqCst) != 0 {
+        crate::stats::poll();
         let Some(next) = THREAD_QUEUE.lock().pop_front() else {
             sleep();
             if INTERRUPTED.swap(false, Ordering::SeqCst) {
@@ -0,0 +1,362 @@
+//! A cooperative executor for running futures on a kernel thread.
+//!
+//! [`block_on`] drives a future (and anything it [`spawn`]s) on the calling kthread.  When no task
+//! is runnable, the executor fires expired [`sleep`] timers and re-polls tasks waiting on host
+//! pipes; if it is only waiting on timers it parks the kthread until the next LAPIC tick, and
+//! otherwise yields so the host and other kthreads can make progress.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    task::Wake,
+};
+
+use crate::{kvmclock, prelude::*};
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+pub type JoinHandle<T> = oneshot::Receiver<T>;
+
+pub(crate) struct Shared {
+    main: AtomicBool,
+    /// Every spawned task whose future has not finished, so the executor decides where the future
+    /// is dropped rather than whichever kthread lets go of its last waker.
+    tasks: SpinLock<BTreeMap<u64, Arc<Task>>>,
+    ready: SpinLock<VecDeque<Arc<Task>>>,
+    timers: SpinLock<BTreeMap<(Duration, u64), Waker>>,
+    pollers: SpinLock<Vec<Waker>>,
+}
+
+impl core::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Executor")
+            .field("tasks", &self.tasks.lock().len())
+            .field("ready", &self.ready.lock().len())
+            .field("timers", &self.timers.lock().len())
+            .field("pollers", &self.pollers.lock().len())
+            .finish()
+    }
+}
+
+impl Shared {
+    fn new() -> Self {
+        Shared {
+            main: AtomicBool::new(true),
+            tasks: SpinLock::new(BTreeMap::new()),
+            ready: SpinLock::new(VecDeque::new()),
+            timers: SpinLock::new(BTreeMap::new()),
+            pollers: SpinLock::new(Vec::new()),
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        self.main.load(Ordering::SeqCst) || !self.ready.lock().is_empty()
+    }
+
+    fn run_ready(&self) -> bool {
+        let mut ran = false;
+        loop {
+            let Some(task) = self.ready.lock().pop_front() else {
+                return ran;
+            };
+            task.run();
+            ran = true;
+        }
+    }
+
+    fn fire_timers(&self) {
+        // reading the clock is a hypercall when the host is journaling it
+        if self.timers.lock().is_empty() {
+            return;
+        }
+        let now = kvmclock::monotonic();
+        let expired = {
+            let mut timers = self.timers.lock();
+            let later = timers.split_off(&(now, u64::MAX));
+            core::mem::replace(&mut *timers, later)
+        };
+        for (_, waker) in expired {
+            waker.wake();
+        }
+    }
+
+    fn wake_pollers(&self) -> bool {
+        let pollers = core::mem::take(&mut *self.pollers.lock());
+        let polling = !pollers.is_empty();
+        for waker in pollers {
+            waker.wake();
+        }
+        polling
+    }
+
+    fn clear(&self) {
+        // drop unfinished futures here, on the executor's kthread, before the tasks they wake
+        let tasks = core::mem::take(&mut *self.tasks.lock());
+        for task in tasks.values() {
+            task.future.lock().take();
+        }
+        self.ready.lock().clear();
+        self.timers.lock().clear();
+        self.pollers.lock().clear();
+    }
+}
+
+static NEXT_TASK: AtomicU64 = AtomicU64::new(0);
+
+struct Task {
+    id: u64,
+    future: SpinLock<Option<LocalFuture>>,
+    queued: AtomicBool,
+    executor: Arc<Shared>,
+}
+
+// Safety: the future is the only part of a task which is not `Send` and `Sync`, and it is only
+// touched on the kthread running the task's `block_on`: it is polled by `Task::run`, which only
+// that executor's loop calls, and dropped either there, when it finishes, or by `Shared::clear`
+// before `block_on` returns (the executor keeps every unfinished task in `tasks` until then).  A
+// waker on another kthread can outlive the future, but waking or dropping it only touches the
+// task's flag, the executor's queue, and the emptied slot.  The lock makes the future's accesses
+// exclusive even so.
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Task {
+    fn run(self: &Arc<Self>) {
+        self.queued.store(false, Ordering::SeqCst);
+        let mut slot = self.future.lock();
+        if let Some(future) = slot.as_mut() {
+            let waker = Waker::from(self.clone());
+            let mut cx = Context::from_waker(&waker);
+            if future.as_mut().poll(&mut cx).is_ready() {
+                *slot = None;
+                self.executor.tasks.lock().remove(&self.id);
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            self.executor.ready.lock().push_back(self.clone());
+        }
+    }
+}
+
+struct MainWaker(Arc<Shared>);
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.main.store(true, Ordering::SeqCst);
+    }
+}
+
+fn current() -> Arc<Shared> {
+    super::executor().expect("future polled outside of a kernel executor")
+}
+
+/// Runs a future to completion on the current kthread, along with any tasks it spawns.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let shared = Arc::new(Shared::new());
+    let previous = super::set_executor(Some(shared.clone()));
+    let waker = Waker::from(Arc::new(MainWaker(shared.clone())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = core::pin::pin!(future);
+
+    let result = loop {
+        if shared.main.swap(false, Ordering::SeqCst) {
+            if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                break result;
+            }
+        }
+        if shared.run_ready() || shared.has_work() {
+            continue;
+        }
+        shared.fire_timers();
+        let polling = shared.wake_pollers();
+        if polling {
+            super::yield_now();
+        } else if !shared.has_work() {
+            super::wfi();
+        }
+    };
+
+    shared.clear();
+    super::set_executor(previous);
+    result
+}
+
+/// Spawns a task onto the executor running on the current kthread.
+///
+/// # Panics
+///
+/// Panics if called outside of [`block_on`].
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let executor = current();
+    let task = Arc::new(Task {
+        id: NEXT_TASK.fetch_add(1, Ordering::SeqCst),
+        future: SpinLock::new(Some(Box::pin(async move {
+            tx.send(future.await);
+        }))),
+        queued: AtomicBool::new(false),
+        executor: executor.clone(),
+    });
+    executor.tasks.lock().insert(task.id, task.clone());
+    task.wake_by_ref();
+    rx
+}
+
+static NEXT_TIMER: AtomicU64 = AtomicU64::new(0);
+
+/// A future which completes once a deadline (measured by `kvmclock`) has passed.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Duration,
+    id: Option<u64>,
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: kvmclock::monotonic() + duration,
+        id: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if kvmclock::monotonic() >= this.deadline {
+            return Poll::Ready(());
+        }
+        let id = *this
+            .id
+            .get_or_insert_with(|| NEXT_TIMER.fetch_add(1, Ordering::SeqCst));
+        current()
+            .timers
+            .lock()
+            .insert((this.deadline, id), cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let (Some(id), Some(executor)) = (self.id, super::executor()) {
+            executor.timers.lock().remove(&(self.deadline, id));
+        }
+    }
+}
+
+/// Yields to the other tasks on this executor.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Waits until `f` returns `Some`.
+///
+/// Used for state the host changes without notifying us (e.g. pipe buffers), so `f` is re-checked
+/// every time the executor runs out of other work.
+pub async fn poll_until<T>(mut f: impl FnMut() -> Option<T>) -> T {
+    core::future::poll_fn(|cx| match f() {
+        Some(x) => Poll::Ready(x),
+        None => {
+            current().pollers.lock().push(cx.waker().clone());
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies block_on returns the output of a ready future.
+    #[test]
+    fn test_block_on() {
+        assert_eq!(block_on(async { 1 + 1 }), 2);
+    }
+
+    /// Verifies spawned tasks run and their results can be awaited.
+    #[test]
+    fn test_spawn() {
+        let result = block_on(async {
+            let a = spawn(async { 1 });
+            let b = spawn(async {
+                yield_now().await;
+                2
+            });
+            a.await + b.await
+        });
+        assert_eq!(result, 3);
+    }
+
+    /// Verifies a task still pending when `block_on` returns has its future dropped then, even if
+    /// a waker for it lives on.
+    #[test]
+    fn test_unfinished_task_dropped() {
+        struct Flag(Arc<AtomicBool>);
+        impl Drop for Flag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+        let dropped = Arc::new(AtomicBool::new(false));
+        let stray: Arc<SpinLock<Option<Waker>>> = Arc::default();
+        let flag = Flag(dropped.clone());
+        let waker = stray.clone();
+        block_on(async move {
+            spawn(async move {
+                let _flag = flag;
+                core::future::poll_fn(|cx| {
+                    *waker.lock() = Some(cx.waker().clone());
+                    Poll::<()>::Pending
+                })
+                .await;
+            });
+            yield_now().await;
+        });
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(stray.lock().is_some());
+    }
+
+    /// Verifies sleep waits for at least the requested duration.
+    #[test]
+    fn test_sleep() {
+        let start = kvmclock::time_since_boot();
+        block_on(sleep(Duration::from_millis(20)));
+        assert!(kvmclock::time_since_boot() - start >= Duration::from_millis(20));
+    }
+}
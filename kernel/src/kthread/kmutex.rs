@@ -51,6 +51,10 @@ impl<T: ?Sized> KMutex<T> {
         }
     }
 
+    pub async fn lock_async(&self) -> KMutexGuard<'_, T> {
+        super::executor::poll_until(|| self.try_lock()).await
+    }
+
     pub fn get(&self) -> *mut T {
         self.data.get()
     }
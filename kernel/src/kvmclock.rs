@@ -118,17 +118,46 @@ pub fn time_since_boot() -> Duration {
     info_and_tsc_to_duration(info, tsc)
 }
 
+/// Passes a clock reading through the host's journal if it is recording or replaying, so that a
+/// replayed guest sees exactly the times it saw while being recorded.
+fn journal(reading: Duration) -> Duration {
+    if !crate::host::journaled() {
+        return reading;
+    }
+    let nanos =
+        unsafe { crate::io::hypercall1(common::hypercall::CLOCK, reading.as_nanos() as u64) };
+    Duration::from_nanos(nanos)
+}
+
+/// Returns the time since boot as programs see it.
+///
+/// This is [`time_since_boot`], except that it is recorded and replayed along with the guest's
+/// other nondeterministic inputs; the kernel's own measurements use [`time_since_boot`].
 #[inline]
-pub fn now() -> OffsetDateTime {
+pub fn monotonic() -> Duration {
+    journal(time_since_boot())
+}
+
+#[inline]
+fn wall_clock() -> Duration {
     let boot_time = read_boot_time();
     let boot_time =
         Duration::from_secs(boot_time.sec as u64) + Duration::from_nanos(boot_time.nsec as u64);
-    let current_time = time_since_boot();
-    let now = boot_time + current_time;
-    let sec = now.as_secs();
-    let nsec = now.subsec_nanos();
-    let timestamp = (sec as i128) * 1_000_000_000 + nsec as i128;
-    OffsetDateTime::from_unix_timestamp_nanos(timestamp).unwrap()
+    boot_time + time_since_boot()
+}
+
+/// Returns the current wall-clock time, as recorded or replayed by the host.
+#[inline]
+pub fn now() -> OffsetDateTime {
+    let now = journal(wall_clock());
+    OffsetDateTime::from_unix_timestamp_nanos(now.as_nanos() as i128).unwrap()
+}
+
+/// Returns the wall-clock time according to the KVM clock, without consulting the host.
+#[inline]
+pub fn kvm_now() -> OffsetDateTime {
+    let now = wall_clock();
+    OffsetDateTime::from_unix_timestamp_nanos(now.as_nanos() as i128).unwrap()
 }
 
 #[inline]
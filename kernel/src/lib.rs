@@ -25,6 +25,7 @@ pub use macros::core_local;
 pub mod allocator;
 pub mod cpu;
 pub mod debugcon;
+pub mod debugger;
 pub mod host;
 pub mod io;
 pub mod iprofile;
@@ -33,6 +34,8 @@ pub mod kvmclock;
 pub mod page;
 pub mod paging;
 pub mod prelude;
+pub mod stats;
+pub mod trace;
 pub mod tsc;
 pub mod types;
 pub mod vm;
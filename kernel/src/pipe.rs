@@ -39,6 +39,20 @@ impl HostPipe {
         Ok(())
     }
 
+    pub async fn read_async(&mut self, bytes: &mut [u8]) -> PipeResult<usize> {
+        let inner = &self.inner;
+        kthread::executor::poll_until(|| inner.can_read().then_some(())).await;
+        self.inner.read(bytes)
+    }
+
+    pub async fn read_exact_async(&mut self, mut bytes: &mut [u8]) -> PipeResult<()> {
+        while !bytes.is_empty() {
+            let n = self.read_async(bytes).await?;
+            bytes = &mut bytes[n..];
+        }
+        Ok(())
+    }
+
     pub fn write(&mut self, bytes: &[u8]) -> PipeResult<usize> {
         while !self.inner.can_write() {
             // kthread::wfi();
@@ -64,11 +78,28 @@ impl HostPipe {
         }
         Ok(())
     }
+
+    pub async fn write_async(&mut self, bytes: &[u8]) -> PipeResult<usize> {
+        let inner = &self.inner;
+        kthread::executor::poll_until(|| inner.can_write().then_some(())).await;
+        self.inner.write(bytes)
+    }
+
+    pub async fn write_exact_async(&mut self, mut bytes: &[u8]) -> PipeResult<()> {
+        while !bytes.is_empty() {
+            let n = self.write_async(bytes).await?;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct TypedPipe<S, R> {
     pipe: HostPipe,
+    /// Whether a request is partway through, which outside of one means it was cancelled and the
+    /// pipe is no longer at a message boundary.
+    busy: bool,
     _send: PhantomData<S>,
     _recv: PhantomData<R>,
 }
@@ -77,12 +108,21 @@ impl<S: serde::Serialize, R: for<'a> serde::Deserialize<'a>> TypedPipe<S, R> {
     pub fn new(pipe: HostPipe) -> Self {
         Self {
             pipe,
+            busy: false,
             _send: PhantomData,
             _recv: PhantomData,
         }
     }
 
+    fn begin(&mut self) {
+        assert!(
+            !core::mem::replace(&mut self.busy, true),
+            "pipe desynchronized by a cancelled request"
+        );
+    }
+
     pub fn request(&mut self, request: &S) -> R {
+        self.begin();
         let bytes = postcard::to_allocvec(request).unwrap();
         let length = bytes.len().to_le_bytes();
         self.pipe.write_exact(&length).unwrap();
@@ -92,6 +132,27 @@ impl<S: serde::Serialize, R: for<'a> serde::Deserialize<'a>> TypedPipe<S, R> {
         let length = usize::from_le_bytes(length);
         let mut bytes = vec![0; length];
         self.pipe.read_exact(&mut bytes).unwrap();
+        self.busy = false;
+        postcard::from_bytes(&bytes).unwrap()
+    }
+
+    /// Sends `request` and waits for the response.
+    ///
+    /// This is not cancellation-safe: dropping the future partway leaves a request half-written or
+    /// its response unread, so any later request on this pipe panics rather than reading the
+    /// wrong response.  Run requests that may be abandoned in a task of their own.
+    pub async fn request_async(&mut self, request: &S) -> R {
+        self.begin();
+        let bytes = postcard::to_allocvec(request).unwrap();
+        let length = bytes.len().to_le_bytes();
+        self.pipe.write_exact_async(&length).await.unwrap();
+        self.pipe.write_exact_async(&bytes).await.unwrap();
+        let mut length = [0; 8];
+        self.pipe.read_exact_async(&mut length).await.unwrap();
+        let length = usize::from_le_bytes(length);
+        let mut bytes = vec![0; length];
+        self.pipe.read_exact_async(&mut bytes).await.unwrap();
+        self.busy = false;
         postcard::from_bytes(&bytes).unwrap()
     }
 }
@@ -103,3 +164,4 @@ pub type ListenerPipe =
     TypedPipe<common::protocol::listener::Request, common::protocol::listener::Response>;
 pub type StreamPipe =
     TypedPipe<common::protocol::stream::Request, common::protocol::stream::Response>;
+pub type DebugPipe = TypedPipe<common::protocol::debug::Event, common::protocol::debug::Command>;
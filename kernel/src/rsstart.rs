@@ -101,6 +101,7 @@ unsafe extern "C" fn _start(
         raw.base = vm::pa2ka(0x1_0000_0000);
         common::buddy::import(raw);
         BuddyAllocator.set_caching(false);
+        crate::host::init();
 
         init_cpu_tls();
     } else {
@@ -0,0 +1,72 @@
+//! Counters published to the host with the `STATS` hypercall.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use common::{hypercall, StatsRecord, STATS_SYSCALLS};
+
+use crate::prelude::*;
+
+/// Timer ticks (on core 0) between heartbeats.
+const HEARTBEAT_TICKS: u64 = 100;
+
+static SYSCALLS: [AtomicU64; STATS_SYSCALLS] = [const { AtomicU64::new(0) }; STATS_SYSCALLS];
+static SYSCALLS_OTHER: AtomicU64 = AtomicU64::new(0);
+static FORCED: AtomicU64 = AtomicU64::new(0);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Set by the timer interrupt when a heartbeat is due.
+static DUE: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn count_syscall(num: u64) {
+    match SYSCALLS.get(num as usize) {
+        Some(count) => count.fetch_add(1, Ordering::Relaxed),
+        None => SYSCALLS_OTHER.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+pub(crate) fn count_force() {
+    FORCED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> StatsRecord {
+    let mut record = StatsRecord {
+        uptime_ns: crate::kvmclock::time_since_boot().as_nanos() as u64,
+        threads_outstanding: crate::kthread::outstanding() as u64,
+        arcas_forced: FORCED.load(Ordering::Relaxed),
+        memory_used: BuddyAllocator.used_size() as u64,
+        memory_total: BuddyAllocator.total_size() as u64,
+        syscalls_other: SYSCALLS_OTHER.load(Ordering::Relaxed),
+        ..Default::default()
+    };
+    for (x, count) in record.syscalls.iter_mut().zip(SYSCALLS.iter()) {
+        *x = count.load(Ordering::Relaxed);
+    }
+    record
+}
+
+/// Sends the current counters to the host.
+pub fn publish() {
+    let record = snapshot();
+    let p = vm::ka2pa(&raw const record);
+    unsafe {
+        crate::io::hypercall1(hypercall::STATS, p as u64);
+    }
+}
+
+/// Counts a timer tick, noting when a heartbeat is due.  The record is too big to build on the
+/// interrupt stack, so [`poll`] publishes it later.  Heartbeats are only sent when the host is
+/// monitoring the guest, since each one costs a kthread and a VM exit.
+pub(crate) fn tick() {
+    if crate::coreid() != 0 || !crate::host::monitored() {
+        return;
+    }
+    if TICKS.fetch_add(1, Ordering::Relaxed) % HEARTBEAT_TICKS == 0 {
+        DUE.store(true, Ordering::Release);
+    }
+}
+
+/// Publishes a heartbeat from a new kthread if one is due; called by the scheduler.
+pub(crate) fn poll() {
+    if DUE.swap(false, Ordering::Acquire) {
+        crate::kthread::spawn(publish);
+    }
+}
@@ -0,0 +1,274 @@
+//! An opt-in tracer for the system calls and effects of Arcane functions.
+//!
+//! While tracing is on ([`begin`]), every system call is recorded with its decoded arguments, its
+//! result and how many cycles it took, and every effect performed with
+//! `call_with_current_continuation` is recorded by name.  Events are tagged with the Arca that
+//! caused them; an Arca keeps its number across continuations, so each one reads as a single
+//! timeline.  The buffer is bounded, and [`export`] writes it to a host file in the Chrome
+//! trace-event format (which Perfetto and `chrome://tracing` both read).
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use arcane::SyscallError;
+use common::protocol::control::ErrorKind;
+
+use crate::prelude::*;
+
+/// The most events kept; later ones are counted and dropped.
+const MAX_EVENTS: usize = 1 << 16;
+
+static TRACING: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static EVENTS: SpinLock<Vec<Event>> = SpinLock::new(Vec::new());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// The Arca's trace number (see [`next_id`]).
+    pub arca: u64,
+    pub core: u32,
+    /// When the event started, in cycles since boot.
+    pub start: u64,
+    pub cycles: u64,
+    pub kind: Kind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Syscall {
+        num: u64,
+        args: [u64; 6],
+        /// `None` if the call did not return to the Arca (it exited or performed an effect).
+        result: Option<Result<usize, SyscallError>>,
+    },
+    Effect {
+        name: String,
+    },
+}
+
+pub fn begin() {
+    TRACING.store(true, Ordering::SeqCst);
+}
+
+pub fn end() {
+    TRACING.store(false, Ordering::SeqCst);
+}
+
+pub fn tracing() -> bool {
+    TRACING.load(Ordering::Relaxed)
+}
+
+pub fn reset() {
+    EVENTS.lock().clear();
+    DROPPED.store(0, Ordering::SeqCst);
+}
+
+/// The number of events that did not fit in the buffer.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// A fresh trace number for an Arca.
+pub fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn record(event: Event) {
+    let mut events = EVENTS.lock();
+    if events.len() < MAX_EVENTS {
+        events.push(event);
+    } else {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records a system call which started at `start` (in cycles) and has just finished.
+pub fn syscall(
+    arca: u64,
+    num: u64,
+    args: [u64; 6],
+    result: Option<Result<usize, SyscallError>>,
+    start: u64,
+) {
+    record(Event {
+        arca,
+        core: crate::coreid(),
+        start,
+        cycles: crate::tsc::read_cycles().saturating_sub(start),
+        kind: Kind::Syscall { num, args, result },
+    });
+}
+
+/// Records an effect performed by `arca`.
+pub fn effect(arca: u64, name: String) {
+    record(Event {
+        arca,
+        core: crate::coreid(),
+        start: crate::tsc::read_cycles(),
+        cycles: 0,
+        kind: Kind::Effect { name },
+    });
+}
+
+pub fn events() -> Vec<Event> {
+    EVENTS.lock().clone()
+}
+
+/// The name and argument names of a system call.
+pub fn signature(num: u64) -> Option<(&'static str, &'static [&'static str])> {
+    let signature: (&str, &[&str]) = match num as u32 {
+        arcane::__NR_nop => ("nop", &[]),
+        arcane::__NR_drop => ("drop", &["descriptor"]),
+        arcane::__NR_clone => ("clone", &["descriptor"]),
+        arcane::__NR_exit => ("exit", &["descriptor"]),
+        arcane::__NR_get_argument => ("get_argument", &[]),
+        arcane::__NR_length => ("length", &["descriptor", "ptr"]),
+        arcane::__NR_get => ("get", &["descriptor", "index", "ptr"]),
+        arcane::__NR_set => ("set", &["descriptor", "index", "value", "ptr"]),
+        arcane::__NR_read => ("read", &["descriptor", "offset", "ptr", "len"]),
+        arcane::__NR_write => ("write", &["descriptor", "offset", "ptr", "len"]),
+        arcane::__NR_type => ("type", &["descriptor"]),
+        arcane::__NR_create_word => ("create_word", &["value"]),
+        arcane::__NR_create_blob => ("create_blob", &["ptr", "len"]),
+        arcane::__NR_create_blob_from_page => ("create_blob_from_page", &["ptr", "len", "flags"]),
+        arcane::__NR_create_tree => ("create_tree", &["len"]),
+        arcane::__NR_create_page => ("create_page", &["len"]),
+        arcane::__NR_create_table => ("create_table", &["len"]),
+        arcane::__NR_create_function => ("create_function", &["data"]),
+        arcane::__NR_slice => ("slice", &["descriptor", "start", "end"]),
+        arcane::__NR_concat => ("concat", &["first", "second"]),
+        arcane::__NR_resize => ("resize", &["descriptor", "len"]),
+        arcane::__NR_get_many => ("get_many", &["tuple", "start", "ptr", "len"]),
+        arcane::__NR_set_many => ("set_many", &["tuple", "start", "ptr", "len"]),
+        arcane::__NR_restrict => ("restrict", &["descriptor", "rights"]),
+        arcane::__NR_batch => ("batch", &["ptr", "len"]),
+        arcane::__NR_apply => ("apply", &["function", "argument"]),
+        arcane::__NR_map => ("map", &["table", "address", "ptr"]),
+        arcane::__NR_mmap => ("mmap", &["address", "ptr"]),
+        arcane::__NR_mprotect => ("mprotect", &["address", "mode"]),
+        arcane::__NR_compat_mmap => ("compat_mmap", &["address", "len", "mode"]),
+        arcane::__NR_call_with_current_continuation => {
+            ("call_with_current_continuation", &["function"])
+        }
+        arcane::__NR_get_continuation => ("get_continuation", &[]),
+        arcane::__NR_debug_show => ("debug_show", &["ptr", "len", "descriptor"]),
+        arcane::__NR_debug_log => ("debug_log", &["ptr", "len"]),
+        arcane::__NR_debug_log_int => ("debug_log_int", &["ptr", "len", "value"]),
+        _ => return None,
+    };
+    Some(signature)
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> core::fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Writes cycles as microseconds, the unit of trace-event timestamps.
+fn write_micros(out: &mut impl Write, cycles: u64) -> core::fmt::Result {
+    let ns = crate::tsc::cycles_to_duration(cycles).as_nanos();
+    write!(out, "{}.{:03}", ns / 1000, ns % 1000)
+}
+
+fn write_event(out: &mut impl Write, event: &Event) -> core::fmt::Result {
+    write!(out, "{{\"pid\":0,\"tid\":{},\"ts\":", event.arca)?;
+    write_micros(out, event.start)?;
+    let args = match &event.kind {
+        Kind::Syscall { num, args, result } => {
+            let (name, names) = signature(*num).unwrap_or(("unknown", &[]));
+            out.write_str(",\"ph\":\"X\",\"cat\":\"syscall\",\"dur\":")?;
+            write_micros(out, event.cycles)?;
+            write!(out, ",\"name\":\"{name}\"")?;
+            Some((num, names, args, result))
+        }
+        Kind::Effect { name } => {
+            out.write_str(",\"ph\":\"i\",\"s\":\"t\",\"cat\":\"effect\",\"name\":")?;
+            write_string(out, name)?;
+            None
+        }
+    };
+    write!(
+        out,
+        ",\"args\":{{\"core\":{},\"cycles\":{}",
+        event.core, event.cycles
+    )?;
+    if let Some((num, names, args, result)) = args {
+        write!(out, ",\"num\":{num}")?;
+        for (name, value) in names.iter().zip(args) {
+            write!(out, ",\"{name}\":{value}")?;
+        }
+        match result {
+            Some(Ok(x)) => write!(out, ",\"result\":{x}")?,
+            Some(Err(e)) => write!(out, ",\"error\":\"{e:?}\"")?,
+            None => {}
+        }
+    }
+    out.write_str("}}")
+}
+
+/// Writes the recorded events as a Chrome trace-event JSON object.
+pub fn write_json(out: &mut impl Write) -> core::fmt::Result {
+    out.write_str("{\"traceEvents\":[")?;
+    for (i, event) in EVENTS.lock().iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        write_event(out, event)?;
+    }
+    write!(out, "],\"dropped\":{}}}", dropped())
+}
+
+/// Writes the recorded events to a file on the host.
+pub fn export(path: &str) -> Result<(), ErrorKind> {
+    let mut json = String::new();
+    write_json(&mut json).unwrap();
+    let mut file = crate::host::fs::File::open(path, false, true, true, false, true)?;
+    file.write_exact(json.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_event() {
+        let event = Event {
+            arca: 3,
+            core: 1,
+            start: 0,
+            cycles: 0,
+            kind: Kind::Syscall {
+                num: arcane::__NR_get as u64,
+                args: [4, 5, 6, 0, 0, 0],
+                result: Some(Err(SyscallError::BadIndex)),
+            },
+        };
+        let mut json = String::new();
+        write_event(&mut json, &event).unwrap();
+        assert!(json.starts_with("{\"pid\":0,\"tid\":3,\"ts\":0.000,\"ph\":\"X\""));
+        assert!(json.contains("\"name\":\"get\""));
+        assert!(json.contains("\"descriptor\":4,\"index\":5,\"ptr\":6,\"error\":\"BadIndex\""));
+
+        let event = Event {
+            kind: Kind::Effect {
+                name: "say \"hi\"".into(),
+            },
+            ..event
+        };
+        let mut json = String::new();
+        write_event(&mut json, &event).unwrap();
+        assert!(json.contains("\"name\":\"say \\\"hi\\\"\""));
+    }
+}
@@ -37,3 +37,4 @@ pub type ValueRef<'a> = ::arca::ValueRef<'a, Runtime>;
 pub type Entry = ::arca::Entry<Runtime>;
 
 pub use arca::{Arca, LoadedArca};
+pub use function::backtrace;
@@ -1,18 +1,42 @@
+use core::{mem::MaybeUninit, ops::Range};
+
 use alloc::{collections::vec_deque::VecDeque, vec::Vec};
 use arcane::SyscallError;
 
 use crate::{cpu::ExitReason, prelude::*};
 
-use super::Value;
+use super::{function::linux, Value};
 
 use crate::types::internal;
+use common::util::rangemap::RangeMap;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Arca {
     page_table: Table,
     register_file: Box<RegisterFile>,
     descriptors: Descriptors,
+    /// The rights (see [`arcane::rights`]) of the descriptors `get_argument` will make for the
+    /// function's arguments, in order; arguments past the end get them all.
+    argument_rights: VecDeque<u64>,
     fsbase: u64,
+    symbols: Option<Blob>,
+    /// A hash of the ELF the Arca was loaded from, if any, which identifies its program to the
+    /// profiler.
+    image: Option<u64>,
+    /// See [`arcane::flags`].
+    flags: u64,
+    /// Address ranges (e.g. `.bss`) left unmapped, whose pages are filled with fresh zeroed pages
+    /// when they are first touched.
+    zero_fill: Vec<Range<usize>>,
+    /// The rights of the ranges `mmap` mapped with fewer than all of them; an entry it displaces
+    /// goes back with the rights of the ranges it overlapped.
+    mapping_rights: RangeMap<u64>,
+    /// See [`linux::State`]; only used with [`arcane::flags::LINUX`].
+    linux: linux::State,
+    /// The profiler's number for this Arca's program, once it has been loaded while profiling.
+    program: Option<usize>,
+    /// The tracer's number for this Arca, once it has made a system call while tracing.
+    trace: Option<u64>,
     // rlimit: Resources,
 }
 
@@ -32,7 +56,16 @@ impl Arca {
             page_table,
             register_file,
             descriptors,
+            argument_rights: VecDeque::new(),
             fsbase: 0,
+            symbols: None,
+            image: None,
+            flags: 0,
+            zero_fill: Vec::new(),
+            mapping_rights: RangeMap::new(),
+            linux: Default::default(),
+            program: None,
+            trace: None,
             // rlimit,
         }
     }
@@ -52,12 +85,21 @@ impl Arca {
             page_table,
             register_file: register_file.into(),
             descriptors,
+            argument_rights: VecDeque::new(),
             fsbase: 0,
+            symbols: None,
+            image: None,
+            flags: 0,
+            zero_fill: Vec::new(),
+            mapping_rights: RangeMap::new(),
+            linux: Default::default(),
+            program: None,
+            trace: None,
             // rlimit,
         }
     }
 
-    pub fn load(self, cpu: &mut Cpu) -> LoadedArca<'_> {
+    pub fn load(mut self, cpu: &mut Cpu) -> LoadedArca<'_> {
         // let memory = ValueRef::Table(&self.page_table).byte_size()
         //     + self
         //         .descriptors
@@ -74,9 +116,25 @@ impl Arca {
         }
         // let rusage = Resources { memory };
         // assert!(rusage.memory <= self.rlimit.memory);
+        if crate::iprofile::profiling() && self.program.is_none() {
+            self.program = Some(crate::iprofile::user::register(
+                self.image,
+                self.symbols.as_ref(),
+            ));
+        }
+        crate::iprofile::user::enter(self.program.unwrap_or(0));
         LoadedArca {
             register_file: self.register_file,
             descriptors: self.descriptors,
+            argument_rights: self.argument_rights,
+            symbols: self.symbols,
+            image: self.image,
+            flags: self.flags,
+            zero_fill: self.zero_fill,
+            mapping_rights: self.mapping_rights,
+            linux: self.linux,
+            program: self.program,
+            trace: self.trace,
             cpu,
             // rlimit: self.rlimit,
             // rusage,
@@ -107,11 +165,85 @@ impl Arca {
         &mut self.descriptors
     }
 
-    pub fn read(self) -> (RegisterFile, Table, Tuple) {
+    /// The program's symbol table (see `common::symbols`), if it was loaded with one.
+    pub fn symbols(&self) -> Option<&Blob> {
+        self.symbols.as_ref()
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Blob>) {
+        self.symbols = symbols;
+    }
+
+    /// A hash of the ELF the Arca was loaded from, if it was loaded from one.
+    pub fn image(&self) -> Option<u64> {
+        self.image
+    }
+
+    pub fn set_image(&mut self, image: Option<u64>) {
+        self.image = image;
+    }
+
+    pub fn flags(&self) -> u64 {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: u64) {
+        self.flags = flags;
+    }
+
+    /// The FS segment base, i.e. the thread pointer.
+    pub fn fsbase(&self) -> u64 {
+        self.fsbase
+    }
+
+    pub fn set_fsbase(&mut self, fsbase: u64) {
+        self.fsbase = fsbase;
+    }
+
+    pub fn zero_fill(&self) -> &[Range<usize>] {
+        &self.zero_fill
+    }
+
+    pub fn set_zero_fill(&mut self, zero_fill: Vec<Range<usize>>) {
+        self.zero_fill = zero_fill;
+    }
+
+    /// The rights of the ranges mapped with fewer than all of them.
+    pub fn mapping_rights(&self) -> &RangeMap<u64> {
+        &self.mapping_rights
+    }
+
+    pub fn set_mapping_rights(&mut self, mapping_rights: RangeMap<u64>) {
+        self.mapping_rights = mapping_rights;
+    }
+
+    pub fn linux(&self) -> &linux::State {
+        &self.linux
+    }
+
+    pub fn set_linux(&mut self, linux: linux::State) {
+        self.linux = linux;
+    }
+
+    /// The rights of the descriptors `get_argument` will make for the function's arguments.
+    pub fn argument_rights(&self) -> &VecDeque<u64> {
+        &self.argument_rights
+    }
+
+    /// Takes away the rights of argument `index`'s descriptor which are not in `rights`.
+    pub fn restrict_argument(&mut self, index: usize, rights: u64) {
+        if self.argument_rights.len() <= index {
+            self.argument_rights.resize(index + 1, arcane::rights::ALL);
+        }
+        self.argument_rights[index] &= rights;
+    }
+
+    pub fn read(self) -> (RegisterFile, Table, Tuple, Option<Blob>) {
         (
             *self.register_file,
             self.page_table,
             Tuple::from_inner(internal::Tuple::new(Vec::from(self.descriptors))),
+            self.symbols,
         )
     }
 
@@ -134,6 +266,15 @@ impl Default for Arca {
 pub struct LoadedArca<'a> {
     register_file: Box<RegisterFile>,
     descriptors: Descriptors,
+    argument_rights: VecDeque<u64>,
+    symbols: Option<Blob>,
+    image: Option<u64>,
+    flags: u64,
+    zero_fill: Vec<Range<usize>>,
+    mapping_rights: RangeMap<u64>,
+    linux: linux::State,
+    program: Option<usize>,
+    trace: Option<u64>,
     cpu: &'a mut Cpu,
     // rlimit: Resources,
     // rusage: Resources,
@@ -184,6 +325,11 @@ impl<'a> LoadedArca<'a> {
         &self.descriptors
     }
 
+    /// This Arca's number in the tracer, assigning one if it does not have one yet.
+    pub fn trace_id(&mut self) -> u64 {
+        *self.trace.get_or_insert_with(crate::trace::next_id)
+    }
+
     pub fn descriptors_mut(&'_ mut self) -> DescriptorsProxy<'_, 'a> {
         DescriptorsProxy { arca: self }
     }
@@ -215,6 +361,7 @@ impl<'a> LoadedArca<'a> {
     }
 
     pub fn unload_with_cpu(self) -> (Arca, &'a mut Cpu) {
+        crate::iprofile::user::leave();
         let page_table = Table::from_inner(self.cpu.deactivate_address_space());
         let mut fsbase: u64;
         unsafe {
@@ -227,8 +374,17 @@ impl<'a> LoadedArca<'a> {
             Arca {
                 register_file: self.register_file,
                 descriptors: self.descriptors,
+                argument_rights: self.argument_rights,
                 page_table,
                 fsbase,
+                symbols: self.symbols,
+                image: self.image,
+                flags: self.flags,
+                zero_fill: self.zero_fill,
+                mapping_rights: self.mapping_rights,
+                linux: self.linux,
+                program: self.program,
+                trace: self.trace,
                 // rlimit: self.rlimit,
             },
             self.cpu,
@@ -238,6 +394,16 @@ impl<'a> LoadedArca<'a> {
     pub fn swap(&mut self, other: &mut Arca) {
         core::mem::swap(&mut self.register_file, &mut other.register_file);
         core::mem::swap(&mut self.descriptors, &mut other.descriptors);
+        core::mem::swap(&mut self.argument_rights, &mut other.argument_rights);
+        core::mem::swap(&mut self.symbols, &mut other.symbols);
+        core::mem::swap(&mut self.image, &mut other.image);
+        core::mem::swap(&mut self.flags, &mut other.flags);
+        core::mem::swap(&mut self.zero_fill, &mut other.zero_fill);
+        core::mem::swap(&mut self.mapping_rights, &mut other.mapping_rights);
+        core::mem::swap(&mut self.linux, &mut other.linux);
+        core::mem::swap(&mut self.program, &mut other.program);
+        core::mem::swap(&mut self.trace, &mut other.trace);
+        crate::iprofile::user::enter(self.program.unwrap_or(0));
         // core::mem::swap(&mut self.rlimit, &mut other.rlimit);
         let mut fsbase: u64;
         unsafe {
@@ -250,88 +416,350 @@ impl<'a> LoadedArca<'a> {
     pub fn cpu(&'_ mut self) -> CpuProxy<'_, 'a> {
         CpuProxy { arca: self }
     }
+
+    pub fn symbols(&self) -> Option<&Blob> {
+        self.symbols.as_ref()
+    }
+
+    pub fn flags(&self) -> u64 {
+        self.flags
+    }
+
+    /// The rights of the descriptor to make for the next argument, which is being taken.
+    pub fn take_argument_rights(&mut self) -> u64 {
+        self.argument_rights
+            .pop_front()
+            .unwrap_or(arcane::rights::ALL)
+    }
+
+    /// Whether `address` lies in one of the Arca's zero-fill regions.
+    pub fn is_zero_fill(&self, address: usize) -> bool {
+        self.zero_fill.iter().any(|x| x.contains(&address))
+    }
+
+    /// Adds `region` to the Arca's zero-fill regions.
+    pub fn add_zero_fill(&mut self, region: Range<usize>) {
+        match self.zero_fill.iter_mut().find(|x| x.end == region.start) {
+            Some(x) => x.end = region.end,
+            None => self.zero_fill.push(region),
+        }
+    }
+
+    /// Removes `region` from the Arca's zero-fill regions, splitting any it lies inside.
+    pub fn remove_zero_fill(&mut self, region: Range<usize>) {
+        let mut kept = Vec::with_capacity(self.zero_fill.len() + 1);
+        for x in self.zero_fill.drain(..) {
+            if x.end <= region.start || region.end <= x.start {
+                kept.push(x);
+                continue;
+            }
+            if x.start < region.start {
+                kept.push(x.start..region.start);
+            }
+            if region.end < x.end {
+                kept.push(region.end..x.end);
+            }
+        }
+        self.zero_fill = kept;
+    }
+
+    /// The rights granted by all the ranges mapped in `range`.
+    pub fn mapping_rights(&self, range: Range<usize>) -> u64 {
+        rights_in(&self.mapping_rights, range)
+    }
+
+    /// Forgets the rights of `range`, which is being displaced, returning the rights the ranges
+    /// it overlapped granted between them.  Ranges straddling its ends keep the rest of theirs.
+    pub fn take_mapping_rights(&mut self, range: Range<usize>) -> u64 {
+        let rights = self.mapping_rights(range.clone());
+        self.mapping_rights.remove(range);
+        rights
+    }
+
+    /// Records the rights of the entry just mapped over `range`.
+    pub fn record_mapping_rights(&mut self, range: Range<usize>, rights: u64) {
+        if rights != arcane::rights::ALL {
+            self.mapping_rights.insert(range, rights);
+        }
+    }
+
+    pub fn linux_mut(&mut self) -> &mut linux::State {
+        &mut self.linux
+    }
+
+    /// Backs the page containing `address` with a fresh zeroed page.
+    pub fn fill_zero_page(&mut self, address: usize) {
+        let page = Page::new(Page4KB::SIZE);
+        self.cpu()
+            .map(address & !(Page4KB::SIZE - 1), Entry::RWPage(page))
+            .expect("could not map zero-fill page");
+    }
+
+    /// Backs the untouched zero-fill pages among the `len` bytes at `address` with zeroed pages.
+    ///
+    /// Only a user-mode fault fills a zero-fill page by itself, so this must be called before the
+    /// kernel copies to or from user memory which may not have been touched yet.
+    pub fn fault_in(&mut self, address: usize, len: usize) {
+        let end = address.saturating_add(len);
+        let regions: Vec<Range<usize>> = self
+            .zero_fill
+            .iter()
+            .map(|x| x.start.max(address)..x.end.min(end))
+            .filter(|x| !x.is_empty())
+            .collect();
+        for region in regions {
+            let mut page = region.start & !(Page4KB::SIZE - 1);
+            while page < region.end {
+                let mut probe = [MaybeUninit::uninit()];
+                if crate::vm::copy_user_to_kernel(&mut probe, page).is_none() {
+                    self.fill_zero_page(page);
+                }
+                page += Page4KB::SIZE;
+            }
+        }
+    }
+
+    /// Runs `f` on the page table of this Arca, which is unloaded while `f` runs.
+    pub fn with_mappings<T>(&mut self, f: impl FnOnce(&mut Table) -> T) -> T {
+        let mut unloaded = self.take();
+        let result = f(unloaded.mappings_mut());
+        self.swap(&mut unloaded);
+        result
+    }
+
+    /// Walks the user frame-pointer chain, returning the current instruction pointer followed by
+    /// up to `limit - 1` return addresses.
+    pub fn backtrace(&mut self, limit: usize) -> Vec<u64> {
+        let rip = self.register_file[Register::RIP];
+        let rbp = self.register_file[Register::RBP];
+        self.with_mappings(|table| backtrace(table, rip, rbp, limit))
+    }
+}
+
+/// Walks a frame-pointer chain through `table`, starting from the frame at `rbp`.
+///
+/// The walk stops at a null or unmapped frame, or at one that does not move up the stack.
+pub fn backtrace(table: &Table, rip: u64, mut rbp: u64, limit: usize) -> Vec<u64> {
+    let mut frames = vec![rip];
+    while frames.len() < limit && rbp != 0 {
+        let mut frame = [0; 16];
+        if !matches!(table.read_bytes(rbp as usize, &mut frame), Ok(16)) {
+            break;
+        }
+        let next = u64::from_le_bytes(frame[..8].try_into().unwrap());
+        let ret = u64::from_le_bytes(frame[8..].try_into().unwrap());
+        if ret == 0 {
+            break;
+        }
+        frames.push(ret);
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+    frames
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DescriptorError {
     AttemptToMutateNull,
     OutOfBounds,
+    /// The descriptor does not grant a right (see [`arcane::rights`]) the operation needs.
+    PermissionDenied,
+}
+
+/// A value held by a descriptor, along with the rights the descriptor grants over it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Slot {
+    value: Value,
+    rights: u64,
+    /// The rights of the elements (by index in a tuple, or by address in a table) which were put
+    /// into the value by descriptors granting fewer than all of them.
+    elements: RangeMap<u64>,
+}
+
+impl Slot {
+    fn new(value: Value, rights: u64) -> Slot {
+        Slot {
+            value,
+            rights,
+            elements: RangeMap::new(),
+        }
+    }
 }
 
+/// The rights granted between them by the ranges of `rights` overlapping `range`.
+fn rights_in(rights: &RangeMap<u64>, range: Range<usize>) -> u64 {
+    rights
+        .overlapping(range)
+        .fold(arcane::rights::ALL, |rights, (_, x)| rights & x)
+}
+
+/// An Arca's descriptor table.  Descriptor 0 is always null; any other empty descriptor reads as
+/// null too, but cannot be mutated.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Descriptors {
-    descriptors: Vec<Value>,
+    table: common::util::descriptors::Descriptors<Slot>,
+    null: Value,
 }
 
 pub type Result<T> = core::result::Result<T, DescriptorError>;
 
 impl Descriptors {
     pub fn new() -> Descriptors {
+        let mut table = common::util::descriptors::Descriptors::new();
+        table.insert(Slot::new(Value::default(), arcane::rights::ALL));
         Descriptors {
-            descriptors: vec![Value::default()],
+            table,
+            null: Value::default(),
+        }
+    }
+
+    fn check(&self, index: usize) -> Result<()> {
+        if index < self.table.len() {
+            Ok(())
+        } else {
+            Err(DescriptorError::OutOfBounds)
         }
     }
 
     pub fn get(&self, index: usize) -> Result<&Value> {
-        self.descriptors
-            .get(index)
-            .ok_or(DescriptorError::OutOfBounds)
+        self.check(index)?;
+        Ok(self.table.get(index).map_or(&self.null, |x| &x.value))
     }
 
     pub fn get_mut(&mut self, index: usize) -> Result<&mut Value> {
         if index == 0 {
             return Err(DescriptorError::AttemptToMutateNull);
         }
-        self.descriptors
+        self.check(index)?;
+        self.table
             .get_mut(index)
-            .ok_or(DescriptorError::OutOfBounds)
+            .map(|x| &mut x.value)
+            .ok_or(DescriptorError::AttemptToMutateNull)
     }
 
     pub fn take(&mut self, index: usize) -> Result<Value> {
         if index == 0 {
             return Ok(Value::default());
         }
-        Ok(core::mem::take(
-            self.descriptors
-                .get_mut(index)
-                .ok_or(DescriptorError::OutOfBounds)?,
-        ))
+        self.check(index)?;
+        Ok(self
+            .table
+            .remove(index)
+            .map(|x| x.value)
+            .unwrap_or_default())
     }
 
     pub fn insert(&mut self, value: Value) -> usize {
+        self.insert_with_rights(value, arcane::rights::ALL)
+    }
+
+    /// Inserts `value` under a descriptor granting only `rights`.
+    pub fn insert_with_rights(&mut self, value: Value, rights: u64) -> usize {
         if value.datatype() == DataType::Null {
             return 0;
         }
-        for (i, x) in self.descriptors.iter_mut().enumerate().skip(1) {
-            if x.datatype() == DataType::Null {
-                *x = value;
-                return i;
+        self.table.insert(Slot::new(value, rights))
+    }
+
+    /// Makes another descriptor of the value descriptor `index` holds, granting the same rights
+    /// over it and its elements.
+    pub fn duplicate(&mut self, index: usize) -> Result<usize> {
+        self.check(index)?;
+        match self.table.get(index) {
+            Some(slot) if index != 0 => {
+                let slot = slot.clone();
+                Ok(self.table.insert(slot))
             }
+            _ => Ok(0),
         }
-        let i = self.descriptors.len();
-        self.descriptors.push(value);
-        i
     }
 
-    pub fn iter(&'_ self) -> Iter<'_> {
-        Iter { i: 0, d: self }
+    /// The rights descriptor `index` grants; an empty descriptor grants them all.
+    pub fn rights(&self, index: usize) -> Result<u64> {
+        self.check(index)?;
+        Ok(self
+            .table
+            .get(index)
+            .map_or(arcane::rights::ALL, |x| x.rights))
     }
-}
 
-pub struct Iter<'a> {
-    i: usize,
-    d: &'a Descriptors,
-}
+    /// The rights descriptor `index` grants over its value wherever the value goes next: its own,
+    /// less any which an element was put into the value without.
+    pub fn carried_rights(&self, index: usize) -> Result<u64> {
+        self.check(index)?;
+        Ok(self.table.get(index).map_or(arcane::rights::ALL, |x| {
+            x.rights & rights_in(&x.elements, 0..usize::MAX)
+        }))
+    }
 
-impl<'a> Iterator for Iter<'a> {
-    type Item = &'a Value;
+    /// The rights descriptor `index` grants over the elements of its value in `range` (indices of
+    /// a tuple, or addresses in a table): its own, less any those elements were put in without.
+    pub fn element_rights(&self, index: usize, range: Range<usize>) -> Result<u64> {
+        self.check(index)?;
+        Ok(self.table.get(index).map_or(arcane::rights::ALL, |x| {
+            x.rights & rights_in(&x.elements, range)
+        }))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.i >= self.d.descriptors.len() {
-            return None;
+    /// Records that the elements of descriptor `index`'s value in `range` were put there by a
+    /// descriptor granting only `rights`.
+    pub fn set_element_rights(
+        &mut self,
+        index: usize,
+        range: Range<usize>,
+        rights: u64,
+    ) -> Result<()> {
+        self.check(index)?;
+        if let Some(slot) = self.table.get_mut(index).filter(|_| index != 0) {
+            if rights == arcane::rights::ALL {
+                slot.elements.remove(range);
+            } else {
+                slot.elements.insert(range, rights);
+            }
         }
-        self.i += 1;
-        Some(self.d.get(self.i - 1).unwrap())
+        Ok(())
+    }
+
+    /// Fails unless descriptor `index` grants all of `rights`.
+    pub fn require(&self, index: usize, rights: u64) -> Result<()> {
+        if self.rights(index)? & rights == rights {
+            Ok(())
+        } else {
+            Err(DescriptorError::PermissionDenied)
+        }
+    }
+
+    /// Takes away the rights of descriptor `index` which are not in `rights`.
+    pub fn restrict(&mut self, index: usize, rights: u64) -> Result<()> {
+        self.check(index)?;
+        if let Some(slot) = self.table.get_mut(index).filter(|_| index != 0) {
+            slot.rights &= rights;
+        }
+        Ok(())
+    }
+
+    /// Takes away the rights of every descriptor which are not in `rights`.
+    pub fn restrict_all(&mut self, rights: u64) {
+        for i in 1..self.table.len() {
+            if let Some(slot) = self.table.get_mut(i) {
+                slot.rights &= rights;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.table.iter().map(|(_, x)| &x.value)
+    }
+
+    /// The rights every descriptor carries (see [`carried_rights`](Self::carried_rights)), in the
+    /// same order as the values in `Vec::from(self)`.  A function's data has nowhere to keep the
+    /// rights of elements, so they narrow those of their containers instead.
+    pub fn all_rights(&self) -> Vec<u64> {
+        (0..self.table.len())
+            .map(|i| self.carried_rights(i).unwrap())
+            .collect()
     }
 }
 
@@ -342,19 +770,28 @@ impl Default for Descriptors {
 }
 
 impl From<Vec<Value>> for Descriptors {
-    fn from(mut value: Vec<Value>) -> Self {
-        if value.is_empty() {
-            value.push(Default::default());
-        } else {
-            value[0] = Default::default();
+    fn from(value: Vec<Value>) -> Self {
+        let mut descriptors = Descriptors::new();
+        for (i, value) in value.into_iter().enumerate().skip(1) {
+            if value.datatype() != DataType::Null {
+                descriptors
+                    .table
+                    .set(i, Slot::new(value, arcane::rights::ALL));
+            }
         }
-        Descriptors { descriptors: value }
+        descriptors
     }
 }
 
 impl From<Descriptors> for Vec<Value> {
-    fn from(value: Descriptors) -> Self {
-        value.descriptors
+    fn from(mut value: Descriptors) -> Self {
+        let mut values = vec![Value::default(); value.table.len()];
+        for i in 1..values.len() {
+            if let Some(slot) = value.table.remove(i) {
+                values[i] = slot.value;
+            }
+        }
+        values
     }
 }
 
@@ -395,6 +832,33 @@ impl<'a> DescriptorsProxy<'a, '_> {
     pub fn get_mut(self, index: usize) -> core::result::Result<&'a mut Value, DescriptorError> {
         self.arca.descriptors.get_mut(index)
     }
+
+    pub fn insert_with_rights(
+        self,
+        value: Value,
+        rights: u64,
+    ) -> core::result::Result<usize, SyscallError> {
+        Ok(self.arca.descriptors.insert_with_rights(value, rights))
+    }
+
+    pub fn duplicate(self, index: usize) -> core::result::Result<usize, SyscallError> {
+        Ok(self.arca.descriptors.duplicate(index)?)
+    }
+
+    pub fn restrict(self, index: usize, rights: u64) -> core::result::Result<(), DescriptorError> {
+        self.arca.descriptors.restrict(index, rights)
+    }
+
+    pub fn set_element_rights(
+        self,
+        index: usize,
+        range: Range<usize>,
+        rights: u64,
+    ) -> core::result::Result<(), DescriptorError> {
+        self.arca
+            .descriptors
+            .set_element_rights(index, range, rights)
+    }
 }
 
 pub struct CpuProxy<'a, 'cpu> {
@@ -423,3 +887,68 @@ impl<'a> CpuProxy<'a, '_> {
         Ok(old)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies freed descriptors are reused and that descriptor 0 stays null.
+    #[test]
+    fn test_descriptor_reuse() {
+        let mut d = Descriptors::new();
+        let a = d.insert(Value::Word(Word::new(1)));
+        let b = d.insert(Value::Word(Word::new(2)));
+        assert_eq!((a, b), (1, 2));
+        assert_eq!(d.take(a), Ok(Value::Word(Word::new(1))));
+        assert_eq!(d.get(a), Ok(&Value::default()));
+        assert_eq!(d.insert(Value::Word(Word::new(3))), a);
+        assert_eq!(d.get_mut(0), Err(DescriptorError::AttemptToMutateNull));
+        assert_eq!(d.insert(Value::default()), 0);
+    }
+
+    /// Verifies rights can only be taken away, and are checked by `require`.
+    #[test]
+    fn test_descriptor_rights() {
+        let mut d = Descriptors::new();
+        let i = d.insert(Value::Word(Word::new(1)));
+        assert_eq!(d.rights(i), Ok(arcane::rights::ALL));
+        d.restrict(i, arcane::rights::READ | arcane::rights::CLONE)
+            .unwrap();
+        d.restrict(i, arcane::rights::READ | arcane::rights::WRITE)
+            .unwrap();
+        assert_eq!(d.rights(i), Ok(arcane::rights::READ));
+        assert_eq!(d.require(i, arcane::rights::READ), Ok(()));
+        assert_eq!(
+            d.require(i, arcane::rights::WRITE),
+            Err(DescriptorError::PermissionDenied)
+        );
+        d.insert_with_rights(Value::Word(Word::new(2)), arcane::rights::APPLY);
+        assert_eq!(
+            d.all_rights(),
+            [
+                arcane::rights::ALL,
+                arcane::rights::READ,
+                arcane::rights::APPLY
+            ]
+        );
+    }
+
+    /// Verifies an element's rights narrow what the container grants over that element, and what
+    /// it carries elsewhere, but not the container's own.
+    #[test]
+    fn test_element_rights() {
+        use arcane::rights::{ALL, READ, WRITE};
+        let mut d = Descriptors::new();
+        let i = d.insert(Value::Tuple(Tuple::new(3)));
+        d.set_element_rights(i, 1..2, READ).unwrap();
+        assert_eq!(d.require(i, WRITE), Ok(()));
+        assert_eq!(d.element_rights(i, 0..1), Ok(ALL));
+        assert_eq!(d.element_rights(i, 1..2), Ok(READ));
+        assert_eq!(d.carried_rights(i), Ok(READ));
+        let j = d.duplicate(i).unwrap();
+        assert_eq!(d.element_rights(j, 1..2), Ok(READ));
+        d.set_element_rights(i, 1..2, ALL).unwrap();
+        assert_eq!(d.carried_rights(i), Ok(ALL));
+        assert_eq!(d.carried_rights(j), Ok(READ));
+    }
+}
@@ -1,44 +1,101 @@
-use core::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut, Range};
 
 use crate::prelude::*;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum Blob {
-    Raw(Box<[u8]>),
-    String(String),
+use super::page::Page;
+
+/// An immutable-by-default byte string.
+///
+/// A blob is a view of a refcounted buffer, so cloning and slicing it never copies bytes; the
+/// buffer is either heap memory or a (shared) page, which lets a blob be made from memory a
+/// program has already filled in.  Writing through a blob copies its bytes first unless the blob
+/// is the only view of a heap buffer.
+#[derive(Clone)]
+pub struct Blob {
+    buffer: Buffer,
+    range: Range<usize>,
+}
+
+#[derive(Clone)]
+enum Buffer {
+    Heap(Arc<Box<[u8]>>),
+    Page(Page),
+}
+
+impl Buffer {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Buffer::Heap(bytes) => bytes,
+            Buffer::Page(page) => page,
+        }
+    }
 }
 
 impl Blob {
     pub fn new<T: Into<Box<[u8]>>>(x: T) -> Self {
         let x = x.into();
-        match String::from_utf8(x.into()) {
-            Ok(x) => Blob::String(x),
-            Err(e) => Blob::Raw(e.into_bytes().into()),
+        let len = x.len();
+        Blob {
+            buffer: Buffer::Heap(Arc::new(x)),
+            range: 0..len,
+        }
+    }
+
+    /// A blob of `range` within `page`, which is shared rather than copied.
+    pub fn from_page(page: Page, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= page.size());
+        Blob {
+            buffer: Buffer::Page(page.shared()),
+            range,
         }
     }
 
-    fn make_blob(&mut self) -> &mut Box<[u8]> {
-        if let Blob::String(s) = self {
-            *self = Blob::Raw(s.as_bytes().into())
+    /// A blob of `range` within this one, sharing its buffer.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.len());
+        Blob {
+            buffer: self.buffer.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+
+    /// Truncates this blob, which only narrows its view, or extends it with zeroes.
+    pub fn resize(&mut self, len: usize) {
+        if len <= self.len() {
+            self.range.end = self.range.start + len;
+        } else {
+            let mut bytes = Vec::with_capacity(len);
+            bytes.extend_from_slice(self);
+            bytes.resize(len, 0);
+            *self = Blob::new(bytes);
+        }
+    }
+
+    fn make_unique(&mut self) -> &mut [u8] {
+        let unique = match &mut self.buffer {
+            Buffer::Heap(bytes) => Arc::get_mut(bytes).is_some(),
+            Buffer::Page(_) => false,
         };
-        let Blob::Raw(items) = self else {
+        if !unique {
+            *self = Blob::new(&self[..]);
+        }
+        let Buffer::Heap(bytes) = &mut self.buffer else {
             unreachable!();
         };
-        items
+        &mut Arc::get_mut(bytes).unwrap()[self.range.clone()]
     }
 
     pub fn into_inner(self) -> Box<[u8]> {
-        match self {
-            Blob::Raw(items) => items,
-            Blob::String(s) => s.as_bytes().into(),
+        match self.buffer {
+            Buffer::Heap(bytes) if self.range == (0..bytes.len()) => {
+                Arc::try_unwrap(bytes).unwrap_or_else(|bytes| bytes[..].into())
+            }
+            buffer => buffer.bytes()[self.range].into(),
         }
     }
 
     pub fn len(&self) -> usize {
-        match self {
-            Blob::Raw(items) => items.len(),
-            Blob::String(s) => s.len(),
-        }
+        self.range.len()
     }
 
     #[must_use]
@@ -51,16 +108,30 @@ impl Deref for Blob {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        match self {
-            Blob::Raw(items) => items,
-            Blob::String(s) => s.as_bytes(),
-        }
+        &self.buffer.bytes()[self.range.clone()]
     }
 }
 
 impl DerefMut for Blob {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.make_blob()
+        self.make_unique()
+    }
+}
+
+impl PartialEq for Blob {
+    fn eq(&self, other: &Self) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for Blob {}
+
+impl core::fmt::Debug for Blob {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match core::str::from_utf8(self) {
+            Ok(s) => f.debug_tuple("Blob").field(&s).finish(),
+            Err(_) => f.debug_tuple("Blob").field(&&self[..]).finish(),
+        }
     }
 }
 
@@ -84,7 +155,7 @@ impl From<String> for Blob {
 
 impl From<&str> for Blob {
     fn from(value: &str) -> Self {
-        Blob::from(value.to_string())
+        Blob::new(value.as_bytes())
     }
 }
 
@@ -123,4 +194,42 @@ mod tests {
         assert_eq!(blob.len(), 4);
         assert_eq!(&*blob, b"test");
     }
+
+    /// Verifies slices share their parent's bytes, and writing to one leaves the parent alone.
+    #[test]
+    fn test_slice() {
+        let blob = Blob::from("hello, world");
+        let mut world = blob.slice(7..12);
+        assert_eq!(&*world, b"world");
+        assert_eq!(world.slice(1..3), Blob::from("or"));
+        world[0] = b'W';
+        assert_eq!(&*world, b"World");
+        assert_eq!(&*blob, b"hello, world");
+        assert_eq!(&*world.into_inner(), b"World");
+    }
+
+    /// Verifies shrinking keeps the shared buffer and growing pads with zeroes.
+    #[test]
+    fn test_resize() {
+        let blob = Blob::from("hello");
+        let mut short = blob.clone();
+        short.resize(4);
+        assert_eq!(&*short, b"hell");
+        short.resize(6);
+        assert_eq!(&*short, b"hell\0\0");
+        assert_eq!(&*blob, b"hello");
+    }
+
+    /// Verifies a page-backed blob sees the page's bytes without taking it over.
+    #[test]
+    fn test_from_page() {
+        let mut page = Page::new(4096);
+        page[100..105].copy_from_slice(b"hello");
+        let page = page.shared();
+        let mut blob = Blob::from_page(page.clone(), 100..105);
+        assert_eq!(blob, Blob::from("hello"));
+        blob[0] = b'j';
+        assert_eq!(&*blob, b"jello");
+        assert_eq!(&page[100..105], b"hello");
+    }
 }
@@ -1,10 +1,13 @@
+pub mod linux;
 pub mod syscall;
 
-use core::ops::ControlFlow;
+use core::ops::{ControlFlow, Range};
 
 use alloc::collections::vec_deque::VecDeque;
+use common::elfloader::{FSBASE, REGISTERS};
+use common::util::rangemap::RangeMap;
 
-use super::arca::Arca;
+use super::arca::{Arca, LoadedArca};
 use crate::{
     cpu::ExitReason,
     prelude::*,
@@ -48,12 +51,44 @@ impl Function {
             let memory: Table = data.get(1).try_into().ok()?;
             let descriptors: Tuple = data.get(2).try_into().ok()?;
             let rlimit: Tuple = data.get(3).try_into().ok()?;
+            let symbols = match data.len() > 4 {
+                true => Blob::try_from(data.get(4)).ok(),
+                false => None,
+            };
+            let flags = match data.len() > 5 {
+                true => Word::try_from(data.get(5)).map(|x| x.read()).unwrap_or(0),
+                false => 0,
+            };
+            let zero_fill = match data.len() > 6 {
+                true => parse_regions(data.get(6))?,
+                false => Vec::new(),
+            };
+            let descriptor_rights = match data.len() > 7 {
+                true => parse_rights(data.get(7))?,
+                false => Vec::new(),
+            };
+            let argument_rights = match data.len() > 8 {
+                true => parse_rights(data.get(8))?,
+                false => Vec::new(),
+            };
+            let linux = match data.len() > 9 {
+                true => linux::State::parse(data.get(9))?,
+                false => linux::State::default(),
+            };
+            let mapping_rights = match data.len() > 10 {
+                true => parse_mapping_rights(data.get(10))?,
+                false => RangeMap::new(),
+            };
+            let image = match data.len() > 11 {
+                true => Word::try_from(data.get(11)).ok().map(|x| x.read()),
+                false => None,
+            };
 
             let registers = registers.into_inner();
             let mut register_file = RegisterFile::new();
             for (i, x) in registers
                 .iter()
-                .take(18)
+                .take(FSBASE)
                 .enumerate()
                 .filter(|(_, x)| x.datatype() != DataType::Null)
             {
@@ -62,7 +97,26 @@ impl Function {
                 };
                 register_file[i] = w.read();
             }
-            let arca = Arca::new_with(register_file, memory, descriptors, rlimit);
+            // the FS base (thread pointer) follows the register file
+            let fsbase = match registers.get(FSBASE) {
+                Some(Value::Word(w)) => w.read(),
+                _ => 0,
+            };
+            let mut arca = Arca::new_with(register_file, memory, descriptors, rlimit);
+            arca.set_fsbase(fsbase);
+            arca.set_symbols(symbols);
+            arca.set_image(image);
+            arca.set_flags(flags);
+            arca.set_zero_fill(zero_fill);
+            arca.set_linux(linux);
+            arca.set_mapping_rights(mapping_rights);
+            for (i, rights) in descriptor_rights.into_iter().enumerate() {
+                // rights for descriptors past the end of the table have nothing to restrict
+                let _ = arca.descriptors_mut().restrict(i, rights);
+            }
+            for (i, rights) in argument_rights.into_iter().enumerate() {
+                arca.restrict_argument(i, rights);
+            }
             Function::arcane_with_args(arca, args)
         } else if symbolic {
             Function::symbolic_with_args(data, args)
@@ -81,14 +135,36 @@ impl Function {
             }
             Definition::Arcane(arca) => Value::Tuple(Tuple::from((
                 Blob::from("Arcane"),
-                Tuple::from({
-                    let (r, t, d) = arca.read();
-                    let mut rr = Tuple::new(18);
-                    for i in 0..18 {
+                {
+                    let flags = arca.flags();
+                    let zero_fill = write_regions(arca.zero_fill());
+                    let descriptor_rights = write_rights(arca.descriptors().all_rights());
+                    let argument_rights = write_rights(arca.argument_rights().iter().copied());
+                    let linux = arca.linux().write();
+                    let mapping_rights = write_mapping_rights(arca.mapping_rights());
+                    let image = arca.image().map(Word::new).map(Value::Word);
+                    let fsbase = arca.fsbase();
+                    let (r, t, d, symbols) = arca.read();
+                    let mut rr = Tuple::new(REGISTERS);
+                    for i in 0..FSBASE {
                         rr.set(i, Value::Word(Word::new(r[i])));
                     }
-                    (Value::Tuple(rr), Value::Table(t), Value::Tuple(d))
-                }),
+                    rr.set(FSBASE, Value::Word(Word::new(fsbase)));
+                    let mut data = Tuple::new(12);
+                    data.set(0, Value::Tuple(rr));
+                    data.set(1, Value::Table(t));
+                    data.set(2, Value::Tuple(d));
+                    data.set(3, Value::Tuple(Tuple::new(0)));
+                    data.set(4, symbols.map(Value::Blob).unwrap_or_default());
+                    data.set(5, Value::Word(Word::new(flags)));
+                    data.set(6, Value::Tuple(zero_fill));
+                    data.set(7, Value::Tuple(descriptor_rights));
+                    data.set(8, Value::Tuple(argument_rights));
+                    data.set(9, Value::Tuple(linux));
+                    data.set(10, Value::Tuple(mapping_rights));
+                    data.set(11, image.unwrap_or_default());
+                    data
+                },
                 args,
             ))),
         }
@@ -108,23 +184,126 @@ impl Function {
         }
     }
 
+    /// The effect a function performs when it raises an exception it cannot handle.
+    ///
+    /// The effect is the symbolic function `""` applied to `"exception"`, `info`, `backtrace` and
+    /// `continuation`, where:
+    ///
+    /// - `info` is `(vector, error, address, description)`: the interrupt vector (a word, or null
+    ///   if the function did not fault but was stopped, e.g. by the debugger), the error code the
+    ///   CPU pushed (or zero), the faulting address of a page fault (CR2, or zero), and a blob
+    ///   describing the exception;
+    /// - `backtrace` is as returned by [`backtrace`];
+    /// - `continuation` is the function stopped at the faulting instruction, which runs it again
+    ///   when forced (e.g. once its mapping has been fixed), or null if it cannot be resumed.
+    pub fn exception(info: impl Into<Value>, backtrace: Tuple, continuation: Value) -> Value {
+        Value::Function(arca::Function::from_inner(Function::symbolic_with_args(
+            "",
+            vec![
+                Value::Blob(Blob::from("exception")),
+                info.into(),
+                Value::Tuple(backtrace),
+                continuation,
+            ]
+            .into(),
+        )))
+    }
+
+    /// The effect a function with [`arcane::flags::PAGE_FAULT_EFFECT`] performs when it page
+    /// faults: the symbolic function `""` applied to `"page_fault"`, the faulting address, the
+    /// page-fault error code (whose bits say whether the page was present and whether the access
+    /// was a write or an instruction fetch), and the continuation stopped at the faulting
+    /// instruction.
+    pub fn page_fault(address: usize, error: u64, continuation: Value) -> Value {
+        Value::Function(arca::Function::from_inner(Function::symbolic_with_args(
+            "",
+            vec![
+                Value::Blob(Blob::from("page_fault")),
+                Value::Word(Word::new(address as u64)),
+                Value::Word(Word::new(error)),
+                continuation,
+            ]
+            .into(),
+        )))
+    }
+
+    /// A short name for this function in traces: its symbol and any leading blob argument (e.g.
+    /// `exception`), or `arcane`.
+    pub fn describe(&self) -> String {
+        let Definition::Symbolic(symbol) = &self.defn else {
+            return "arcane".into();
+        };
+        let mut name = String::new();
+        for x in core::iter::once(&**symbol).chain(self.args.front()) {
+            let Value::Blob(blob) = x else {
+                continue;
+            };
+            let bytes: &[u8] = blob.inner();
+            if !bytes.is_empty() {
+                if !name.is_empty() {
+                    name.push(' ');
+                }
+                name.push_str(&String::from_utf8_lossy(bytes));
+            }
+        }
+        name
+    }
+
     pub fn apply(&mut self, arg: impl Into<Value>) {
         self.args.push_back(arg.into());
     }
 
+    /// Applies the function to `arg`, whose descriptor will grant only `rights` (see
+    /// [`arcane::rights`]) when an Arcane function gets it with `get_argument`.
+    pub fn apply_with_rights(&mut self, arg: impl Into<Value>, rights: u64) {
+        if let Definition::Arcane(arca) = &mut self.defn {
+            arca.restrict_argument(self.args.len(), rights);
+        }
+        self.apply(arg);
+    }
+
+    /// Takes away the rights which are not in `rights` from every descriptor an Arcane function
+    /// holds or will be given for its arguments.
+    pub fn restrict(&mut self, rights: u64) {
+        if let Definition::Arcane(arca) = &mut self.defn {
+            arca.descriptors_mut().restrict_all(rights);
+            for i in 0..self.args.len() {
+                arca.restrict_argument(i, rights);
+            }
+        }
+    }
+
     pub fn force(self) -> Value {
         let mut cpu = CPU.borrow_mut();
         self.force_on(&mut cpu)
     }
 
-    pub fn force_on(mut self, cpu: &mut Cpu) -> Value {
+    pub fn force_on(self, cpu: &mut Cpu) -> Value {
+        self.force_with(cpu, |arca| ControlFlow::Continue(arca.run()))
+    }
+
+    /// Forces the function, using `run` to resume the loaded Arca each time it has to enter user
+    /// mode; `run` can end the function early by breaking with its result.
+    pub fn force_with(
+        mut self,
+        cpu: &mut Cpu,
+        mut run: impl FnMut(&mut LoadedArca) -> ControlFlow<Value, ExitReason>,
+    ) -> Value {
         match self.defn {
             Definition::Symbolic(_) => Value::Function(arca::Function::from_inner(self)),
             Definition::Arcane(arca) => {
+                crate::stats::count_force();
                 let mut arca = arca.load(cpu);
+                let personality_linux = arca.flags() & arcane::flags::LINUX != 0;
+                if personality_linux {
+                    linux::resume(&mut arca, &mut self.args);
+                }
 
                 loop {
-                    let result = arca.run();
+                    let result = match run(&mut arca) {
+                        ControlFlow::Continue(result) => result,
+                        ControlFlow::Break(value) => return value,
+                    };
                     match result {
                         ExitReason::SystemCall => {}
                         ExitReason::Interrupted(x) => {
@@ -133,21 +312,47 @@ impl Function {
                             }
                             panic!("exited with interrupt: {x:?}");
                         }
+                        ExitReason::PageFault { addr, error }
+                            if error & PAGE_FAULT_PRESENT == 0 && arca.is_zero_fill(addr) =>
+                        {
+                            arca.fill_zero_page(addr);
+                            continue;
+                        }
+                        ExitReason::PageFault { addr, error }
+                            if arca.flags() & arcane::flags::PAGE_FAULT_EFFECT != 0 =>
+                        {
+                            if crate::trace::tracing() {
+                                crate::trace::effect(arca.trace_id(), "page_fault".into());
+                            }
+                            let k = Function::arcane_with_args(
+                                arca.take(),
+                                core::mem::take(&mut self.args),
+                            );
+                            let k = Value::Function(arca::Function::from_inner(k));
+                            return Function::page_fault(addr, error, k);
+                        }
                         x => {
                             log::error!(
                                 "exited with exception: {x:x?} @ rip={:#x}",
                                 arca.registers()[Register::RIP]
                             );
-                            return Value::Function(arca::Function::from_inner(
-                                Function::symbolic_with_args(
-                                    "",
-                                    vec![Value::Blob(Blob::from("exception")), Value::from(x)]
-                                        .into(),
-                                ),
-                            ));
+                            let backtrace = backtrace(&mut arca);
+                            for (i, frame) in backtrace.iter().enumerate() {
+                                log::error!("    {i:>2}: {}", describe_frame(&frame));
+                            }
+                            let k = Function::arcane_with_args(
+                                arca.take(),
+                                core::mem::take(&mut self.args),
+                            );
+                            let k = Value::Function(arca::Function::from_inner(k));
+                            return Function::exception(x, backtrace, k);
                         }
                     }
-                    if let ControlFlow::Break(result) = handle_syscall(&mut arca, &mut self.args) {
+                    let handle = match personality_linux {
+                        true => linux::handle_syscall,
+                        false => handle_syscall,
+                    };
+                    if let ControlFlow::Break(result) = handle(&mut arca, &mut self.args) {
                         return result;
                     }
                 }
@@ -167,10 +372,163 @@ impl Function {
     }
 }
 
+/// The page-fault error code bit set when the faulting page was present.
+const PAGE_FAULT_PRESENT: u64 = 1 << 0;
+
+/// Parses a tuple of `(address, length)` pairs into address ranges.
+fn parse_regions(value: Value) -> Option<Vec<Range<usize>>> {
+    let regions: Tuple = value.try_into().ok()?;
+    regions
+        .iter()
+        .map(|region| {
+            let region: Tuple = region.try_into().ok()?;
+            let start = Word::try_from(region.get(0)).ok()?.read() as usize;
+            let len = Word::try_from(region.get(1)).ok()?.read() as usize;
+            Some(start..start.checked_add(len)?)
+        })
+        .collect()
+}
+
+fn write_regions(regions: &[Range<usize>]) -> Tuple {
+    let regions: Vec<Value> = regions
+        .iter()
+        .map(|x| {
+            Value::Tuple(Tuple::from((
+                Word::new(x.start as u64),
+                Word::new(x.len() as u64),
+            )))
+        })
+        .collect();
+    Tuple::from_inner(internal::Tuple::new(regions))
+}
+
+/// Parses a tuple of rights words, one per descriptor; a null entry grants every right.
+fn parse_rights(value: Value) -> Option<Vec<u64>> {
+    if value.datatype() == DataType::Null {
+        return Some(Vec::new());
+    }
+    let rights: Tuple = value.try_into().ok()?;
+    rights
+        .iter()
+        .map(|x| match x {
+            Value::Null(_) => Some(arcane::rights::ALL),
+            x => Some(Word::try_from(x).ok()?.read()),
+        })
+        .collect()
+}
+
+fn write_rights(rights: impl IntoIterator<Item = u64>) -> Tuple {
+    let rights: Vec<Value> = rights
+        .into_iter()
+        .map(|x| Value::Word(Word::new(x)))
+        .collect();
+    Tuple::from_inner(internal::Tuple::new(rights))
+}
+
+/// Parses a tuple of `(address, length, rights)` triples of words.
+fn parse_mapping_rights(value: Value) -> Option<RangeMap<u64>> {
+    if value.datatype() == DataType::Null {
+        return Some(RangeMap::new());
+    }
+    let triples: Tuple = value.try_into().ok()?;
+    triples
+        .iter()
+        .map(|triple| {
+            let triple: Tuple = triple.try_into().ok()?;
+            let address = Word::try_from(triple.get(0)).ok()?.read() as usize;
+            let len = Word::try_from(triple.get(1)).ok()?.read() as usize;
+            let rights = Word::try_from(triple.get(2)).ok()?.read();
+            Some((address..address.checked_add(len)?, rights))
+        })
+        .collect()
+}
+
+fn write_mapping_rights(mapping_rights: &RangeMap<u64>) -> Tuple {
+    let triples: Vec<Value> = mapping_rights
+        .iter()
+        .map(|(range, &rights)| {
+            Value::Tuple(Tuple::from((
+                Word::new(range.start as u64),
+                Word::new(range.len() as u64),
+                Word::new(rights),
+            )))
+        })
+        .collect();
+    Tuple::from_inner(internal::Tuple::new(triples))
+}
+
+/// The most frames [`backtrace`] will walk.
+const BACKTRACE_LIMIT: usize = 64;
+
+/// Walks the user stack of `arca`, producing a tuple of frames, innermost first.
+///
+/// Each frame is `(address, symbol, offset)`, where `symbol` is the name of the function containing
+/// `address` (or null if the program has no symbol for it) and `offset` is `address`'s offset into
+/// it.
+pub fn backtrace(arca: &mut LoadedArca) -> Tuple {
+    let addresses = arca.backtrace(BACKTRACE_LIMIT);
+    let symbols = arca.symbols();
+    let symbols = symbols.and_then(|blob| common::symbols::Symbols::new(blob.inner()));
+    let frames: Vec<Value> = addresses
+        .into_iter()
+        .map(|address| {
+            let (symbol, offset) = match symbols.and_then(|x| x.lookup(address)) {
+                Some((name, offset)) => (Value::Blob(Blob::from(name)), offset),
+                None => (Value::default(), 0),
+            };
+            Value::Tuple(Tuple::from((Word::new(address), symbol, Word::new(offset))))
+        })
+        .collect();
+    Tuple::from_inner(internal::Tuple::new(frames))
+}
+
+fn describe_frame(frame: &Value) -> String {
+    let Value::Tuple(frame) = frame else {
+        return format!("{frame:?}");
+    };
+    let address = Word::try_from(frame.get(0)).map(|x| x.read()).unwrap_or(0);
+    match (frame.get(1), Word::try_from(frame.get(2))) {
+        (Value::Blob(name), Ok(offset)) => format!(
+            "{address:#x} <{}+{:#x}>",
+            String::from_utf8_lossy(name.inner()),
+            offset.read()
+        ),
+        _ => format!("{address:#x}"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Makes an Arcane function from data with the registers, address space, descriptors and
+    /// rlimit every function needs, then each `(index, value)` of `extra`.
+    fn arcane(extra: impl IntoIterator<Item = (usize, Value)>) -> Function {
+        let extra = Vec::from_iter(extra);
+        let len = extra.iter().map(|(i, _)| i + 1).fold(4, usize::max);
+        let mut data = Tuple::new(len);
+        data.set(0, Value::Tuple(Tuple::new(FSBASE)));
+        data.set(1, Value::Table(Table::new(1 << 21)));
+        data.set(2, Value::Tuple(Tuple::new(0)));
+        data.set(3, Value::Tuple(Tuple::new(0)));
+        for (i, x) in extra {
+            data.set(i, x);
+        }
+        let value = Value::Tuple(Tuple::from((Blob::from("Arcane"), data, Tuple::new(0))));
+        Function::new(value).expect("arcane parse failed")
+    }
+
+    /// Reads `func` back and returns its data.
+    fn read_data(func: Function) -> Tuple {
+        let Value::Tuple(read) = func.read() else {
+            panic!("function did not read as a tuple");
+        };
+        let Value::Tuple(data) = read.get(1) else {
+            panic!("function data is not a tuple");
+        };
+        data
+    }
+
     /// Verifies symbolic function parsing and read round-trip.
     #[test]
     fn test_symbolic_parse_and_read() {
@@ -185,6 +543,22 @@ mod tests {
         assert_eq!(func.read(), value);
     }
 
+    /// Verifies effects are named by their symbol and leading blob argument.
+    #[test]
+    fn test_describe() {
+        let exception = Function::exception(Blob::from("oops"), Tuple::new(0), Value::default());
+        let Value::Function(exception) = exception else {
+            panic!("exception is not a function");
+        };
+        assert_eq!(exception.inner().describe(), "exception");
+        let f = Function::symbolic_with_args("read", vec![Value::Word(Word::new(1))].into());
+        assert_eq!(f.describe(), "read");
+        assert_eq!(
+            Function::arcane_with_args(Arca::new(), Default::default()).describe(),
+            "arcane"
+        );
+    }
+
     /// Ensures unrecognized function tags are rejected.
     #[test]
     fn test_invalid_tag_rejected() {
@@ -213,4 +587,172 @@ mod tests {
         let func = Function::new(value).expect("arcane parse failed");
         assert!(func.is_arcane());
     }
+
+    /// Verifies an arcane function's symbol table survives a parse and read round-trip.
+    #[test]
+    fn test_arcane_symbols_round_trip() {
+        let symbols = Blob::from(&common::symbols::encode([(0x1000, 0x10, "main")])[..]);
+        let func = arcane([(4, Value::Blob(symbols.clone()))]);
+        assert_eq!(read_data(func).get(4), Value::Blob(symbols));
+    }
+
+    /// Verifies the rights of entries mapped with fewer than all of them survive a parse and read
+    /// round-trip.
+    #[test]
+    fn test_mapping_rights_round_trip() {
+        let triple = Value::Tuple(Tuple::from((
+            Word::new(0x4000),
+            Word::new(0x2000),
+            Word::new(arcane::rights::READ | arcane::rights::MAP),
+        )));
+        let mut triples = Tuple::new(1);
+        triples.set(0, triple.clone());
+        let func = arcane([(10, Value::Tuple(triples))]);
+        let Value::Tuple(triples) = read_data(func).get(10) else {
+            panic!("mapping rights are not a tuple");
+        };
+        assert_eq!(triples.len(), 1);
+        assert_eq!(triples.get(0), triple);
+    }
+
+    /// Verifies the FS base, which follows the register file, survives a parse and read round-trip.
+    #[test]
+    fn test_arcane_fsbase_round_trip() {
+        let mut registers = Tuple::new(REGISTERS);
+        registers.set(FSBASE, Value::Word(Word::new(0x7f_ffff_efc0)));
+        let func = arcane([(0, Value::Tuple(registers))]);
+        let Value::Tuple(registers) = read_data(func).get(0) else {
+            panic!("arcane function registers were not a tuple");
+        };
+        assert_eq!(
+            registers.get(FSBASE),
+            Value::Word(Word::new(0x7f_ffff_efc0))
+        );
+    }
+
+    /// Verifies the frame-pointer walk follows saved frames up the stack and stops at a null one.
+    #[test]
+    fn test_backtrace_walk() {
+        let mut table = Table::new(1 << 21);
+        let page = arca::Page::from_inner(crate::types::internal::Page::new(1));
+        table.map(0x1000, arca::Entry::RWPage(page)).unwrap();
+        // frame at 0x1100 returns to 0x4000 and points at the frame at 0x1200, which returns to
+        // 0x5000 and ends the chain
+        let frames = [(0x1100, 0x1200u64, 0x4000u64), (0x1200, 0, 0x5000)];
+        for (at, next, ret) in frames {
+            table.write_bytes(at, &next.to_le_bytes()).unwrap();
+            table.write_bytes(at + 8, &ret.to_le_bytes()).unwrap();
+        }
+        let trace = crate::types::arca::backtrace(&table, 0x3000, 0x1100, 64);
+        assert_eq!(trace, [0x3000, 0x4000, 0x5000]);
+        let trace = crate::types::arca::backtrace(&table, 0x3000, 0x1100, 2);
+        assert_eq!(trace, [0x3000, 0x4000]);
+    }
+
+    /// Verifies exceptions record their vector, error code and faulting address.
+    #[test]
+    fn test_exception_info() {
+        let fault = ExitReason::PageFault {
+            addr: 0x1234,
+            error: 0b111,
+        };
+        let Value::Tuple(info) = Value::from(fault) else {
+            panic!("exception info is not a tuple");
+        };
+        assert_eq!(info.get(0), Value::Word(Word::new(14)));
+        assert_eq!(info.get(1), Value::Word(Word::new(0b111)));
+        assert_eq!(info.get(2), Value::Word(Word::new(0x1234)));
+        assert_eq!(info.get(3).datatype(), DataType::Blob);
+
+        let Value::Tuple(info) = Value::from(ExitReason::GeneralProtectionFault { selector: 8 })
+        else {
+            panic!("exception info is not a tuple");
+        };
+        assert_eq!(info.get(1), Value::Word(Word::new(8)));
+        assert_eq!(info.get(2), Value::Word(Word::new(0)));
+    }
+
+    /// Verifies the flags word survives parsing and reading an Arcane function.
+    #[test]
+    fn test_arcane_flags() {
+        let flags = arcane::flags::PAGE_FAULT_EFFECT;
+        let func = arcane([(5, Value::Word(Word::new(flags)))]);
+        let Definition::Arcane(arca) = &func.defn else {
+            panic!("function is not arcane");
+        };
+        assert_eq!(arca.flags(), flags);
+        assert_eq!(read_data(func).get(5), Value::Word(Word::new(flags)));
+    }
+
+    /// Verifies zero-fill regions survive parsing and reading an Arcane function.
+    #[test]
+    fn test_arcane_zero_fill_round_trip() {
+        let region = Value::Tuple(Tuple::from((Word::new(0x4000), Word::new(0x2000))));
+        let mut regions = Tuple::new(1);
+        regions.set(0, region.clone());
+        let func = arcane([(6, Value::Tuple(regions))]);
+        let Definition::Arcane(arca) = &func.defn else {
+            panic!("function is not arcane");
+        };
+        assert_eq!(arca.zero_fill(), [0x4000..0x6000]);
+        let Value::Tuple(regions) = read_data(func).get(6) else {
+            panic!("zero-fill regions are not a tuple");
+        };
+        assert_eq!(regions.get(0), region);
+    }
+
+    /// Verifies a system call copying into an untouched zero-fill page (e.g. a buffer in `.bss`)
+    /// fills the page instead of failing.
+    #[test]
+    fn test_read_into_zero_fill() {
+        let mut arca = Arca::new_with(
+            RegisterFile::new(),
+            Table::new(1 << 21),
+            Tuple::new(0),
+            Tuple::new(0),
+        );
+        arca.set_zero_fill(vec![0x4000..0x6000]);
+        let mut cpu = CPU.borrow_mut();
+        let mut arca = arca.load(&mut cpu);
+        let blob = arca
+            .descriptors_mut()
+            .insert(Value::Blob(Blob::from("hello")))
+            .unwrap();
+        // the buffer straddles both untouched pages
+        let args = [blob as u64, 0, 0x4ffe, 5, 0, 0];
+        assert_eq!(syscall::sys_read(args, &mut arca), Ok(5));
+        let mut bytes = [0; 8];
+        syscall::copy_user_to_kernel_buf(&mut bytes, 0x4ffd).unwrap();
+        assert_eq!(&bytes, b"\0hello\0\0");
+        arca.unload();
+    }
+
+    /// Verifies descriptor and argument rights survive parsing and reading an Arcane function.
+    #[test]
+    fn test_arcane_rights_round_trip() {
+        let read_only = Value::Word(Word::new(arcane::rights::READ));
+        let descriptors = Tuple::from((Null::new(), Word::new(7)));
+        let descriptor_rights = Tuple::from((Null::new(), read_only.clone()));
+        let mut func = arcane([
+            (2, Value::Tuple(descriptors)),
+            (7, Value::Tuple(descriptor_rights)),
+        ]);
+        func.apply_with_rights(Word::new(1), arcane::rights::APPLY);
+        let Definition::Arcane(arca) = &func.defn else {
+            panic!("function is not arcane");
+        };
+        assert_eq!(arca.descriptors().rights(1), Ok(arcane::rights::READ));
+        let data = read_data(func);
+        let Value::Tuple(descriptor_rights) = data.get(7) else {
+            panic!("descriptor rights are not a tuple");
+        };
+        assert_eq!(descriptor_rights.get(1), read_only);
+        let Value::Tuple(argument_rights) = data.get(8) else {
+            panic!("argument rights are not a tuple");
+        };
+        assert_eq!(
+            argument_rights.get(0),
+            Value::Word(Word::new(arcane::rights::APPLY))
+        );
+    }
 }
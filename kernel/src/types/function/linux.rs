@@ -0,0 +1,455 @@
+//! The Linux personality (see [`arcane::flags::LINUX`]), which lets unmodified static musl binaries
+//! run as Arcane functions by translating their Linux x86-64 system calls.
+//!
+//! File I/O is performed as the effects `user::io` performs (`read`, `write`, `open` and `close`),
+//! and `exit_group` as `exit`.  While an effect is outstanding the system call stays in the
+//! registers; once the continuation is applied to the effect's result, [`resume`] finishes it.
+//! Memory is managed by the Arca itself: the heap and anonymous mappings are zero-fill regions, so
+//! their pages are only allocated once they are touched.
+
+use core::ops::{ControlFlow, Range};
+
+use alloc::collections::vec_deque::VecDeque;
+
+use crate::{
+    prelude::*,
+    types::{arca::LoadedArca, internal},
+    vm,
+};
+
+use super::syscall::{copy_kernel_to_user, copy_user_to_kernel_buf};
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_CLOSE: u64 = 3;
+const SYS_MMAP: u64 = 9;
+const SYS_MUNMAP: u64 = 11;
+const SYS_BRK: u64 = 12;
+const SYS_WRITEV: u64 = 20;
+const SYS_EXIT: u64 = 60;
+const SYS_ARCH_PRCTL: u64 = 158;
+const SYS_SET_TID_ADDRESS: u64 = 218;
+const SYS_CLOCK_GETTIME: u64 = 228;
+const SYS_EXIT_GROUP: u64 = 231;
+const SYS_OPENAT: u64 = 257;
+
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+const CLOCK_REALTIME: u64 = 0;
+const AT_FDCWD: i64 = -100;
+/// The most buffers a `writev` may gather, as on Linux.
+const IOV_MAX: usize = 1024;
+/// The longest path `openat` will read.
+const PATH_MAX: usize = 4096;
+/// The most bytes one `read`, `write` or `writev` transfers; as on Linux, a larger request is cut
+/// short rather than failed, and the program makes another for the rest.
+const MAX_RW_COUNT: usize = 1 << 20;
+
+/// The top of the region anonymous mappings are placed in; each new one goes just below the last.
+pub const MMAP_TOP: usize = 0x40_0000_0000;
+
+/// The Linux personality's part of an Arca.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct State {
+    /// The memory `brk` manages, from the end of the program's image to the program break.
+    pub heap: Range<usize>,
+    /// The lowest address `mmap` has placed a mapping at.
+    pub mmap_base: usize,
+    /// Whether the system call in the registers is waiting on the result of an effect.
+    pub pending: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            heap: 0..0,
+            mmap_base: MMAP_TOP,
+            pending: false,
+        }
+    }
+}
+
+impl State {
+    /// Parses a tuple of `(heap start, program break, mmap base, pending)` words; entries which
+    /// are missing keep their defaults, as does the whole state if `value` is null.
+    pub fn parse(value: Value) -> Option<State> {
+        let default = State::default();
+        let mut words = [
+            default.heap.start as u64,
+            default.heap.end as u64,
+            default.mmap_base as u64,
+            default.pending as u64,
+        ];
+        if value.datatype() != DataType::Null {
+            let tuple: Tuple = value.try_into().ok()?;
+            for (word, x) in words.iter_mut().zip(tuple.iter()) {
+                *word = Word::try_from(x).ok()?.read();
+            }
+        }
+        let [start, end, mmap_base, pending] = words.map(|x| x as usize);
+        Some(State {
+            heap: start..end,
+            mmap_base,
+            pending: pending != 0,
+        })
+    }
+
+    pub fn write(&self) -> Tuple {
+        Tuple::from((
+            Word::new(self.heap.start as u64),
+            Word::new(self.heap.end as u64),
+            Word::new(self.mmap_base as u64),
+            Word::new(self.pending as u64),
+        ))
+    }
+}
+
+type Result<T> = core::result::Result<T, u32>;
+
+/// Handles a system call made by an Arca with the Linux personality, breaking with an effect if
+/// it has to perform one.
+pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> ControlFlow<Value> {
+    let regs = arca.registers();
+    let num = regs[Register::RAX];
+    let args = [
+        regs[Register::RDI],
+        regs[Register::RSI],
+        regs[Register::RDX],
+        regs[Register::R10],
+        regs[Register::R8],
+        regs[Register::R9],
+    ];
+
+    let result = match num {
+        SYS_READ => {
+            let fd = args[0];
+            let len = core::cmp::min(args[2], MAX_RW_COUNT as u64);
+            return perform(
+                arca,
+                argv,
+                "read",
+                [Word::new(fd).into(), Word::new(len).into()],
+            );
+        }
+        SYS_WRITE => {
+            let fd = args[0];
+            let blob = match read_user(arca, args[1] as usize, args[2] as usize) {
+                Ok(blob) => blob,
+                Err(e) => return finish(arca, Err(e)),
+            };
+            return perform(arca, argv, "write", [Word::new(fd).into(), blob.into()]);
+        }
+        SYS_WRITEV => {
+            let fd = args[0];
+            let blob = match gather(arca, args[1] as usize, args[2] as usize) {
+                Ok(blob) => blob,
+                Err(e) => return finish(arca, Err(e)),
+            };
+            return perform(arca, argv, "write", [Word::new(fd).into(), blob.into()]);
+        }
+        SYS_OPENAT => {
+            if args[0] as i64 != AT_FDCWD {
+                return finish(arca, Err(arcane::ENOSYS));
+            }
+            let path = match read_path(arca, args[1] as usize) {
+                Ok(path) => path,
+                Err(e) => return finish(arca, Err(e)),
+            };
+            let (flags, mode) = (args[2], args[3]);
+            return perform(
+                arca,
+                argv,
+                "open",
+                [path.into(), Word::new(flags).into(), Word::new(mode).into()],
+            );
+        }
+        SYS_CLOSE => {
+            let fd = args[0];
+            return perform(arca, argv, "close", [Word::new(fd).into()]);
+        }
+        SYS_EXIT | SYS_EXIT_GROUP => {
+            let code = args[0] & 0xff;
+            return perform(arca, argv, "exit", [Word::new(code).into()]);
+        }
+        SYS_BRK => Ok(brk(arca, args[0] as usize) as u64),
+        SYS_MMAP => mmap(arca, args),
+        SYS_MUNMAP => munmap(arca, args[0] as usize, args[1] as usize).map(|_| 0),
+        SYS_ARCH_PRCTL => arch_prctl(arca, args[0], args[1]),
+        SYS_SET_TID_ADDRESS => Ok(1),
+        SYS_CLOCK_GETTIME => clock_gettime(arca, args[0], args[1] as usize),
+        _ => {
+            log::debug!("unsupported Linux system call {num}");
+            Err(arcane::ENOSYS)
+        }
+    };
+    finish(arca, result)
+}
+
+/// Finishes a system call whose effect has produced `result`, if the Arca was waiting on one.
+pub fn resume(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) {
+    if !core::mem::take(&mut arca.linux_mut().pending) {
+        return;
+    }
+    let regs = arca.registers();
+    let (num, ptr, len) = (
+        regs[Register::RAX],
+        regs[Register::RSI] as usize,
+        regs[Register::RDX] as usize,
+    );
+    let result = match (num, argv.pop_front().unwrap_or_default()) {
+        (SYS_READ, Value::Blob(blob)) => {
+            let len = core::cmp::min(len, blob.len());
+            arca.fault_in(ptr, len);
+            copy_kernel_to_user(ptr, &blob.inner()[..len])
+                .map(|_| len as u64)
+                .map_err(|_| arcane::EFAULT)
+        }
+        // any effect can answer with the result itself, e.g. a negative error number
+        (_, Value::Word(word)) => Ok(word.read()),
+        (SYS_CLOSE, _) => Ok(0),
+        _ => Err(arcane::EIO),
+    };
+    let _ = finish(arca, result);
+}
+
+/// Stores the result of a system call in the Arca's registers, as Linux returns it.
+fn finish(arca: &mut LoadedArca, result: Result<u64>) -> ControlFlow<Value> {
+    arca.registers_mut()[Register::RAX] = match result {
+        Ok(x) => x,
+        Err(e) => -(e as i64) as u64,
+    };
+    ControlFlow::Continue(())
+}
+
+/// Breaks out of the Arca to perform the effect `name` on `args`, passing it the Arca as its
+/// continuation.
+fn perform<const N: usize>(
+    arca: &mut LoadedArca,
+    argv: &mut VecDeque<Value>,
+    name: &str,
+    args: [Value; N],
+) -> ControlFlow<Value> {
+    if crate::trace::tracing() {
+        crate::trace::effect(arca.trace_id(), name.into());
+    }
+    arca.linux_mut().pending = true;
+    let k = internal::Function::arcane_with_args(arca.take(), core::mem::take(argv));
+    let mut effect = internal::Function::symbolic_with_args(name, args.into_iter().collect());
+    effect.apply(Value::Function(arca::Function::from_inner(k)));
+    ControlFlow::Break(Value::Function(arca::Function::from_inner(effect)))
+}
+
+/// Checks the `len` bytes at `ptr` lie in user memory.
+fn check_user(ptr: usize, len: usize) -> Result<()> {
+    match ptr.checked_add(len) {
+        Some(end) if vm::is_user(ptr) && end <= vm::USER_MEMORY_LIMIT + 1 => Ok(()),
+        _ => Err(arcane::EFAULT),
+    }
+}
+
+/// Reads up to [`MAX_RW_COUNT`] of the `len` bytes at `ptr`.
+fn read_user(arca: &mut LoadedArca, ptr: usize, len: usize) -> Result<Blob> {
+    check_user(ptr, len)?;
+    let len = core::cmp::min(len, MAX_RW_COUNT);
+    let mut bytes = vec![0; len];
+    arca.fault_in(ptr, len);
+    copy_user_to_kernel_buf(&mut bytes, ptr).map_err(|_| arcane::EFAULT)?;
+    Ok(Blob::from(&bytes[..]))
+}
+
+/// Reads the NUL-terminated path at `ptr`.
+fn read_path(arca: &mut LoadedArca, ptr: usize) -> Result<Blob> {
+    let mut path = Vec::new();
+    let mut byte = [0];
+    while path.len() < PATH_MAX {
+        arca.fault_in(ptr + path.len(), 1);
+        copy_user_to_kernel_buf(&mut byte, ptr + path.len()).map_err(|_| arcane::EFAULT)?;
+        if byte[0] == 0 {
+            return Ok(Blob::from(&path[..]));
+        }
+        path.push(byte[0]);
+    }
+    Err(arcane::ENAMETOOLONG)
+}
+
+/// Gathers the `count` buffers described by the `struct iovec`s at `iov` into one blob, up to
+/// [`MAX_RW_COUNT`] bytes.
+fn gather(arca: &mut LoadedArca, iov: usize, count: usize) -> Result<Blob> {
+    if count > IOV_MAX {
+        return Err(arcane::EINVAL);
+    }
+    check_user(iov, count * 16)?;
+    let mut iovecs = vec![0; count * 16];
+    arca.fault_in(iov, iovecs.len());
+    copy_user_to_kernel_buf(&mut iovecs, iov).map_err(|_| arcane::EFAULT)?;
+    let mut bytes = Vec::new();
+    for iovec in iovecs.chunks_exact(16) {
+        let base = u64::from_ne_bytes(iovec[..8].try_into().unwrap()) as usize;
+        let len = u64::from_ne_bytes(iovec[8..].try_into().unwrap()) as usize;
+        check_user(base, len)?;
+        let start = bytes.len();
+        let len = core::cmp::min(len, MAX_RW_COUNT - start);
+        bytes.resize(start + len, 0);
+        arca.fault_in(base, len);
+        copy_user_to_kernel_buf(&mut bytes[start..], base).map_err(|_| arcane::EFAULT)?;
+    }
+    Ok(Blob::from(&bytes[..]))
+}
+
+fn page_up(address: usize) -> usize {
+    address.next_multiple_of(Page4KB::SIZE)
+}
+
+/// Moves the program break to `address`, returning where it ends up: unchanged if `address` is
+/// outside the memory the heap can use.
+fn brk(arca: &mut LoadedArca, address: usize) -> usize {
+    let heap = arca.linux_mut().heap.clone();
+    let limit = arca.linux_mut().mmap_base;
+    if address < heap.start || address > limit {
+        return heap.end;
+    }
+    let (old, new) = (page_up(heap.end), page_up(address));
+    if new > old {
+        arca.add_zero_fill(old..new);
+    } else if new < old {
+        unmap(arca, new..old);
+    }
+    arca.linux_mut().heap.end = address;
+    address
+}
+
+fn mmap(arca: &mut LoadedArca, args: [u64; 6]) -> Result<u64> {
+    let (address, len, flags) = (args[0] as usize, args[1] as usize, args[3]);
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(arcane::ENODEV);
+    }
+    if len == 0 || (flags & MAP_FIXED != 0 && !address.is_multiple_of(Page4KB::SIZE)) {
+        return Err(arcane::EINVAL);
+    }
+    let len = page_up(len);
+    let address = if flags & MAP_FIXED != 0 {
+        address
+    } else {
+        let state = arca.linux_mut();
+        let address = state
+            .mmap_base
+            .checked_sub(len)
+            .filter(|&x| x >= page_up(state.heap.end))
+            .ok_or(arcane::ENOMEM)?;
+        state.mmap_base = address;
+        address
+    };
+    let region = user_region(address, len).ok_or(arcane::ENOMEM)?;
+    unmap(arca, region.clone());
+    arca.add_zero_fill(region);
+    Ok(address as u64)
+}
+
+fn munmap(arca: &mut LoadedArca, address: usize, len: usize) -> Result<()> {
+    if !address.is_multiple_of(Page4KB::SIZE) || len == 0 {
+        return Err(arcane::EINVAL);
+    }
+    let region = user_region(address, page_up(len)).ok_or(arcane::EINVAL)?;
+    unmap(arca, region);
+    Ok(())
+}
+
+/// The `len` bytes at `address`, if they lie in user memory.
+fn user_region(address: usize, len: usize) -> Option<Range<usize>> {
+    let end = address.checked_add(len)?;
+    (vm::is_user(address) && end <= vm::USER_MEMORY_LIMIT + 1).then_some(address..end)
+}
+
+/// Unmaps the pages in `region` and stops filling them with zeroes.
+fn unmap(arca: &mut LoadedArca, region: Range<usize>) {
+    arca.remove_zero_fill(region.clone());
+    arca.with_mappings(|table| clear(table, 0, &region));
+}
+
+/// Clears the entries in `region` of `table`, which starts at `base`.  Entries wholly inside
+/// `region` are dropped at once and absent ones skipped, so the work done depends on what is
+/// mapped rather than on the size of `region`; a large page only partly inside it stays mapped.
+fn clear(table: &mut Table, base: usize, region: &Range<usize>) {
+    let size = table.len() / 512;
+    let start = region.start.max(base);
+    let end = region.end.min(base + table.len());
+    if start >= end {
+        return;
+    }
+    for index in (start - base) / size..(end - base).div_ceil(size) {
+        let entry_base = base + index * size;
+        let covered = region.start <= entry_base && entry_base + size <= region.end;
+        let kept = match table.set(index, Entry::Null(size)) {
+            Ok(Entry::Null(_)) | Err(_) => continue,
+            Ok(_) if covered => continue,
+            Ok(Entry::ROTable(mut nested)) => {
+                clear(&mut nested, entry_base, region);
+                Entry::ROTable(nested)
+            }
+            Ok(Entry::RWTable(mut nested)) => {
+                clear(&mut nested, entry_base, region);
+                Entry::RWTable(nested)
+            }
+            Ok(page) => page,
+        };
+        let _ = table.set(index, kept);
+    }
+}
+
+fn arch_prctl(arca: &mut LoadedArca, code: u64, address: u64) -> Result<u64> {
+    match code {
+        ARCH_SET_FS => {
+            unsafe {
+                core::arch::asm!("wrfsbase {base}", base=in(reg) address);
+            }
+            Ok(0)
+        }
+        ARCH_GET_FS => {
+            let base: u64;
+            unsafe {
+                core::arch::asm!("rdfsbase {base}", base=out(reg) base);
+            }
+            arca.fault_in(address as usize, 8);
+            copy_kernel_to_user(address as usize, &base.to_ne_bytes())
+                .map(|_| 0)
+                .map_err(|_| arcane::EFAULT)
+        }
+        _ => Err(arcane::EINVAL),
+    }
+}
+
+fn clock_gettime(arca: &mut LoadedArca, clock: u64, ptr: usize) -> Result<u64> {
+    let (sec, nsec) = if clock == CLOCK_REALTIME {
+        let now = crate::kvmclock::now();
+        (now.unix_timestamp() as u64, now.nanosecond() as u64)
+    } else {
+        let now = crate::kvmclock::monotonic();
+        (now.as_secs(), now.subsec_nanos() as u64)
+    };
+    let mut timespec = [0; 16];
+    timespec[..8].copy_from_slice(&sec.to_ne_bytes());
+    timespec[8..].copy_from_slice(&nsec.to_ne_bytes());
+    arca.fault_in(ptr, timespec.len());
+    copy_kernel_to_user(ptr, &timespec)
+        .map(|_| 0)
+        .map_err(|_| arcane::EFAULT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verifies the state survives writing and parsing, and that a null one is the default.
+    #[test]
+    fn test_state_round_trip() {
+        let state = State {
+            heap: 0x40_0000..0x41_0000,
+            mmap_base: 0x1000_0000,
+            pending: true,
+        };
+        assert_eq!(State::parse(Value::Tuple(state.write())), Some(state));
+        assert_eq!(State::parse(Value::default()), Some(State::default()));
+    }
+}
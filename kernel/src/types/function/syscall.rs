@@ -1,7 +1,10 @@
-use core::{mem::MaybeUninit, ops::ControlFlow};
+use core::{
+    mem::MaybeUninit,
+    ops::{ControlFlow, Range},
+};
 
 use alloc::collections::vec_deque::VecDeque;
-use arcane::SyscallError;
+use arcane::{rights, SyscallError};
 
 use crate::{
     prelude::*,
@@ -13,6 +16,9 @@ use crate::{
 
 pub type Result<T> = core::result::Result<T, SyscallError>;
 
+/// The rights making descriptors of (parts of) a value takes, since they are copies of it.
+const COPY_RIGHTS: u64 = rights::READ | rights::CLONE;
+
 pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> ControlFlow<Value> {
     let regs = arca.registers();
     let num = regs[Register::RAX];
@@ -24,7 +30,36 @@ pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> Cont
         regs[Register::R8],
         regs[Register::R9],
     ];
+    crate::stats::count_syscall(num);
+
+    let traced = crate::trace::tracing().then(|| (arca.trace_id(), crate::tsc::read_cycles()));
+    let result = dispatch(num, args, arca, argv);
+    if let Some((id, start)) = traced {
+        let result = match &result {
+            ControlFlow::Continue(result) => Some(*result),
+            ControlFlow::Break(_) => None,
+        };
+        crate::trace::syscall(id, num, args, result, start);
+    }
+    let result = result?;
+
+    let regs = arca.registers_mut();
+    if let Err(err) = result {
+        log::debug!("system call {num} failed with {err:?}");
+    }
+    regs[Register::RAX] = match result {
+        Ok(x) => x as u64,
+        Err(e) => -(e as i64) as u64,
+    };
+    ControlFlow::Continue(())
+}
 
+fn dispatch(
+    num: u64,
+    args: [u64; 6],
+    arca: &mut LoadedArca,
+    argv: &mut VecDeque<Value>,
+) -> ControlFlow<Value, Result<usize>> {
     let result = match num as u32 {
         arcane::__NR_nop => Ok(0),
         arcane::__NR_drop => sys_drop(args, arca),
@@ -33,7 +68,8 @@ pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> Cont
         arcane::__NR_exit => sys_exit(args, arca)?,
         arcane::__NR_get_argument => {
             if let Some(front) = argv.pop_front() {
-                let idx = arca.descriptors_mut().insert(front);
+                let rights = arca.take_argument_rights();
+                let idx = arca.descriptors_mut().insert_with_rights(front, rights);
                 idx
             } else {
                 arca.registers_mut()[Register::RAX] = (-(arcane::__ERR_interrupted as i32)) as u64;
@@ -53,11 +89,21 @@ pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> Cont
 
         arcane::__NR_create_word => sys_create_word(args, arca),
         arcane::__NR_create_blob => sys_create_blob(args, arca),
+        arcane::__NR_create_blob_from_page => sys_create_blob_from_page(args, arca),
         arcane::__NR_create_tree => sys_create_tuple(args, arca),
         arcane::__NR_create_page => sys_create_page(args, arca),
         arcane::__NR_create_table => sys_create_table(args, arca),
         arcane::__NR_create_function => sys_create_function(args, arca),
 
+        arcane::__NR_slice => sys_slice(args, arca),
+        arcane::__NR_concat => sys_concat(args, arca),
+        arcane::__NR_resize => sys_resize(args, arca),
+        arcane::__NR_get_many => sys_get_many(args, arca),
+        arcane::__NR_set_many => sys_set_many(args, arca),
+        arcane::__NR_restrict => sys_restrict(args, arca),
+
+        arcane::__NR_batch => sys_batch(args, arca, argv),
+
         arcane::__NR_apply => sys_apply(args, arca),
         // arcane::__NR_force => sys_force(args, arca),
         arcane::__NR_map => sys_map(args, arca),
@@ -76,19 +122,12 @@ pub fn handle_syscall(arca: &mut LoadedArca, argv: &mut VecDeque<Value>) -> Cont
 
         _ => {
             log::error!("invalid syscall {num}");
-            panic!("invalid syscall @ {:#x} ({args:#x?})", regs[Register::RIP]);
+            let rip = arca.registers()[Register::RIP];
+            panic!("invalid syscall @ {rip:#x} ({args:#x?})");
             // Err(SyscallError::BadSyscall)
         }
     };
-    let regs = arca.registers_mut();
-    if let Err(err) = result {
-        log::debug!("system call {num} failed with {err:?}");
-    }
-    regs[Register::RAX] = match result {
-        Ok(x) => x as u64,
-        Err(e) => -(e as i64) as u64,
-    };
-    ControlFlow::Continue(())
+    ControlFlow::Continue(result)
 }
 
 pub fn sys_drop(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
@@ -99,8 +138,15 @@ pub fn sys_drop(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
 
 pub fn sys_clone(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let src = args[0] as usize;
-    let clone = arca.descriptors_mut().get(src)?.clone();
-    arca.descriptors_mut().insert(clone)
+    arca.descriptors().require(src, rights::CLONE)?;
+    arca.descriptors_mut().duplicate(src)
+}
+
+pub fn sys_restrict(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let idx = args[0] as usize;
+    let rights = args[1];
+    arca.descriptors_mut().restrict(idx, rights)?;
+    Ok(0)
 }
 
 pub fn sys_exit(args: [u64; 6], arca: &mut LoadedArca) -> ControlFlow<Value, Result<usize>> {
@@ -127,6 +173,7 @@ pub fn sys_len(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
         Value::Table(table) => table.len(),
         _ => return Err(SyscallError::BadType),
     };
+    arca.fault_in(ptr, 8);
     copy_kernel_to_user(ptr, &len.to_ne_bytes())?;
     Ok(0)
 }
@@ -136,18 +183,31 @@ pub fn sys_set(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let inner_idx = args[1] as usize;
     let target = arca.descriptors().get(target_idx)?;
     let datatype = target.datatype();
+    arca.descriptors().require(target_idx, rights::WRITE)?;
+    // the container grants no more over an element than the descriptor it was put in with did, and
+    // the element it displaces goes back with no more than that one was put in with
     match datatype {
         DataType::Tuple => {
             let value_idx = args[2] as usize;
+            let element = inner_idx..inner_idx.saturating_add(1);
+            let value_rights = arca.descriptors().carried_rights(value_idx)?;
+            let displaced_rights = arca
+                .descriptors()
+                .element_rights(target_idx, element.clone())?;
             let value = arca.descriptors_mut().take(value_idx)?;
             let Value::Tuple(ref mut tree) = arca.descriptors_mut().get_mut(target_idx)? else {
                 unreachable!();
             };
             let value = tree.set(inner_idx, value);
-            arca.descriptors_mut().insert(value)
+            arca.descriptors_mut()
+                .set_element_rights(target_idx, element, value_rights)?;
+            arca.descriptors_mut()
+                .insert_with_rights(value, displaced_rights)
         }
         DataType::Table => {
+            arca.descriptors().require(target_idx, rights::MAP)?;
             let ptr = args[3] as usize;
+            arca.fault_in(ptr, core::mem::size_of::<arcane::arca_entry>());
             let mut entry: MaybeUninit<arcane::arca_entry> = MaybeUninit::uninit();
             copy_user_to_kernel(
                 unsafe {
@@ -157,14 +217,20 @@ pub fn sys_set(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
                 ptr,
             )?;
             let entry = unsafe { MaybeUninit::assume_init(entry) };
-            let entry = read_entry(arca, entry)?;
+            let (entry, entry_rights) = read_entry(arca, entry)?;
             let Value::Table(ref mut table) = arca.descriptors_mut().get_mut(target_idx)? else {
                 unreachable!();
             };
+            let element = entry_range(table, inner_idx);
             let Ok(entry) = table.set(inner_idx, entry) else {
                 todo!();
             };
-            let entry = write_entry(arca, entry);
+            let displaced_rights = arca
+                .descriptors()
+                .element_rights(target_idx, element.clone())?;
+            arca.descriptors_mut()
+                .set_element_rights(target_idx, element, entry_rights)?;
+            let entry = write_entry(arca, entry, displaced_rights);
             copy_kernel_to_user(ptr, unsafe {
                 &*(&entry as *const arcane::arca_entry
                     as *const [u8; core::mem::size_of::<arcane::arca_entry>()])
@@ -178,16 +244,23 @@ pub fn sys_set(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
 pub fn sys_get(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let target_idx = args[0] as usize;
     let inner_idx = args[1] as usize;
+    arca.descriptors().require(target_idx, COPY_RIGHTS)?;
     let target = arca.descriptors_mut().get_mut(target_idx)?;
     match target {
         Value::Tuple(tree) => {
             let value = tree.get(inner_idx);
-            arca.descriptors_mut().insert(value)
+            let rights = arca
+                .descriptors()
+                .element_rights(target_idx, inner_idx..inner_idx.saturating_add(1))?;
+            arca.descriptors_mut().insert_with_rights(value, rights)
         }
         Value::Table(table) => {
             let ptr = args[2] as usize;
+            let element = entry_range(table, inner_idx);
             let entry = table.get(inner_idx)?;
-            let entry = write_entry(arca, entry);
+            let rights = arca.descriptors().element_rights(target_idx, element)?;
+            let entry = write_entry(arca, entry, rights);
+            arca.fault_in(ptr, core::mem::size_of::<arcane::arca_entry>());
             copy_kernel_to_user(ptr, unsafe {
                 &*(&entry as *const arcane::arca_entry
                     as *const [u8; core::mem::size_of::<arcane::arca_entry>()])
@@ -200,10 +273,14 @@ pub fn sys_get(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
 
 pub fn sys_read(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let idx = args[0] as usize;
+    arca.descriptors().require(idx, rights::READ)?;
+    let len = buffer_len(arca, idx, args)?;
+    arca.fault_in(args[2] as usize, len);
     match arca.descriptors_mut().get_mut(idx)? {
         Value::Word(word) => {
             let ptr = args[1] as usize;
             let word = word.read();
+            arca.fault_in(ptr, 8);
             copy_kernel_to_user(ptr, &word.to_ne_bytes())?;
             Ok(8)
         }
@@ -230,6 +307,9 @@ pub fn sys_read(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
 
 pub fn sys_write(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let idx = args[0] as usize;
+    arca.descriptors().require(idx, rights::WRITE)?;
+    let len = buffer_len(arca, idx, args)?;
+    arca.fault_in(args[2] as usize, len);
     match arca.descriptors_mut().get_mut(idx)? {
         Value::Blob(blob) => {
             let offset = args[1] as usize;
@@ -252,6 +332,17 @@ pub fn sys_write(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     }
 }
 
+/// The number of bytes `read` or `write` on the blob or page `idx` copies, given its arguments
+/// `(idx, offset, ptr, len)`; zero for other values.
+fn buffer_len(arca: &LoadedArca, idx: usize, args: [u64; 6]) -> Result<usize> {
+    let size = match arca.descriptors().get(idx)? {
+        Value::Blob(blob) => blob.len(),
+        Value::Page(page) => page.len(),
+        _ => 0,
+    };
+    Ok(size.saturating_sub(args[1] as usize).min(args[3] as usize))
+}
+
 pub fn sys_type(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let idx = args[0] as usize;
     let val = arca.descriptors().get(idx)?;
@@ -276,11 +367,65 @@ pub fn sys_create_blob(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let ptr = args[0] as usize;
     let len = args[1] as usize;
     let mut buffer = Box::new_uninit_slice(len);
+    arca.fault_in(ptr, len);
     let buffer = copy_user_to_kernel(&mut buffer, ptr)?;
     arca.descriptors_mut()
         .insert(Value::Blob(Blob::new(buffer)))
 }
 
+pub fn sys_create_blob_from_page(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let ptr = args[0] as usize;
+    let len = args[1] as usize;
+    let flags = args[2];
+    if flags & !arcane::SHARE_PAGE != 0 {
+        return Err(SyscallError::BadArgument);
+    }
+    if flags & arcane::SHARE_PAGE == 0 {
+        return sys_create_blob(args, arca);
+    }
+    arca.fault_in(ptr, len);
+
+    // Find the page containing ptr, largest first, so that unmapping a size never splits a
+    // larger page; a table found instead goes straight back.
+    for size in [Page1GB::SIZE, Page2MB::SIZE, Page4KB::SIZE] {
+        let base = ptr & !(size - 1);
+        if !vm::is_user(base) {
+            return Err(SyscallError::BadArgument);
+        }
+        let entry = match arca.cpu().map(base, Entry::Null(size)) {
+            Ok(entry @ (Entry::ROPage(_) | Entry::RWPage(_))) => entry,
+            Ok(table @ (Entry::ROTable(_) | Entry::RWTable(_))) => {
+                let _ = arca.cpu().map(base, table);
+                continue;
+            }
+            _ => return Err(SyscallError::BadArgument),
+        };
+        let offset = ptr - base;
+        let Some(end) = offset.checked_add(len).filter(|&end| end <= size) else {
+            let _ = arca.cpu().map(base, entry);
+            return Err(SyscallError::BadArgument);
+        };
+        if len < size / 2 {
+            // a slice this small is cheaper to copy than to keep its whole page alive for
+            let _ = arca.cpu().map(base, entry);
+            return sys_create_blob(args, arca);
+        }
+        let (Entry::ROPage(page) | Entry::RWPage(page)) = entry else {
+            unreachable!();
+        };
+
+        // The page is now shared with the blob, so it goes back read-only; writing to it again
+        // takes an mprotect, which gives the program its own copy.
+        let page = page.into_inner().shared();
+        let blob = internal::Blob::from_page(page.clone(), offset..end);
+        let _ = arca.cpu().map(base, Entry::ROPage(Page::from_inner(page)));
+        return arca
+            .descriptors_mut()
+            .insert(Value::Blob(Blob::from_inner(blob)));
+    }
+    Err(SyscallError::BadArgument)
+}
+
 pub fn sys_create_tuple(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let len = args[0] as usize;
     let buf = vec![Value::default(); len];
@@ -302,25 +447,253 @@ pub fn sys_create_table(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize>
 
 pub fn sys_create_function(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let data = args[0] as usize;
+    // the function's descriptors can grant no more than the one for its data did
+    let rights = arca.descriptors().carried_rights(data)?;
     let data = arca.descriptors_mut().take(data)?;
-    let result = Function::new(data)?;
-    arca.descriptors_mut().insert(result.into())
+    let mut result = Function::new(data)?;
+    result.inner_mut().restrict(rights);
+    arca.descriptors_mut()
+        .insert_with_rights(result.into(), rights)
+}
+
+pub fn sys_slice(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let idx = args[0] as usize;
+    let range = args[1] as usize..args[2] as usize;
+    arca.descriptors().require(idx, COPY_RIGHTS)?;
+    let rights = arca.descriptors().carried_rights(idx)?;
+    let value = match arca.descriptors().get(idx)? {
+        Value::Blob(blob) => Value::Blob(
+            <Runtime as arca::Runtime>::slice_blob(blob, range)
+                .map_err(|_| SyscallError::BadIndex)?,
+        ),
+        Value::Tuple(tuple) => Value::Tuple(
+            <Runtime as arca::Runtime>::slice_tuple(tuple, range)
+                .map_err(|_| SyscallError::BadIndex)?,
+        ),
+        _ => return Err(SyscallError::BadType),
+    };
+    arca.descriptors_mut().insert_with_rights(value, rights)
+}
+
+pub fn sys_concat(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    arca.descriptors().require(args[0] as usize, COPY_RIGHTS)?;
+    arca.descriptors().require(args[1] as usize, COPY_RIGHTS)?;
+    let rights = arca.descriptors().carried_rights(args[0] as usize)?
+        & arca.descriptors().carried_rights(args[1] as usize)?;
+    let first = arca.descriptors().get(args[0] as usize)?;
+    let second = arca.descriptors().get(args[1] as usize)?;
+    let value = match (first, second) {
+        (Value::Blob(first), Value::Blob(second)) => Value::Blob(first.concat(second)),
+        (Value::Tuple(first), Value::Tuple(second)) => Value::Tuple(first.concat(second)),
+        _ => return Err(SyscallError::BadType),
+    };
+    arca.descriptors_mut().insert_with_rights(value, rights)
+}
+
+pub fn sys_resize(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let idx = args[0] as usize;
+    let len = args[1] as usize;
+    arca.descriptors().require(idx, rights::WRITE)?;
+    match arca.descriptors_mut().get_mut(idx)? {
+        Value::Blob(blob) => blob.resize(len),
+        Value::Tuple(tuple) => tuple.resize(len),
+        _ => return Err(SyscallError::BadType),
+    }
+    arca.descriptors_mut()
+        .set_element_rights(idx, len..usize::MAX, rights::ALL)?;
+    Ok(0)
+}
+
+pub fn sys_get_many(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let idx = args[0] as usize;
+    let start = args[1] as usize;
+    let ptr = args[2] as usize;
+    let len = args[3] as usize;
+    arca.descriptors().require(idx, COPY_RIGHTS)?;
+    let Value::Tuple(tuple) = arca.descriptors().get(idx)? else {
+        return Err(SyscallError::BadType);
+    };
+    let end = start.checked_add(len).ok_or(SyscallError::BadIndex)?;
+    let Some(values) = tuple.inner().get(start..end) else {
+        return Err(SyscallError::BadIndex);
+    };
+    let values = values.to_vec();
+    let mut descriptors = Vec::with_capacity(len);
+    for (i, value) in (start..end).zip(values) {
+        let rights = arca.descriptors().element_rights(idx, i..i + 1)?;
+        descriptors.push(arca.descriptors_mut().insert_with_rights(value, rights)? as u64);
+    }
+    if let Err(e) = write_descriptors(arca, ptr, &descriptors) {
+        for descriptor in descriptors {
+            arca.descriptors_mut().take(descriptor as usize)?;
+        }
+        return Err(e);
+    }
+    Ok(0)
+}
+
+pub fn sys_set_many(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
+    let idx = args[0] as usize;
+    let start = args[1] as usize;
+    let ptr = args[2] as usize;
+    let len = args[3] as usize;
+    arca.descriptors().require(idx, rights::WRITE)?;
+    let Value::Tuple(tuple) = arca.descriptors().get(idx)? else {
+        return Err(SyscallError::BadType);
+    };
+    let end = start.checked_add(len).ok_or(SyscallError::BadIndex)?;
+    if end > tuple.len() {
+        return Err(SyscallError::BadIndex);
+    }
+    let descriptors = read_descriptors(arca, ptr, len)?;
+    // Check everything which could fail before changing anything.  Each value is taken from its
+    // descriptor, so no descriptor but the null one may appear twice, nor may the tuple's own; and
+    // the old values' descriptors go back where these came from, so that has to be writable.
+    let mut distinct: Vec<u64> = descriptors.iter().copied().filter(|&x| x != 0).collect();
+    distinct.sort_unstable();
+    if distinct.windows(2).any(|x| x[0] == x[1]) || distinct.binary_search(&(idx as u64)).is_ok() {
+        return Err(SyscallError::BadArgument);
+    }
+    let mut value_rights = Vec::with_capacity(len);
+    let mut displaced_rights = Vec::with_capacity(len);
+    for (i, &descriptor) in (start..end).zip(&descriptors) {
+        value_rights.push(arca.descriptors().carried_rights(descriptor as usize)?);
+        displaced_rights.push(arca.descriptors().element_rights(idx, i..i + 1)?);
+    }
+    write_descriptors(arca, ptr, &descriptors)?;
+
+    let mut values = Vec::with_capacity(len);
+    for descriptor in descriptors {
+        values.push(arca.descriptors_mut().take(descriptor as usize)?);
+    }
+    let Value::Tuple(tuple) = arca.descriptors_mut().get_mut(idx)? else {
+        unreachable!();
+    };
+    tuple.set_many(start, &mut values);
+    for (i, rights) in (start..end).zip(value_rights) {
+        arca.descriptors_mut()
+            .set_element_rights(idx, i..i + 1, rights)?;
+    }
+    let mut descriptors = Vec::with_capacity(len);
+    for (value, rights) in values.into_iter().zip(displaced_rights) {
+        let descriptor = arca.descriptors_mut().insert_with_rights(value, rights)?;
+        descriptors.push(descriptor as u64);
+    }
+    write_descriptors(arca, ptr, &descriptors)?;
+    Ok(0)
+}
+
+/// Whether system call `num` can run in a batch: it must return to the program without capturing
+/// it, and must not itself be a batch.
+fn batchable(num: u32) -> bool {
+    matches!(
+        num,
+        arcane::__NR_nop
+            | arcane::__NR_drop
+            | arcane::__NR_clone
+            | arcane::__NR_length
+            | arcane::__NR_get
+            | arcane::__NR_set
+            | arcane::__NR_read
+            | arcane::__NR_write
+            | arcane::__NR_type
+            | arcane::__NR_create_word
+            | arcane::__NR_create_blob
+            | arcane::__NR_create_blob_from_page
+            | arcane::__NR_create_tree
+            | arcane::__NR_create_page
+            | arcane::__NR_create_table
+            | arcane::__NR_create_function
+            | arcane::__NR_slice
+            | arcane::__NR_concat
+            | arcane::__NR_resize
+            | arcane::__NR_get_many
+            | arcane::__NR_set_many
+            | arcane::__NR_restrict
+            | arcane::__NR_apply
+            | arcane::__NR_map
+            | arcane::__NR_mmap
+            | arcane::__NR_mprotect
+            | arcane::__NR_compat_mmap
+            | arcane::__NR_debug_show
+            | arcane::__NR_debug_log
+            | arcane::__NR_debug_log_int
+    )
+}
+
+pub fn sys_batch(
+    args: [u64; 6],
+    arca: &mut LoadedArca,
+    argv: &mut VecDeque<Value>,
+) -> Result<usize> {
+    let ptr = args[0] as usize;
+    let len = args[1] as usize;
+    let size = core::mem::size_of::<arcane::BatchEntry>();
+    if len > arcane::MAX_BATCH {
+        return Err(SyscallError::BadArgument);
+    }
+    let total = len * size;
+    let mut entries = vec![arcane::BatchEntry::default(); len];
+    // SAFETY: a BatchEntry is plain integers with no padding, so any bytes are a valid one
+    let bytes = unsafe { core::slice::from_raw_parts_mut(entries.as_mut_ptr() as *mut u8, total) };
+    arca.fault_in(ptr, total);
+    copy_user_to_kernel_buf(bytes, ptr)?;
+
+    let mut done = 0;
+    while done < len {
+        let mut entry = entries[done];
+        let mut result = Ok(());
+        for (i, arg) in entry.args.iter_mut().enumerate() {
+            if entry.refs & (1 << i) != 0 {
+                match entries[..done].get(*arg as usize) {
+                    Some(earlier) => *arg = earlier.result as u64,
+                    None => result = Err(SyscallError::BadArgument),
+                }
+            }
+        }
+        let num = entry.num as u32;
+        let result = result.and_then(|_| {
+            if entry.num > u32::MAX as u64 || !batchable(num) {
+                return Err(SyscallError::BadSyscall);
+            }
+            crate::stats::count_syscall(entry.num);
+            match dispatch(num, entry.args, arca, argv) {
+                ControlFlow::Continue(result) => result,
+                ControlFlow::Break(_) => unreachable!("batched system calls return"),
+            }
+        });
+        entries[done].result = match result {
+            Ok(x) => x as i64,
+            Err(e) => -(e as i64),
+        };
+        if result.is_err() {
+            break;
+        }
+        done += 1;
+    }
+
+    let written = core::cmp::min(done + 1, len);
+    let bytes = unsafe { core::slice::from_raw_parts(entries.as_ptr() as *const u8, total) };
+    copy_kernel_to_user(ptr, &bytes[..written * size])?;
+    Ok(done)
 }
 
 pub fn sys_apply(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let lambda = args[0] as usize;
     let arg = args[1] as usize;
+    arca.descriptors().require(lambda, rights::APPLY)?;
+    let rights = arca.descriptors().carried_rights(lambda)?;
+    let arg_rights = arca.descriptors().carried_rights(arg)?;
 
-    let f: Function = arca
+    let mut f: Function = arca
         .descriptors_mut()
         .take(lambda)?
         .try_into()
         .map_err(|_| SyscallError::BadType)?;
     let x = arca.descriptors_mut().take(arg)?;
 
-    let thunk = f.apply(x);
-    let idx = arca.descriptors_mut().insert(thunk.into());
-    idx
+    f.inner_mut().apply_with_rights(x, arg_rights);
+    arca.descriptors_mut().insert_with_rights(f.into(), rights)
 }
 
 #[allow(unused)]
@@ -344,10 +717,11 @@ pub fn sys_force(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
 }
 
 pub fn sys_map(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
-    let table = args[0] as usize;
+    let table_idx = args[0] as usize;
     let addr = args[1] as usize;
     let ptr = args[2] as usize;
 
+    arca.fault_in(ptr, core::mem::size_of::<arcane::arca_entry>());
     let mut entry: MaybeUninit<arcane::arca_entry> = MaybeUninit::uninit();
     copy_user_to_kernel(
         unsafe {
@@ -357,9 +731,12 @@ pub fn sys_map(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
         ptr,
     )?;
     let entry = unsafe { MaybeUninit::assume_init(entry) };
-    let entry = read_entry(arca, entry)?;
+    arca.descriptors().require(table_idx, rights::MAP)?;
+    let (entry, entry_rights) = read_entry(arca, entry)?;
+    // the entry displaced goes back with no more rights than the one it was mapped with
+    let element = addr..addr.saturating_add(entry.len());
 
-    let table = match arca.descriptors_mut().get_mut(table)? {
+    let table = match arca.descriptors_mut().get_mut(table_idx)? {
         Value::Table(table) => table,
         Value::Function(_) => todo!("mapping into Function"),
         _ => return Err(SyscallError::BadType),
@@ -367,7 +744,12 @@ pub fn sys_map(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let entry = table
         .map(addr, entry)
         .map_err(|_| SyscallError::BadArgument)?;
-    let entry = write_entry(arca, entry);
+    let displaced_rights = arca
+        .descriptors()
+        .element_rights(table_idx, element.clone())?;
+    arca.descriptors_mut()
+        .set_element_rights(table_idx, element, entry_rights)?;
+    let entry = write_entry(arca, entry, displaced_rights);
     copy_kernel_to_user(ptr, unsafe {
         &*(&entry as *const arcane::arca_entry
             as *const [u8; core::mem::size_of::<arcane::arca_entry>()])
@@ -380,6 +762,7 @@ pub fn sys_mmap(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let addr = args[0] as usize;
     let ptr = args[1] as usize;
 
+    arca.fault_in(ptr, core::mem::size_of::<arcane::arca_entry>());
     let mut entry: MaybeUninit<arcane::arca_entry> = MaybeUninit::uninit();
     copy_user_to_kernel(
         unsafe {
@@ -389,13 +772,17 @@ pub fn sys_mmap(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
         ptr,
     )?;
     let entry = unsafe { MaybeUninit::assume_init(entry) };
-    let entry = read_entry(arca, entry)?;
+    let (entry, entry_rights) = read_entry(arca, entry)?;
+    let size = entry.len();
 
     let entry = arca
         .cpu()
         .map(addr, entry)
         .map_err(|_| SyscallError::BadArgument)?;
-    let entry = write_entry(arca, entry);
+    // the displaced entry goes back with no more rights than it was mapped with
+    let displaced_rights = arca.take_mapping_rights(addr..addr.saturating_add(size));
+    arca.record_mapping_rights(addr..addr.saturating_add(size), entry_rights);
+    let entry = write_entry(arca, entry, displaced_rights);
     copy_kernel_to_user(ptr, unsafe {
         &*(&entry as *const arcane::arca_entry
             as *const [u8; core::mem::size_of::<arcane::arca_entry>()])
@@ -411,6 +798,7 @@ pub fn sys_mprotect(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     match mode {
         arcane::__MODE_none => {
             let _ = arca.cpu().map(addr, Entry::Null(4096));
+            arca.take_mapping_rights(addr..addr.saturating_add(4096));
         }
         arcane::__MODE_read_only => {
             let old = arca
@@ -427,6 +815,9 @@ pub fn sys_mprotect(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
             let _ = arca.cpu().map(addr, new);
         }
         arcane::__MODE_read_write => {
+            if arca.mapping_rights(addr..addr.saturating_add(4096)) & rights::WRITE == 0 {
+                return Err(SyscallError::PermissionDenied);
+            }
             let old = arca
                 .cpu()
                 .map(addr, Entry::Null(4096))
@@ -458,6 +849,8 @@ pub fn sys_compat_mmap(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     //     return Err(SyscallError::OutOfMemory);
     // }
 
+    // whatever was mapped here is dropped
+    arca.take_mapping_rights(addr..addr.saturating_add(len));
     let mut p = addr;
     while p < addr + len {
         if p.is_multiple_of(Page1GB::SIZE) && len >= Page1GB::SIZE {
@@ -508,6 +901,9 @@ pub fn sys_call_with_current_continuation(
     arca: &mut LoadedArca,
 ) -> ControlFlow<Value, Result<usize>> {
     let func = args[0] as usize;
+    if let Err(e) = arca.descriptors().require(func, rights::APPLY) {
+        return ControlFlow::Continue(Err(e.into()));
+    }
     let func = match arca.descriptors_mut().take(func) {
         Ok(x) => x,
         Err(e) => return ControlFlow::Continue(Err(e)),
@@ -515,6 +911,9 @@ pub fn sys_call_with_current_continuation(
     let Ok(func) = Function::try_from(func) else {
         return ControlFlow::Continue(Err(SyscallError::BadType));
     };
+    if crate::trace::tracing() {
+        crate::trace::effect(arca.trace_id(), func.inner().describe());
+    }
     let k: Value = Function::from_inner(internal::Function::arcane_with_args(
         arca.take(),
         Default::default(),
@@ -542,6 +941,7 @@ pub fn sys_show(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let idx = args[2] as usize;
 
     let mut buffer = Box::new_uninit_slice(len);
+    arca.fault_in(ptr, len);
     copy_user_to_kernel(&mut buffer, ptr)?;
     let msg = String::from_utf8(unsafe { buffer.assume_init().into() })
         .map_err(|_| SyscallError::BadArgument)?;
@@ -551,11 +951,12 @@ pub fn sys_show(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     Ok(0)
 }
 
-pub fn sys_log(args: [u64; 6], _: &mut LoadedArca) -> Result<usize> {
+pub fn sys_log(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let ptr = args[0] as usize;
     let len = args[1] as usize;
 
     let mut buffer = Box::new_uninit_slice(len);
+    arca.fault_in(ptr, len);
     copy_user_to_kernel(&mut buffer, ptr)?;
     let msg = String::from_utf8(unsafe { buffer.assume_init().into() })
         .map_err(|_| SyscallError::BadArgument)?;
@@ -564,12 +965,13 @@ pub fn sys_log(args: [u64; 6], _: &mut LoadedArca) -> Result<usize> {
     Ok(0)
 }
 
-pub fn sys_log_int(args: [u64; 6], _: &mut LoadedArca) -> Result<usize> {
+pub fn sys_log_int(args: [u64; 6], arca: &mut LoadedArca) -> Result<usize> {
     let ptr = args[0] as usize;
     let len = args[1] as usize;
     let val = args[2];
 
     let mut buffer = Box::new_uninit_slice(len);
+    arca.fault_in(ptr, len);
     copy_user_to_kernel(&mut buffer, ptr)?;
     let msg = String::from_utf8(unsafe { buffer.assume_init().into() })
         .map_err(|_| SyscallError::BadArgument)?;
@@ -578,7 +980,10 @@ pub fn sys_log_int(args: [u64; 6], _: &mut LoadedArca) -> Result<usize> {
     Ok(0)
 }
 
-fn copy_kernel_to_user(dst: usize, src: &[u8]) -> Result<()> {
+// The copies fail on pages which are not mapped, so callers first fault in (see
+// `LoadedArca::fault_in`) any zero-fill pages the user range may cover.
+
+pub(super) fn copy_kernel_to_user(dst: usize, src: &[u8]) -> Result<()> {
     if crate::vm::copy_kernel_to_user(dst, src) {
         Ok(())
     } else {
@@ -590,7 +995,7 @@ fn copy_user_to_kernel(dst: &mut [MaybeUninit<u8>], src: usize) -> Result<&mut [
     crate::vm::copy_user_to_kernel(dst, src).ok_or(SyscallError::BadArgument)
 }
 
-fn copy_user_to_kernel_buf(dst: &mut [u8], src: usize) -> Result<&mut [u8]> {
+pub(super) fn copy_user_to_kernel_buf(dst: &mut [u8], src: usize) -> Result<&mut [u8]> {
     crate::vm::copy_user_to_kernel(
         unsafe { core::mem::transmute::<&mut [u8], &mut [MaybeUninit<u8>]>(dst) },
         src,
@@ -598,42 +1003,77 @@ fn copy_user_to_kernel_buf(dst: &mut [u8], src: usize) -> Result<&mut [u8]> {
     .ok_or(SyscallError::BadArgument)
 }
 
-fn read_entry(arca: &mut LoadedArca, entry: arcane::arca_entry) -> Result<Entry> {
+/// Reads an array of `len` descriptors (as `u64`s) from user memory.
+fn read_descriptors(arca: &mut LoadedArca, ptr: usize, len: usize) -> Result<Vec<u64>> {
+    let size = len.checked_mul(8).ok_or(SyscallError::BadArgument)?;
+    let mut bytes = vec![0; size];
+    arca.fault_in(ptr, size);
+    copy_user_to_kernel_buf(&mut bytes, ptr)?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|x| u64::from_ne_bytes(x.try_into().unwrap()))
+        .collect())
+}
+
+fn write_descriptors(arca: &mut LoadedArca, ptr: usize, descriptors: &[u64]) -> Result<()> {
+    let bytes: Vec<u8> = descriptors.iter().flat_map(|x| x.to_ne_bytes()).collect();
+    arca.fault_in(ptr, bytes.len());
+    copy_kernel_to_user(ptr, &bytes)
+}
+
+/// Reads an entry to map, taking the descriptor of its page or table.  Returns the entry and the
+/// rights that descriptor granted.
+fn read_entry(arca: &mut LoadedArca, entry: arcane::arca_entry) -> Result<(Entry, u64)> {
     Ok(match entry {
         arcane::arca_entry {
             mode: arcane::__MODE_none,
             datatype: _,
             data,
-        } => arca::Entry::Null(data),
+        } => (arca::Entry::Null(data), rights::ALL),
         arcane::arca_entry {
             mode: arcane::__MODE_read_only,
             datatype: _,
             data,
         } => {
+            arca.descriptors().require(data, rights::MAP)?;
+            let rights = arca.descriptors().carried_rights(data)?;
             let value = arca.descriptors_mut().take(data)?;
-            match value {
+            let entry = match value {
                 Value::Page(page) => arca::Entry::ROPage(page),
                 Value::Table(table) => arca::Entry::ROTable(table),
                 _ => return Err(SyscallError::BadType),
-            }
+            };
+            (entry, rights)
         }
         arcane::arca_entry {
             mode: arcane::__MODE_read_write,
             datatype: _,
             data,
         } => {
+            arca.descriptors()
+                .require(data, rights::MAP | rights::WRITE)?;
+            let rights = arca.descriptors().carried_rights(data)?;
             let value = arca.descriptors_mut().take(data)?;
-            match value {
+            let entry = match value {
                 Value::Page(page) => arca::Entry::RWPage(page),
                 Value::Table(table) => arca::Entry::RWTable(table),
                 _ => return Err(SyscallError::BadType),
-            }
+            };
+            (entry, rights)
         }
         _ => return Err(SyscallError::BadArgument),
     })
 }
 
-fn write_entry(arca: &mut LoadedArca, entry: Entry) -> arcane::arca_entry {
+/// The addresses entry `index` of `table` covers.
+fn entry_range(table: &Table, index: usize) -> Range<usize> {
+    let size = table.len() / 512;
+    let start = index.saturating_mul(size);
+    start..start.saturating_add(size)
+}
+
+/// Makes a descriptor granting `rights` for the page or table of an entry taken out of a mapping.
+fn write_entry(arca: &mut LoadedArca, entry: Entry, rights: u64) -> arcane::arca_entry {
     let (mode, datatype, value) = match entry {
         arca::Entry::Null(data) => {
             return arcane::arca_entry {
@@ -647,7 +1087,10 @@ fn write_entry(arca: &mut LoadedArca, entry: Entry) -> arcane::arca_entry {
         arca::Entry::ROTable(x) => (arcane::__MODE_read_only, arcane::__TYPE_table, x.into()),
         arca::Entry::RWTable(x) => (arcane::__MODE_read_write, arcane::__TYPE_table, x.into()),
     };
-    let index = arca.descriptors_mut().insert(value).unwrap();
+    let index = arca
+        .descriptors_mut()
+        .insert_with_rights(value, rights)
+        .unwrap();
     arcane::arca_entry {
         mode,
         datatype,
@@ -660,6 +1103,7 @@ impl From<DescriptorError> for SyscallError {
         match value {
             DescriptorError::AttemptToMutateNull => SyscallError::BadIndex,
             DescriptorError::OutOfBounds => SyscallError::BadIndex,
+            DescriptorError::PermissionDenied => SyscallError::PermissionDenied,
         }
     }
 }
@@ -1,47 +1,96 @@
 use core::ops::{Deref, DerefMut};
 
+use arca::Digest;
+use common::util::spinlock::SpinLock;
+
 use crate::paging::Impossible;
 use crate::prelude::*;
 
 use crate::page::{CowPage, Page1GB, Page2MB, Page4KB};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub enum Page {
+enum Frame {
     Page4KB(CowPage<Page4KB>),
     Page2MB(CowPage<Page2MB>),
     Page1GB(CowPage<Page1GB>),
 }
 
+#[derive(Debug)]
+pub struct Page {
+    frame: Frame,
+    /// The digest of the page's bytes, once computed; cleared when the page is borrowed mutably.
+    digest: SpinLock<Option<Digest>>,
+}
+
 impl Page {
     pub fn new(size: usize) -> Page {
         if size <= Page4KB::SIZE {
-            Page::Page4KB(Default::default())
+            Page::from_frame(Frame::Page4KB(Default::default()))
         } else if size <= Page2MB::SIZE {
-            Page::Page2MB(Default::default())
+            Page::from_frame(Frame::Page2MB(Default::default()))
         } else if size <= Page1GB::SIZE {
-            Page::Page1GB(Default::default())
+            Page::from_frame(Frame::Page1GB(Default::default()))
         } else {
             panic!();
         }
     }
 
     pub fn shared(self) -> Page {
-        match self {
-            Page::Page4KB(page) => Page::Page4KB(page.shared().into()),
-            Page::Page2MB(page) => Page::Page2MB(page.shared().into()),
-            Page::Page1GB(page) => Page::Page1GB(page.shared().into()),
+        let frame = match self.frame {
+            Frame::Page4KB(page) => Frame::Page4KB(page.shared().into()),
+            Frame::Page2MB(page) => Frame::Page2MB(page.shared().into()),
+            Frame::Page1GB(page) => Frame::Page1GB(page.shared().into()),
+        };
+        Page {
+            frame,
+            digest: self.digest,
         }
     }
 
     pub fn size(&self) -> usize {
-        match self {
-            Page::Page4KB(_) => 1 << 12,
-            Page::Page2MB(_) => 1 << 21,
-            Page::Page1GB(_) => 1 << 30,
+        match self.frame {
+            Frame::Page4KB(_) => 1 << 12,
+            Frame::Page2MB(_) => 1 << 21,
+            Frame::Page1GB(_) => 1 << 30,
+        }
+    }
+
+    fn from_frame(frame: Frame) -> Page {
+        Page {
+            frame,
+            digest: SpinLock::new(None),
+        }
+    }
+
+    /// The digest of the page's bytes, computed once per write.
+    pub fn digest(&self) -> Digest {
+        if let Some(digest) = *self.digest.lock() {
+            return digest;
         }
+        // hashing a large page takes a while, so do it without holding the lock
+        let digest = Digest::of_bytes(self);
+        *self.digest.lock() = Some(digest);
+        digest
     }
 }
 
+impl Clone for Page {
+    fn clone(&self) -> Self {
+        Page {
+            frame: self.frame.clone(),
+            digest: SpinLock::new(*self.digest.lock()),
+        }
+    }
+}
+
+impl PartialEq for Page {
+    fn eq(&self, other: &Self) -> bool {
+        self.frame == other.frame
+    }
+}
+
+impl Eq for Page {}
+
 impl From<CowPage<Impossible>> for Page {
     fn from(_: CowPage<Impossible>) -> Self {
         unreachable!()
@@ -50,19 +99,19 @@ impl From<CowPage<Impossible>> for Page {
 
 impl From<CowPage<Page4KB>> for Page {
     fn from(value: CowPage<Page4KB>) -> Self {
-        Page::Page4KB(value)
+        Page::from_frame(Frame::Page4KB(value))
     }
 }
 
 impl From<CowPage<Page2MB>> for Page {
     fn from(value: CowPage<Page2MB>) -> Self {
-        Page::Page2MB(value)
+        Page::from_frame(Frame::Page2MB(value))
     }
 }
 
 impl From<CowPage<Page1GB>> for Page {
     fn from(value: CowPage<Page1GB>) -> Self {
-        Page::Page1GB(value)
+        Page::from_frame(Frame::Page1GB(value))
     }
 }
 
@@ -70,9 +119,12 @@ impl TryFrom<Page> for CowPage<Page4KB> {
     type Error = Page;
 
     fn try_from(value: Page) -> Result<Self, Self::Error> {
-        match value {
-            Page::Page4KB(page) => Ok(page),
-            _ => Err(value),
+        match value.frame {
+            Frame::Page4KB(page) => Ok(page),
+            frame => Err(Page {
+                frame,
+                digest: value.digest,
+            }),
         }
     }
 }
@@ -81,9 +133,12 @@ impl TryFrom<Page> for CowPage<Page2MB> {
     type Error = Page;
 
     fn try_from(value: Page) -> Result<Self, Self::Error> {
-        match value {
-            Page::Page2MB(page) => Ok(page),
-            _ => Err(value),
+        match value.frame {
+            Frame::Page2MB(page) => Ok(page),
+            frame => Err(Page {
+                frame,
+                digest: value.digest,
+            }),
         }
     }
 }
@@ -92,31 +147,44 @@ impl TryFrom<Page> for CowPage<Page1GB> {
     type Error = Page;
 
     fn try_from(value: Page) -> Result<Self, Self::Error> {
-        match value {
-            Page::Page1GB(page) => Ok(page),
-            _ => Err(value),
+        match value.frame {
+            Frame::Page1GB(page) => Ok(page),
+            frame => Err(Page {
+                frame,
+                digest: value.digest,
+            }),
         }
     }
 }
 
+/// No page fits in a 256 TiB table's entries.
+impl TryFrom<Page> for CowPage<Impossible> {
+    type Error = Page;
+
+    fn try_from(value: Page) -> Result<Self, Self::Error> {
+        Err(value)
+    }
+}
+
 impl Deref for Page {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        match self {
-            Page::Page4KB(page) => &page[..],
-            Page::Page2MB(page) => &page[..],
-            Page::Page1GB(page) => &page[..],
+        match &self.frame {
+            Frame::Page4KB(page) => &page[..],
+            Frame::Page2MB(page) => &page[..],
+            Frame::Page1GB(page) => &page[..],
         }
     }
 }
 
 impl DerefMut for Page {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        match self {
-            Page::Page4KB(page) => &mut page[..],
-            Page::Page2MB(page) => &mut page[..],
-            Page::Page1GB(page) => &mut page[..],
+        *self.digest.lock() = None;
+        match &mut self.frame {
+            Frame::Page4KB(page) => &mut page[..],
+            Frame::Page2MB(page) => &mut page[..],
+            Frame::Page1GB(page) => &mut page[..],
         }
     }
 }
@@ -146,6 +214,17 @@ mod tests {
         assert_eq!(page[0], 7);
     }
 
+    /// Checks that the cached digest is dropped when the page is written.
+    #[test]
+    fn test_digest_cleared_on_write() {
+        let mut page = Page::new(1);
+        let zero = page.digest();
+        assert_eq!(page.clone().digest(), zero);
+        page[0] = 1;
+        assert_ne!(page.digest(), zero);
+        assert_eq!(page.digest(), Digest::of_bytes(&page));
+    }
+
     /// Ensures shared() preserves written content.
     #[test]
     fn test_shared_preserves_content() {
@@ -1,3 +1,7 @@
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
 use super::internal;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -120,6 +124,67 @@ impl arca::Runtime for Runtime {
         Ok(core::mem::replace(&mut inner[index], value))
     }
 
+    fn slice_blob(
+        blob: &arca::Blob<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Blob<Self>, Self::Error> {
+        if range.start > range.end || range.end > blob.len() {
+            return Err(Error::InvalidIndex(range.end));
+        }
+        Ok(arca::Blob::from_inner(blob.inner().slice(range)))
+    }
+
+    fn concat_blobs(first: &arca::Blob<Self>, second: &arca::Blob<Self>) -> arca::Blob<Self> {
+        let mut bytes = Vec::with_capacity(first.len() + second.len());
+        bytes.extend_from_slice(first.inner());
+        bytes.extend_from_slice(second.inner());
+        arca::Blob::from_inner(internal::Blob::new(bytes))
+    }
+
+    fn resize_blob(blob: &mut arca::Blob<Self>, len: usize) {
+        blob.inner_mut().resize(len);
+    }
+
+    fn slice_tuple(
+        tuple: &arca::Tuple<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Tuple<Self>, Self::Error> {
+        let values = tuple.inner().get(range.clone());
+        let values = values.ok_or(Error::InvalidIndex(range.end))?;
+        Ok(arca::Tuple::from_inner(internal::Tuple::new(values)))
+    }
+
+    fn concat_tuples(first: &arca::Tuple<Self>, second: &arca::Tuple<Self>) -> arca::Tuple<Self> {
+        let values = first.inner().iter().chain(second.inner().iter()).cloned();
+        arca::Tuple::from_inner(values.collect())
+    }
+
+    fn resize_tuple(tuple: &mut arca::Tuple<Self>, len: usize) {
+        tuple.inner_mut().resize(len);
+    }
+
+    fn get_tuple_many(
+        tuple: &arca::Tuple<Self>,
+        start: usize,
+        values: &mut [arca::Value<Self>],
+    ) -> Result<(), Self::Error> {
+        let end = start + values.len();
+        let elements = tuple.inner().get(start..end);
+        values.clone_from_slice(elements.ok_or(Error::InvalidIndex(end))?);
+        Ok(())
+    }
+
+    fn set_tuple_many(
+        tuple: &mut arca::Tuple<Self>,
+        start: usize,
+        values: &mut [arca::Value<Self>],
+    ) -> Result<(), Self::Error> {
+        let end = start + values.len();
+        let elements = tuple.inner_mut().get_mut(start..end);
+        values.swap_with_slice(elements.ok_or(Error::InvalidIndex(end))?);
+        Ok(())
+    }
+
     fn get_table(
         table: &arca::Table<Self>,
         index: usize,
@@ -174,4 +239,8 @@ impl arca::Runtime for Runtime {
     fn with_page_as_ref<T>(page: &arca::Page<Self>, f: impl FnOnce(&[u8]) -> T) -> T {
         f(page.inner())
     }
+
+    fn page_digest(page: &arca::Page<Self>) -> arca::Digest {
+        page.inner().digest()
+    }
 }
@@ -9,17 +9,23 @@ type TableImpossible = AugmentedPageTable<Impossible>;
 type Table2MB = AugmentedPageTable<PageTable2MB>;
 type Table1GB = AugmentedPageTable<PageTable1GB>;
 type Table512GB = AugmentedPageTable<PageTable512GB>;
-// type Table256TB = AugmentedPageTable<PageTable256TB>;
+type Table256TB = AugmentedPageTable<PageTable256TB>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Table {
     Table2MB(CowPage<Table2MB>),
     Table1GB(CowPage<Table1GB>),
     Table512GB(CowPage<Table512GB>),
+    /// A whole 48-bit address space, of which only the user half (the first 256 entries) can be
+    /// mapped.
+    Table256TB(CowPage<Table256TB>),
 }
 
 pub type Entry = arca::Entry<Runtime>;
 
+/// The entries of a 256 TiB table in the lower (user) half of the address space.
+pub const USER_ENTRIES: usize = 256;
+
 impl Table {
     pub fn new(size: usize) -> Table {
         if size <= Table2MB::SIZE {
@@ -28,6 +34,8 @@ impl Table {
             Table::Table1GB(Default::default())
         } else if size <= Table512GB::SIZE {
             Table::Table512GB(Default::default())
+        } else if size <= Table256TB::SIZE {
+            Table::Table256TB(Default::default())
         } else {
             panic!("attempted to create table with size {size}");
         }
@@ -46,6 +54,8 @@ impl Table {
             Table::Table2MB(table) => table.entry_mut(index).replace(entry.try_into()?).into(),
             Table::Table1GB(table) => table.entry_mut(index).replace(entry.try_into()?).into(),
             Table::Table512GB(table) => table.entry_mut(index).replace(entry.try_into()?).into(),
+            Table::Table256TB(_) if index >= USER_ENTRIES => return Err(entry),
+            Table::Table256TB(table) => table.entry_mut(index).replace(entry.try_into()?).into(),
         })
     }
 
@@ -63,6 +73,10 @@ impl Table {
                 .entry(index)
                 .map(|x| x.clone().unmap().into())
                 .unwrap_or_else(|| arca::Entry::Null(1 << 30)),
+            Table::Table256TB(table) => table
+                .entry(index)
+                .map(|x| x.clone().unmap().into())
+                .unwrap_or_else(|| arca::Entry::Null(1 << 39)),
         }
     }
 
@@ -71,6 +85,7 @@ impl Table {
             Table::Table2MB(_) => 1 << 21,
             Table::Table1GB(_) => 1 << 30,
             Table::Table512GB(_) => 1 << 39,
+            Table::Table256TB(_) => 1 << 48,
         }
     }
 }
@@ -166,6 +181,12 @@ impl From<CowPage<Table512GB>> for Table {
     }
 }
 
+impl From<CowPage<Table256TB>> for Table {
+    fn from(value: CowPage<Table256TB>) -> Self {
+        Table::Table256TB(value)
+    }
+}
+
 impl TryFrom<Table> for CowPage<TableImpossible> {
     type Error = Table;
 
@@ -219,6 +240,22 @@ mod tests {
 
         let large = Table::new((1 << 21) + 1);
         assert_eq!(large.size(), 1 << 30);
+
+        let whole = Table::new((1 << 39) + 1);
+        assert_eq!(whole.size(), 1 << 48);
+    }
+
+    /// Verifies a 256 TiB table holds 512 GiB tables in its user half only.
+    #[test]
+    fn test_256tb_entries() {
+        let mut table = Table::new(1 << 48);
+        assert_eq!(table.get(3), arca::Entry::Null(1 << 39));
+        let child = arca::Entry::RWTable(arca::Table::from_inner(Table::new(1 << 39)));
+        assert!(table.set(3, child.clone()).is_ok());
+        assert!(matches!(table.get(3), arca::Entry::RWTable(_)));
+        assert_eq!(table.set(USER_ENTRIES, child.clone()), Err(child));
+        let page = arca::Entry::RWPage(arca::Page::from_inner(Page::new(1)));
+        assert_eq!(table.set(4, page.clone()), Err(page));
     }
 
     /// Ensures empty table slots return the correct default Null entry.
@@ -238,4 +275,20 @@ mod tests {
         assert_eq!(old, arca::Entry::Null(1 << 12));
         assert_eq!(table.get(0), entry);
     }
+
+    /// Verifies byte access through a table crosses page boundaries, ignores read-only mappings
+    /// and stops at unmapped memory.
+    #[test]
+    fn test_read_write_bytes() {
+        let mut table = crate::types::Table::new(1 << 21);
+        let ro = arca::Entry::ROPage(arca::Page::from_inner(Page::new(1)));
+        let rw = arca::Entry::RWPage(arca::Page::from_inner(Page::new(1)));
+        table.map(0x1000, ro).unwrap();
+        table.map(0x2000, rw).unwrap();
+        assert_eq!(table.write_bytes(0x1ffe, &[1, 2, 3, 4]).unwrap(), 4);
+        let mut buf = [0; 4];
+        assert_eq!(table.read_bytes(0x1ffe, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(table.read_bytes(0x2ffe, &mut buf).unwrap(), 2);
+    }
 }
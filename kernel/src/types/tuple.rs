@@ -20,6 +20,13 @@ impl Tuple {
     pub fn into_inner(self) -> Box<[Value]> {
         self.contents
     }
+
+    /// Truncates this tuple, or extends it with nulls, to `len` elements.
+    pub fn resize(&mut self, len: usize) {
+        let mut contents = core::mem::take(&mut self.contents).into_vec();
+        contents.resize(len, Value::default());
+        self.contents = contents.into();
+    }
 }
 
 impl Deref for Tuple {
@@ -56,6 +63,20 @@ mod tests {
         assert!(matches!(tuple[1], Value::Null(_)));
     }
 
+    /// Verifies resize truncates and pads with Null values.
+    #[test]
+    fn test_resize() {
+        let mut tuple = Tuple::new(alloc::vec![
+            Value::Word(1u64.into()),
+            Value::Word(2u64.into())
+        ]);
+        tuple.resize(1);
+        assert_eq!(&*tuple, &[Value::Word(1u64.into())]);
+        tuple.resize(3);
+        assert_eq!(tuple.len(), 3);
+        assert!(matches!(tuple[2], Value::Null(_)));
+    }
+
     /// Verifies FromIterator collects values into a correctly sized tuple.
     #[test]
     fn test_from_iter() {
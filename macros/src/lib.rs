@@ -20,6 +20,8 @@ pub fn bench(attr: TokenStream, item: TokenStream) -> TokenStream {
     testing::bench(attr, item)
 }
 
+/// Samples the kernel while the function runs and writes the profile to a host file: the given
+/// path, or `<function>.folded`.  Paths ending in `.pb` or `.pprof` get a pprof profile.
 #[proc_macro_attribute]
 pub fn profile(attr: TokenStream, item: TokenStream) -> TokenStream {
     testing::profile(attr, item)
@@ -2,7 +2,7 @@ use proc_macro::TokenStream;
 use proc_macro2::Span;
 use proc_macro_crate::{crate_name, FoundCrate};
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, Ident, ItemFn};
+use syn::{parse_macro_input, Ident, ItemFn, LitStr};
 
 fn kernel_ident() -> Ident {
     let found_crate = crate_name("kernel").expect("kernel is present in `Cargo.toml`");
@@ -85,7 +85,12 @@ pub fn bench(_: TokenStream, item: TokenStream) -> TokenStream {
     .into()
 }
 
-pub fn profile(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn profile(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let path = if attr.is_empty() {
+        None
+    } else {
+        Some(parse_macro_input!(attr as LitStr))
+    };
     let item = parse_macro_input!(item as ItemFn);
     let ItemFn {
         attrs,
@@ -95,19 +100,24 @@ pub fn profile(_: TokenStream, item: TokenStream) -> TokenStream {
     } = item;
     let kernel = kernel_ident();
     let name = sig.ident.to_string();
+    let path = match path {
+        Some(path) => quote!(#path),
+        None => quote!(concat!(#name, ".folded")),
+    };
+    let call = if sig.asyncness.is_some() {
+        quote!((async || #block)().await)
+    } else {
+        quote!((|| #block)())
+    };
     quote! {
         #(#attrs)*
         #vis #sig {
             static __function_name: &str = const {
                 concat!(#name, "@", file!(), ":", line!())
             };
-            let mut f = async || #block;
-
-            let __start = #kernel::kvmclock::time_since_boot();
-            let result = f().await;
-            let end = #kernel::kvmclock::time_since_boot();
-            let duration = end - __start;
-            #kernel::aprofile::log_time_spent(&__function_name, duration);
+            let __session = #kernel::iprofile::Session::begin(__function_name, #path);
+            let result = #call;
+            __session.finish();
             result
         }
     }
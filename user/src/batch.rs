@@ -0,0 +1,116 @@
+//! Submitting several system calls at once.
+//!
+//! Every system call is a full exit to the kernel, so a program which makes many small ones (e.g.
+//! dropping each element of a tuple as it goes) spends most of its time switching.  A [`Batch`]
+//! collects system calls and submits them with one `batch` call; an entry can pass the result of
+//! an earlier one (e.g. a descriptor it created) as an argument.
+//!
+//! The runtime batches transparently where nothing needs an answer: dropping a [`Ref`] queues its
+//! `drop`, and the queue is submitted once it fills up (or by [`flush`]).  Until then the values
+//! stay alive in the kernel, where the program can no longer name them.  So that they are not
+//! captured with it, the queue is flushed before the program exits or captures its continuation
+//! (which performing an effect does too).
+//!
+//! [`Ref`]: crate::Ref
+
+use arcane::BatchEntry;
+use spin::Mutex;
+
+use crate::{ArcaError, syscall_result_raw};
+
+pub struct Batch<const N: usize> {
+    len: usize,
+    entries: [BatchEntry; N],
+}
+
+impl<const N: usize> Batch<N> {
+    pub const fn new() -> Self {
+        Batch {
+            len: 0,
+            entries: [BatchEntry::new(0, [0; 6]); N],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Adds `entry`, returning its index (for [`BatchEntry::with_ref`]), or gives it back if the
+    /// batch is full.
+    pub fn push(&mut self, entry: BatchEntry) -> Result<usize, BatchEntry> {
+        if self.is_full() {
+            return Err(entry);
+        }
+        self.entries[self.len] = entry;
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    /// Performs the entries in order and empties the batch, returning their results.  If one
+    /// fails, the ones after it are not performed, and the error is returned with its index.
+    pub fn submit(&mut self) -> Result<Results<'_>, (usize, ArcaError)> {
+        let len = core::mem::take(&mut self.len);
+        let entries = &mut self.entries[..len];
+        let done = unsafe { syscall_result_raw(arcane::arca_batch(entries.as_mut_ptr(), len)) };
+        let done = done.map_err(|e| (0, e))? as usize;
+        if done < len {
+            let error = syscall_result_raw(entries[done].result).unwrap_err();
+            return Err((done, error));
+        }
+        Ok(Results { entries })
+    }
+}
+
+impl<const N: usize> Default for Batch<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The results of a submitted [`Batch`], by entry.
+pub struct Results<'a> {
+    entries: &'a [BatchEntry],
+}
+
+impl Results<'_> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<i64> {
+        self.entries.get(index).map(|x| x.result)
+    }
+}
+
+static DEFERRED: Mutex<Batch<{ arcane::MAX_BATCH }>> = Mutex::new(Batch::new());
+
+/// Queues `entry`, which must not fail, to be submitted with the next full batch.
+pub(crate) fn defer(entry: BatchEntry) {
+    let mut deferred = DEFERRED.lock();
+    if deferred.is_full() {
+        deferred.submit().unwrap();
+    }
+    deferred.push(entry).unwrap();
+}
+
+/// Submits any system calls the runtime has queued.
+pub fn flush() {
+    let mut deferred = DEFERRED.lock();
+    if !deferred.is_empty() {
+        deferred.submit().unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! Decoding the exceptions raised by Arcane functions.
+//!
+//! A function which faults (or is killed by the debugger) returns the effect
+//! `("", "exception", info, backtrace, continuation)`: the symbolic function `""` applied to
+//!
+//! - the blob `"exception"`;
+//! - `info`, the tuple `(vector, error, address, description)`: the interrupt vector (null if the
+//!   function was stopped rather than faulting), the CPU's error code (or zero), the faulting
+//!   address of a page fault (or zero), and a blob describing the exception;
+//! - `backtrace`, a tuple of `(address, symbol, offset)` frames, innermost first;
+//! - `continuation`, the function stopped at the faulting instruction (or null), which runs that
+//!   instruction again when forced.
+//!
+//! A handler can fix whatever caused a page fault (e.g. by mapping the page into the
+//! continuation's table) and force the continuation to carry on.
+//!
+//! A function with [`arcane::flags::PAGE_FAULT_EFFECT`] set (see [`with_page_fault_effects`])
+//! instead performs `("", "page_fault", address, error, continuation)` when it page faults, which
+//! [`page_fault`] decodes; this is how demand paging is implemented in user space.
+
+use arcane::flags::PAGE_FAULT_EFFECT;
+
+use crate::prelude::*;
+
+pub const GENERAL_PROTECTION_FAULT: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+
+// page-fault error code bits
+const PRESENT: u64 = 1 << 0;
+const WRITE: u64 = 1 << 1;
+const RESERVED: u64 = 1 << 3;
+const INSTRUCTION: u64 = 1 << 4;
+
+#[derive(Debug)]
+pub struct Exception {
+    /// The interrupt vector, or `None` if the function was stopped rather than faulting.
+    pub vector: Option<u8>,
+    pub error: u64,
+    /// The faulting address of a page fault.
+    pub address: u64,
+    pub description: Blob,
+    pub backtrace: Tuple,
+    pub continuation: Option<Function>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PageFault {
+    pub address: u64,
+    pub access: Access,
+    /// Whether the page was mapped, so the access broke its permissions (e.g. a write to a
+    /// read-only page), rather than absent.
+    pub present: bool,
+    /// Whether a reserved bit was set in a page-table entry.
+    pub reserved: bool,
+}
+
+fn word(value: Value) -> Option<u64> {
+    Word::try_from(value).ok().map(|x| x.read())
+}
+
+impl PageFault {
+    /// Decodes a page fault at `address` from its error code.
+    pub fn new(address: u64, error: u64) -> PageFault {
+        let access = if error & INSTRUCTION != 0 {
+            Access::Execute
+        } else if error & WRITE != 0 {
+            Access::Write
+        } else {
+            Access::Read
+        };
+        PageFault {
+            address,
+            access,
+            present: error & PRESENT != 0,
+            reserved: error & RESERVED != 0,
+        }
+    }
+}
+
+/// The arguments of a symbolic effect named `name`, if `effect` is one.
+fn effect_args(effect: &Function, name: &[u8]) -> Option<Tuple> {
+    if !effect.is_symbolic() {
+        return None;
+    }
+    let Value::Tuple(function) = effect.read_cloned() else {
+        return None;
+    };
+    let Value::Tuple(args) = function.get(2) else {
+        return None;
+    };
+    let Value::Blob(x) = args.get(0) else {
+        return None;
+    };
+    x.with_ref(|x| x == name).then_some(args)
+}
+
+/// Decodes a `page_fault` effect into the fault and the continuation which retries the faulting
+/// instruction.
+pub fn page_fault(effect: &Function) -> Option<(PageFault, Function)> {
+    let mut args = effect_args(effect, b"page_fault")?;
+    if args.len() < 4 {
+        return None;
+    }
+    let fault = PageFault::new(word(args.get(1))?, word(args.get(2))?);
+    Some((fault, Function::try_from(args.take(3)).ok()?))
+}
+
+/// Makes an Arcane function perform `page_fault` effects when it page faults, instead of raising
+/// exceptions.
+pub fn with_page_fault_effects(f: Function) -> Option<Function> {
+    if !f.is_arcane() {
+        return None;
+    }
+    let Value::Tuple(mut function) = f.read() else {
+        return None;
+    };
+    let Value::Tuple(mut data) = function.take(1) else {
+        return None;
+    };
+    let mut extended = Tuple::new(data.len().max(6));
+    for i in 0..data.len() {
+        extended.set(i, data.take(i));
+    }
+    let flags = word(extended.get(5)).unwrap_or(0);
+    extended.set(5, Word::new(flags | PAGE_FAULT_EFFECT));
+    function.set(1, extended);
+    Function::new(function).ok()
+}
+
+impl Exception {
+    /// Decodes the effect returned by forcing a function, if it is an exception.
+    pub fn decode(effect: &Function) -> Option<Exception> {
+        let mut args = effect_args(effect, b"exception")?;
+        if args.len() < 4 {
+            return None;
+        }
+        let Value::Tuple(info) = args.take(1) else {
+            return None;
+        };
+        let vector = match info.get(0) {
+            Value::Null(_) => None,
+            x => Some(word(x)? as u8),
+        };
+        Some(Exception {
+            vector,
+            error: word(info.get(1))?,
+            address: word(info.get(2))?,
+            description: Blob::try_from(info.get(3)).ok()?,
+            backtrace: Tuple::try_from(args.take(2)).ok()?,
+            continuation: Function::try_from(args.take(3)).ok(),
+        })
+    }
+
+    /// The details of a page fault, decoded from its error code.
+    pub fn page_fault(&self) -> Option<PageFault> {
+        if self.vector != Some(PAGE_FAULT) {
+            return None;
+        }
+        Some(PageFault::new(self.address, self.error))
+    }
+}
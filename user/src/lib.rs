@@ -6,10 +6,13 @@ use core::{
     fmt::Write,
     marker::PhantomData,
     num::NonZero,
+    ops::Range,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+pub mod batch;
 pub mod error;
+pub mod exception;
 pub mod os;
 pub mod prelude;
 pub use arca;
@@ -34,6 +37,7 @@ pub enum ArcaError {
     BadType,
     BadArgument,
     Interrupted,
+    PermissionDenied,
     Unknown(u32),
 }
 
@@ -48,6 +52,7 @@ fn syscall_result_raw(value: i64) -> Result<u32, ArcaError> {
             arcane::__ERR_bad_type => ArcaError::BadType,
             arcane::__ERR_bad_argument => ArcaError::BadArgument,
             arcane::__ERR_interrupted => ArcaError::Interrupted,
+            arcane::__ERR_permission_denied => ArcaError::PermissionDenied,
             x => ArcaError::Unknown(x),
         })
     }
@@ -71,10 +76,9 @@ impl Clone for Ref {
 
 impl Drop for Ref {
     fn drop(&mut self) {
-        if let Some(idx) = self.idx.take() {
-            unsafe {
-                syscall_result_raw(arca_drop(idx as i64)).unwrap();
-            }
+        // descriptor 0 is always null, and dropping anything else needs no answer
+        if let Some(idx) = self.idx.take().filter(|&idx| idx != 0) {
+            batch::defer(BatchEntry::new(__NR_drop, [idx as u64, 0, 0, 0, 0, 0]));
         }
     }
 }
@@ -91,6 +95,12 @@ impl Ref {
     pub fn as_raw(&self) -> u32 {
         self.idx.unwrap()
     }
+
+    /// Takes away the rights (see [`arcane::rights`]) this descriptor grants which are not in
+    /// `rights`, e.g. before passing its value to another function.
+    pub fn restrict(&self, rights: u64) -> Result<(), ArcaError> {
+        syscall_result_raw(unsafe { arca_restrict(self.as_raw() as i64, rights) }).map(|_| ())
+    }
 }
 
 impl arca::Runtime for Runtime {
@@ -221,6 +231,121 @@ impl arca::Runtime for Runtime {
         }
     }
 
+    fn slice_blob(
+        blob: &arca::Blob<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Blob<Self>, Self::Error> {
+        unsafe {
+            Ok(arca::Blob::from_inner(syscall_result(arcane::arca_slice(
+                blob.inner().as_raw() as i64,
+                range.start,
+                range.end,
+            ))?))
+        }
+    }
+
+    fn concat_blobs(first: &arca::Blob<Self>, second: &arca::Blob<Self>) -> arca::Blob<Self> {
+        unsafe {
+            arca::Blob::from_inner(
+                syscall_result(arcane::arca_concat(
+                    first.inner().as_raw() as i64,
+                    second.inner().as_raw() as i64,
+                ))
+                .unwrap(),
+            )
+        }
+    }
+
+    fn resize_blob(blob: &mut arca::Blob<Self>, len: usize) {
+        unsafe {
+            syscall_result_raw(arcane::arca_resize(blob.inner().as_raw() as i64, len)).unwrap();
+        }
+    }
+
+    fn slice_tuple(
+        tuple: &arca::Tuple<Self>,
+        range: Range<usize>,
+    ) -> Result<arca::Tuple<Self>, Self::Error> {
+        unsafe {
+            Ok(arca::Tuple::from_inner(syscall_result(
+                arcane::arca_slice(tuple.inner().as_raw() as i64, range.start, range.end),
+            )?))
+        }
+    }
+
+    fn concat_tuples(first: &arca::Tuple<Self>, second: &arca::Tuple<Self>) -> arca::Tuple<Self> {
+        unsafe {
+            arca::Tuple::from_inner(
+                syscall_result(arcane::arca_concat(
+                    first.inner().as_raw() as i64,
+                    second.inner().as_raw() as i64,
+                ))
+                .unwrap(),
+            )
+        }
+    }
+
+    fn resize_tuple(tuple: &mut arca::Tuple<Self>, len: usize) {
+        unsafe {
+            syscall_result_raw(arcane::arca_resize(tuple.inner().as_raw() as i64, len)).unwrap();
+        }
+    }
+
+    fn get_tuple_many(
+        tuple: &arca::Tuple<Self>,
+        start: usize,
+        values: &mut [arca::Value<Self>],
+    ) -> Result<(), Self::Error> {
+        if start + values.len() > tuple.len() {
+            return Err(ArcaError::BadIndex);
+        }
+        let mut raw = [0i64; 64];
+        for (i, chunk) in values.chunks_mut(raw.len()).enumerate() {
+            let raw = &mut raw[..chunk.len()];
+            unsafe {
+                syscall_result_raw(arcane::arca_tuple_get_many(
+                    tuple.inner().as_raw() as i64,
+                    start + i * 64,
+                    raw.as_mut_ptr(),
+                    raw.len(),
+                ))?;
+                for (value, &descriptor) in chunk.iter_mut().zip(raw.iter()) {
+                    *value = Self::raw_convert(Ref::from_raw(descriptor as u32));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_tuple_many(
+        tuple: &mut arca::Tuple<Self>,
+        start: usize,
+        values: &mut [arca::Value<Self>],
+    ) -> Result<(), Self::Error> {
+        if start + values.len() > tuple.len() {
+            return Err(ArcaError::BadIndex);
+        }
+        let mut raw = [0i64; 64];
+        for (i, chunk) in values.chunks_mut(raw.len()).enumerate() {
+            let raw = &mut raw[..chunk.len()];
+            unsafe {
+                for (descriptor, value) in raw.iter_mut().zip(chunk.iter_mut()) {
+                    *descriptor = Self::get_raw(core::mem::take(value)).into_raw() as i64;
+                }
+                syscall_result_raw(arcane::arca_tuple_set_many(
+                    tuple.inner().as_raw() as i64,
+                    start + i * 64,
+                    raw.as_mut_ptr(),
+                    raw.len(),
+                ))?;
+                for (value, &descriptor) in chunk.iter_mut().zip(raw.iter()) {
+                    *value = Self::raw_convert(Ref::from_raw(descriptor as u32));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn get_table(
         tuple: &arca::Table<Self>,
         index: usize,
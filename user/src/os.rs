@@ -16,6 +16,7 @@ pub fn argument() -> Value {
 pub fn exit(value: impl Into<Value>) -> ! {
     unsafe {
         let val = super::Runtime::get_raw(value.into()).into_raw();
+        crate::batch::flush();
         loop {
             arca_exit(val.into())
         }
@@ -24,10 +25,9 @@ pub fn exit(value: impl Into<Value>) -> ! {
 
 pub fn call_with_current_continuation(f: Function) -> Value {
     unsafe {
-        syscall_result_raw(arca_call_with_current_continuation(
-            f.into_inner().into_raw() as i64,
-        ))
-        .unwrap();
+        let f = f.into_inner().into_raw();
+        crate::batch::flush();
+        syscall_result_raw(arca_call_with_current_continuation(f as i64)).unwrap();
         os::argument()
     }
 }
@@ -36,6 +36,7 @@ pub struct Error;
 
 pub fn continuation() -> Result<Function, Error> {
     unsafe {
+        crate::batch::flush();
         let result = arca_get_continuation();
         if result <= 0 {
             Err(Error)
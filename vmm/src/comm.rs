@@ -1,12 +1,21 @@
-use crate::pipe::{ControlPipe, FilePipe, ListenerPipe, StreamPipe};
+use crate::journal::{Channel, Journal};
+use crate::pipe::{
+    ControlPipe, DebugPipe, FilePipe, GuestPipe, ListenerPipe, StreamPipe, TypedPipe,
+};
+use crate::shutdown::Shutdown;
+use crate::snapshot::{self, Gate, Resource, Service};
 use common::protocol::control::PipeData;
 use common::BuddyAllocator;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::Arc;
+use std::time::Duration;
 
-fn decompose_pipe(pipe: common::pipe::Pipe) -> PipeData {
+/// How often blocked host sockets check whether the VMM is shutting down.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn decompose_pipe(pipe: common::pipe::Pipe) -> PipeData {
     let (rx, tx) = pipe.into_inner();
     let rx = rx.into_inner();
     let tx = tx.into_inner();
@@ -22,102 +31,299 @@ fn decompose_pipe(pipe: common::pipe::Pipe) -> PipeData {
     }
 }
 
-pub fn control_thread(argv: Vec<String>, mut pipe: ControlPipe) {
-    use common::protocol::control::*;
-    loop {
-        let response = match pipe.recv() {
-            Request::GetArgs => Response::Args(argv.clone()),
-            Request::Exit(code) => std::process::exit(code),
-            Request::Open(path, mode) => {
-                let f = OpenOptions::new()
-                    .read(mode.read)
-                    .write(mode.write)
-                    .create(mode.create)
-                    .append(mode.append)
-                    .truncate(mode.truncate)
-                    .open(path);
-                match f {
-                    Ok(f) => {
-                        let (p, q) = common::pipe::pipe(1024);
-                        std::thread::spawn(move || {
-                            let pipe = crate::pipe::GuestPipe::new(q);
-                            file_thread(f, FilePipe::new(pipe));
-                        });
-                        Response::Pipe(decompose_pipe(p))
+/// Rebuilds a pipe from [`decompose_pipe`]'s description of it.
+///
+/// # Safety
+///
+/// `pipe` must describe a pipe end that is not otherwise in use.
+pub unsafe fn compose_pipe(pipe: &PipeData) -> common::pipe::Pipe {
+    use common::pipe::{Pipe, Reader, Writer};
+    let rxp: *const u8 = BuddyAllocator.from_offset(pipe.rx_ptr);
+    let txp: *const u8 = BuddyAllocator.from_offset(pipe.tx_ptr);
+    let rx = Arc::from_raw_in(std::ptr::from_raw_parts(rxp, pipe.rx_len), BuddyAllocator);
+    let tx = Arc::from_raw_in(std::ptr::from_raw_parts(txp, pipe.tx_len), BuddyAllocator);
+    Pipe::from_inner(Reader::from_inner(rx), Writer::from_inner(tx))
+}
+
+/// The journal channel a service thread responds on, and the channels of the pipes it opens.
+#[derive(Debug)]
+pub struct Context {
+    journal: Arc<Journal>,
+    shutdown: Arc<Shutdown>,
+    gate: Arc<Gate>,
+    channel: Channel,
+    children: u32,
+}
+
+impl Context {
+    pub fn new(journal: Arc<Journal>, shutdown: Arc<Shutdown>, gate: Arc<Gate>) -> Self {
+        Context {
+            journal,
+            shutdown,
+            gate,
+            channel: vec![],
+            children: 0,
+        }
+    }
+
+    fn child(&mut self) -> Context {
+        let mut channel = self.channel.clone();
+        channel.push(self.children);
+        self.children += 1;
+        Context {
+            journal: self.journal.clone(),
+            shutdown: self.shutdown.clone(),
+            gate: self.gate.clone(),
+            channel,
+            children: 0,
+        }
+    }
+
+    fn replaying(&self) -> bool {
+        self.journal.is_replaying()
+    }
+
+    /// Replays the next response, or produces one with `f` and records it; `None` means the VMM
+    /// is shutting down.
+    fn respond<T: serde::Serialize + serde::de::DeserializeOwned>(
+        &self,
+        f: impl FnOnce() -> Option<T>,
+    ) -> Option<T> {
+        if self.replaying() {
+            return Some(self.journal.next(&self.channel));
+        }
+        let response = f()?;
+        self.journal.append(&self.channel, &response);
+        Some(response)
+    }
+
+    /// Spawns a service for `resource` on a new pipe, returning the guest's end; `file` shares
+    /// the offset of a served file.
+    fn spawn(
+        &mut self,
+        resource: Resource,
+        file: Option<File>,
+        f: impl FnOnce(GuestPipe, Context) + Send + 'static,
+    ) -> PipeData {
+        let (p, q) = common::pipe::pipe(1024);
+        let guest = decompose_pipe(p);
+        let service = self
+            .gate
+            .register(snapshot::mirror(&guest), resource, file, None);
+        let ctx = self.child();
+        let pipe = GuestPipe::new(q, self.shutdown.clone()).register(service);
+        self.shutdown
+            .track(std::thread::spawn(move || f(pipe, ctx)));
+        guest
+    }
+
+    /// Retries a host operation that times out periodically until it succeeds or the VMM is
+    /// shutting down.
+    fn retry<T>(&self, mut f: impl FnMut() -> std::io::Result<T>) -> Option<T> {
+        loop {
+            match f() {
+                Ok(x) => return Some(x),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.shutdown.requested() {
+                        return None;
                     }
-                    Err(e) => Response::Err(e.kind().into()),
                 }
+                Err(e) => panic!("host I/O failed: {e}"),
             }
-            Request::Mkdir(path) => match std::fs::create_dir_all(&path) {
-                Ok(()) => Response::Ack,
-                Err(e) => Response::Err(e.kind().into()),
-            },
-            Request::Listen { ip, port } => {
-                let listener = TcpListener::bind(SocketAddr::from((ip, port))).unwrap();
-                let (p, q) = common::pipe::pipe(1024);
-                std::thread::spawn(move || {
-                    let pipe = crate::pipe::GuestPipe::new(q);
-                    listener_thread(listener, ListenerPipe::new(pipe));
-                });
-                Response::Pipe(decompose_pipe(p))
-            }
-            Request::Connect { host, port } => {
-                let stream = TcpStream::connect((host.as_str(), port)).unwrap();
-                let (p, q) = common::pipe::pipe(1024);
-                std::thread::spawn(move || {
-                    let pipe = crate::pipe::GuestPipe::new(q);
-                    stream_thread(stream, StreamPipe::new(pipe));
-                });
-                Response::Pipe(decompose_pipe(p))
+        }
+    }
+}
+
+/// Opens a debug pipe served by a gdb stub on `port`.
+///
+/// Debug sessions are interactive, so they are neither recorded nor replayed (and their pipes are
+/// not part of the journal's channel numbering).
+fn debugger(port: Option<u16>, ctx: &Context) -> common::protocol::control::Response {
+    use common::protocol::control::*;
+    let shutdown = &ctx.shutdown;
+    let Some(port) = port else {
+        return Response::Err(IoErrorKind::Unsupported);
+    };
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("could not listen for gdb on port {port}: {e}");
+            return Response::Err(e.kind().into());
+        }
+    };
+    listener.set_nonblocking(true).unwrap();
+    let (p, q) = common::pipe::pipe(1024);
+    let guest = decompose_pipe(p);
+    let service = ctx
+        .gate
+        .register(snapshot::mirror(&guest), Resource::Debugger, None, None);
+    let pipe = DebugPipe::new(GuestPipe::new(q, shutdown.clone()).register(service));
+    let stub = shutdown.clone();
+    shutdown.track(std::thread::spawn(move || {
+        crate::gdb::serve(listener, pipe, stub)
+    }));
+    Response::Pipe(guest)
+}
+
+pub fn control_thread(
+    argv: Vec<String>,
+    gdb: Option<u16>,
+    mut pipe: ControlPipe,
+    mut ctx: Context,
+) {
+    use common::protocol::control::*;
+    while let Some(request) = pipe.recv() {
+        if let Request::Exit(code) = request {
+            ctx.shutdown.request(code);
+            return;
+        }
+        if let Request::Debugger = request {
+            pipe.send(&debugger(gdb, &ctx));
+            continue;
+        }
+        let response = if ctx.replaying() {
+            let response: Response = ctx.journal.next(&ctx.channel);
+            match (request, response) {
+                (Request::Open(path, mode), Response::Pipe(_)) => {
+                    let resource = file_resource(path, &mode);
+                    Response::Pipe(ctx.spawn(resource, None, |pipe, ctx| {
+                        file_thread(None, FilePipe::new(pipe), ctx)
+                    }))
+                }
+                (Request::Listen { ip, port }, Response::Pipe(_)) => {
+                    let resource = Resource::Listener(SocketAddr::from((ip, port)));
+                    Response::Pipe(ctx.spawn(resource, None, |pipe, ctx| {
+                        listener_thread(None, ListenerPipe::new(pipe), ctx)
+                    }))
+                }
+                (Request::Connect { .. }, Response::Pipe(_)) => {
+                    Response::Pipe(ctx.spawn(Resource::Stream, None, |pipe, ctx| {
+                        stream_thread(None, StreamPipe::new(pipe), ctx)
+                    }))
+                }
+                (_, response) => response,
             }
+        } else {
+            let response = match request {
+                Request::GetArgs => Response::Args(argv.clone()),
+                Request::Exit(_) | Request::Debugger => unreachable!(),
+                Request::Open(path, mode) => {
+                    let f = OpenOptions::new()
+                        .read(mode.read)
+                        .write(mode.write)
+                        .create(mode.create)
+                        .append(mode.append)
+                        .truncate(mode.truncate)
+                        .open(&path);
+                    match f {
+                        Ok(f) => {
+                            let resource = file_resource(path, &mode);
+                            let offset = f.try_clone().ok();
+                            Response::Pipe(ctx.spawn(resource, offset, |pipe, ctx| {
+                                file_thread(Some(f), FilePipe::new(pipe), ctx)
+                            }))
+                        }
+                        Err(e) => Response::Err(e.kind().into()),
+                    }
+                }
+                Request::Mkdir(path) => match std::fs::create_dir_all(&path) {
+                    Ok(()) => Response::Ack,
+                    Err(e) => Response::Err(e.kind().into()),
+                },
+                Request::Listen { ip, port } => {
+                    let listener = TcpListener::bind(SocketAddr::from((ip, port))).unwrap();
+                    listener.set_nonblocking(true).unwrap();
+                    let resource = Resource::Listener(listener.local_addr().unwrap());
+                    Response::Pipe(ctx.spawn(resource, None, |pipe, ctx| {
+                        listener_thread(Some(listener), ListenerPipe::new(pipe), ctx)
+                    }))
+                }
+                Request::Connect { host, port } => {
+                    let stream = TcpStream::connect((host.as_str(), port)).unwrap();
+                    Response::Pipe(ctx.spawn(Resource::Stream, None, |pipe, ctx| {
+                        stream_thread(Some(stream), StreamPipe::new(pipe), ctx)
+                    }))
+                }
+            };
+            ctx.journal.append(&ctx.channel, &response);
+            response
         };
         pipe.send(&response);
     }
 }
 
-pub fn file_thread(mut file: File, mut pipe: FilePipe) {
+/// Serves a file to the guest; `file` is `None` when replaying.
+pub fn file_thread(mut file: Option<File>, mut pipe: FilePipe, ctx: Context) {
     use common::protocol::file::*;
-    loop {
-        let response = match pipe.recv() {
-            Request::Read(len) => {
-                let mut buf = vec![0; len];
-                let len = file.read(&mut buf).unwrap();
-                buf.truncate(len);
-                Response::Bytes(buf)
-            }
-            Request::Write(bytes) => {
-                let len = file.write(&bytes).unwrap();
-                Response::Length(len)
-            }
-            Request::Seek(whence) => {
-                let from = match whence {
-                    Whence::Start(x) => SeekFrom::Start(x),
-                    Whence::Current(x) => SeekFrom::Current(x),
-                    Whence::End(x) => SeekFrom::End(x),
-                };
-                let offset = file.seek(from).unwrap();
-                Response::Offset(offset)
-            }
-            Request::Close => {
-                return;
-            }
+    while let Some(request) = pipe.recv() {
+        if let Request::Close = request {
+            break;
+        }
+        let Some(response) = ctx.respond(|| {
+            let file = file.as_mut().unwrap();
+            Some(match request {
+                Request::Read(len) => {
+                    let mut buf = vec![0; len];
+                    let len = file.read(&mut buf).unwrap();
+                    buf.truncate(len);
+                    Response::Bytes(buf)
+                }
+                Request::Write(bytes) => {
+                    let len = file.write(&bytes).unwrap();
+                    Response::Length(len)
+                }
+                Request::Seek(whence) => {
+                    let from = match whence {
+                        Whence::Start(x) => SeekFrom::Start(x),
+                        Whence::Current(x) => SeekFrom::Current(x),
+                        Whence::End(x) => SeekFrom::End(x),
+                    };
+                    let offset = file.seek(from).unwrap();
+                    Response::Offset(offset)
+                }
+                Request::Close => unreachable!(),
+            })
+        }) else {
+            break;
         };
         pipe.send(&response);
     }
 }
 
-pub fn listener_thread(listener: TcpListener, mut pipe: ListenerPipe) {
+/// Serves a TCP listener to the guest; `listener` is `None` when replaying.
+pub fn listener_thread(listener: Option<TcpListener>, mut pipe: ListenerPipe, mut ctx: Context) {
     use common::protocol::listener::*;
-    loop {
-        let response = match pipe.recv() {
+    while let Some(request) = pipe.recv() {
+        let response = match request {
             Request::Accept => {
-                let (stream, _) = listener.accept().unwrap();
-                let (p, q) = common::pipe::pipe(1024);
-                std::thread::spawn(move || {
-                    let pipe = crate::pipe::GuestPipe::new(q);
-                    stream_thread(stream, StreamPipe::new(pipe));
-                });
-                Response::Pipe(decompose_pipe(p))
+                if !ctx.replaying() {
+                    let listener = listener.as_ref().unwrap();
+                    let Some((stream, _)) = ctx.retry(|| {
+                        let result = listener.accept();
+                        if result.is_err() {
+                            std::thread::sleep(POLL_INTERVAL);
+                        }
+                        result
+                    }) else {
+                        return;
+                    };
+                    stream.set_nonblocking(false).unwrap();
+                    let response =
+                        Response::Pipe(ctx.spawn(Resource::Stream, None, |pipe, ctx| {
+                            stream_thread(Some(stream), StreamPipe::new(pipe), ctx)
+                        }));
+                    ctx.journal.append(&ctx.channel, &response);
+                    response
+                } else {
+                    match ctx.journal.next(&ctx.channel) {
+                        Response::Pipe(_) => {
+                            Response::Pipe(ctx.spawn(Resource::Stream, None, |pipe, ctx| {
+                                stream_thread(None, StreamPipe::new(pipe), ctx)
+                            }))
+                        }
+                        response => response,
+                    }
+                }
             }
             Request::Close => {
                 return;
@@ -127,24 +333,151 @@ pub fn listener_thread(listener: TcpListener, mut pipe: ListenerPipe) {
     }
 }
 
-pub fn stream_thread(mut stream: TcpStream, mut pipe: StreamPipe) {
+/// Serves a TCP stream to the guest; `stream` is `None` when replaying.
+pub fn stream_thread(mut stream: Option<TcpStream>, mut pipe: StreamPipe, ctx: Context) {
     use common::protocol::stream::*;
-    loop {
-        let response = match pipe.recv() {
-            Request::Receive(len) => {
-                let mut buf = vec![0; len];
-                let len = stream.read(&mut buf).unwrap();
-                buf.truncate(len);
-                Response::Bytes(buf)
-            }
-            Request::Send(bytes) => {
-                let len = stream.write(&bytes).unwrap();
-                Response::Length(len)
-            }
-            Request::Close => {
-                return;
-            }
+    if let Some(stream) = &stream {
+        stream.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+    }
+    while let Some(request) = pipe.recv() {
+        if let Request::Close = request {
+            return;
+        }
+        let Some(response) = ctx.respond(|| {
+            let stream = stream.as_mut().unwrap();
+            Some(match request {
+                Request::Receive(len) => {
+                    let mut buf = vec![0; len];
+                    let len = ctx.retry(|| stream.read(&mut buf))?;
+                    buf.truncate(len);
+                    Response::Bytes(buf)
+                }
+                Request::Send(bytes) => {
+                    let len = stream.write(&bytes).unwrap();
+                    Response::Length(len)
+                }
+                Request::Close => unreachable!(),
+            })
+        }) else {
+            return;
+        };
+        pipe.send(&response);
+    }
+}
+
+/// What to reopen a file with after a restore; it must not be created or truncated again.
+fn file_resource(path: String, mode: &common::protocol::control::FileMode) -> Resource {
+    Resource::File {
+        path: path.into(),
+        read: mode.read,
+        write: mode.write,
+        append: mode.append,
+        offset: 0,
+    }
+}
+
+/// Answers requests on a restored pipe whose host resource is gone, until `answer` has nothing
+/// to say (when the guest closes it).
+fn orphan_thread<S: serde::Serialize, R: for<'a> serde::Deserialize<'a>>(
+    mut pipe: TypedPipe<S, R>,
+    answer: impl Fn(R) -> Option<S>,
+) {
+    while let Some(request) = pipe.recv() {
+        let Some(response) = answer(request) else {
+            return;
         };
         pipe.send(&response);
     }
 }
+
+/// Respawns a host service saved in a snapshot (other than the control service, which the runtime
+/// starts itself).
+///
+/// # Safety
+///
+/// `service.pipe` must be the host end of a pipe in the restored guest memory.
+pub unsafe fn restore(service: Service, ctx: &mut Context) {
+    let Service {
+        pipe: host,
+        resource,
+        pending,
+    } = service;
+    let pipe = GuestPipe::new(compose_pipe(&host), ctx.shutdown.clone());
+    let child = ctx.child();
+    let thread = match resource.clone() {
+        Resource::Control => unreachable!("the control service is restored by the runtime"),
+        Resource::File {
+            path,
+            read,
+            write,
+            append,
+            offset,
+        } => {
+            let file = OpenOptions::new()
+                .read(read)
+                .write(write)
+                .append(append)
+                .open(&path)
+                .and_then(|mut f| f.seek(SeekFrom::Start(offset)).map(|_| f));
+            let file = match file {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    log::error!("could not reopen {}: {e}", path.display());
+                    None
+                }
+            };
+            let offset = file.as_ref().and_then(|f| f.try_clone().ok());
+            let pipe =
+                FilePipe::new(pipe.register(ctx.gate.register(host, resource, offset, pending)));
+            match file {
+                Some(file) => std::thread::spawn(move || file_thread(Some(file), pipe, child)),
+                None => std::thread::spawn(move || {
+                    use common::protocol::file::*;
+                    orphan_thread(pipe, |request| match request {
+                        Request::Read(_) => Some(Response::Bytes(vec![])),
+                        Request::Write(_) => Some(Response::Length(0)),
+                        Request::Seek(_) => Some(Response::Offset(0)),
+                        Request::Close => None,
+                    })
+                }),
+            }
+        }
+        Resource::Listener(address) => {
+            let pipe =
+                ListenerPipe::new(pipe.register(ctx.gate.register(host, resource, None, pending)));
+            match TcpListener::bind(address) {
+                Ok(listener) => {
+                    listener.set_nonblocking(true).unwrap();
+                    std::thread::spawn(move || listener_thread(Some(listener), pipe, child))
+                }
+                Err(e) => {
+                    // leave the pipe open but unserved, so the guest waits for a connection
+                    // which never comes
+                    log::error!("could not listen on {address} again: {e}");
+                    std::mem::forget(pipe);
+                    return;
+                }
+            }
+        }
+        Resource::Stream => {
+            let pipe =
+                StreamPipe::new(pipe.register(ctx.gate.register(host, resource, None, pending)));
+            std::thread::spawn(move || {
+                use common::protocol::stream::*;
+                orphan_thread(pipe, |request| match request {
+                    Request::Receive(_) => Some(Response::Bytes(vec![])),
+                    Request::Send(_) => Some(Response::Length(0)),
+                    Request::Close => None,
+                })
+            })
+        }
+        Resource::Debugger => {
+            let pipe =
+                DebugPipe::new(pipe.register(ctx.gate.register(host, resource, None, pending)));
+            std::thread::spawn(move || {
+                orphan_thread(pipe, |_| Some(common::protocol::debug::Command::Detach))
+            })
+        }
+    };
+    ctx.shutdown.track(thread);
+}
@@ -0,0 +1,374 @@
+//! A GDB remote serial protocol stub for debugging Arcane functions.
+//!
+//! The kernel opens a debug pipe (with `Request::Debugger`) when it starts forcing a function under
+//! the debugger, and reports every stop on it; this stub waits for gdb to connect, then translates
+//! gdb's packets into [`Command`]s on the pipe.  Only what stock gdb needs for a single-threaded
+//! amd64 target is supported: registers, memory, software breakpoints, continue and single-step.
+
+use std::{
+    fmt::Write as _,
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use common::protocol::debug::{Command, Event, Stop, REGISTERS};
+
+use crate::{pipe::DebugPipe, shutdown::Shutdown};
+
+/// How often a blocked stub checks whether the VMM is shutting down.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The index into our register file of each of gdb's amd64 general-purpose registers, in gdb's
+/// order (`rax rbx rcx rdx rsi rdi rbp rsp r8`–`r15 rip`).
+const GDB_REGISTERS: [usize; 17] = [0, 3, 1, 2, 6, 7, 5, 4, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+const RFLAGS: usize = 17;
+/// gdb's `cs ss ds es fs gs`, which we report as zero.
+const SEGMENTS: usize = 6;
+
+/// Encodes our register file as the body of a `g` reply.
+pub fn encode_registers(registers: &[u64]) -> String {
+    let mut out = String::new();
+    for &i in GDB_REGISTERS.iter() {
+        out.push_str(&hex(&registers[i].to_le_bytes()));
+    }
+    out.push_str(&hex(&(registers[RFLAGS] as u32).to_le_bytes()));
+    for _ in 0..SEGMENTS {
+        out.push_str(&hex(&0u32.to_le_bytes()));
+    }
+    out
+}
+
+/// Decodes the body of a `G` packet into `registers`, ignoring the segment registers.
+pub fn decode_registers(body: &str, registers: &mut [u64]) -> Option<()> {
+    let bytes = unhex(body)?;
+    for (j, &i) in GDB_REGISTERS.iter().enumerate() {
+        registers[i] = u64::from_le_bytes(bytes.get(j * 8..j * 8 + 8)?.try_into().ok()?);
+    }
+    let offset = GDB_REGISTERS.len() * 8;
+    registers[RFLAGS] = u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as u64;
+    Some(())
+}
+
+/// Frames a packet body as `$body#checksum`.
+pub fn frame(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |x, y| x.wrapping_add(y));
+    format!("${body}#{checksum:02x}")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(out, "{byte:02x}").unwrap();
+    }
+    out
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses `addr,len` (both hex).
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (address, len) = s.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// The stop reply gdb expects for a stop.
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Entry | Stop::Step => "S05".into(),
+        Stop::Breakpoint => "T05swbreak:;".into(),
+        Stop::Exception(vector) => {
+            let signal = match vector {
+                0 | 16 | 19 => 8, // SIGFPE
+                1 | 3 => 5,       // SIGTRAP
+                6 => 4,           // SIGILL
+                17 => 7,          // SIGBUS
+                _ => 11,          // SIGSEGV
+            };
+            format!("S{signal:02x}")
+        }
+        Stop::Exited => "W00".into(),
+    }
+}
+
+/// What to do after handling a packet.
+enum Next {
+    Reply(String),
+    /// The packet has already been answered.
+    Replied,
+    /// Resume the guest, replying once it stops again.
+    Resume(Command),
+    /// Send the reply and end the session.
+    Finish(String),
+}
+
+struct Stub {
+    pipe: DebugPipe,
+    stream: TcpStream,
+    shutdown: Arc<Shutdown>,
+    stop: Stop,
+    ack: bool,
+}
+
+/// Waits for gdb to connect to `listener`, then serves it until it detaches or the function
+/// exits.
+pub fn serve(listener: TcpListener, mut pipe: DebugPipe, shutdown: Arc<Shutdown>) {
+    let Some(Event::Stopped(stop)) = pipe.recv() else {
+        return;
+    };
+    if let Ok(address) = listener.local_addr() {
+        log::info!("waiting for gdb on {address}");
+    }
+    let stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if shutdown.requested() {
+                    return;
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => {
+                log::error!("could not accept gdb connection: {e}");
+                pipe.send(&Command::Detach);
+                return;
+            }
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+    let mut stub = Stub {
+        pipe,
+        stream,
+        shutdown,
+        stop,
+        ack: true,
+    };
+    stub.run();
+}
+
+impl Stub {
+    fn run(&mut self) {
+        loop {
+            let Some(packet) = self.packet() else {
+                // gdb went away; let the function finish on its own
+                if !self.shutdown.requested() {
+                    self.pipe.send(&Command::Detach);
+                }
+                return;
+            };
+            let next = match self.handle(&packet) {
+                Some(next) => next,
+                None => return,
+            };
+            match next {
+                Next::Reply(reply) => self.reply(&reply),
+                Next::Replied => {}
+                Next::Resume(command) => {
+                    let Some(Event::Stopped(stop)) = self.command(command) else {
+                        return;
+                    };
+                    self.stop = stop;
+                    self.reply(&stop_reply(stop));
+                    if stop == Stop::Exited {
+                        self.pipe.send(&Command::Detach);
+                        return;
+                    }
+                }
+                Next::Finish(reply) => {
+                    self.reply(&reply);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends a command to the kernel and waits for its answer; `None` means the VMM is shutting
+    /// down.
+    fn command(&mut self, command: Command) -> Option<Event> {
+        self.pipe.send(&command);
+        self.pipe.recv()
+    }
+
+    fn handle(&mut self, packet: &str) -> Option<Next> {
+        let reply = |x: &str| Some(Next::Reply(x.into()));
+        if packet.is_empty() || !packet.is_char_boundary(1) {
+            return reply("");
+        }
+        let (kind, body) = packet.split_at(1);
+        match kind {
+            "?" => reply(&stop_reply(self.stop)),
+            "g" => match self.command(Command::ReadRegisters)? {
+                Event::Registers(registers) if registers.len() == REGISTERS => {
+                    reply(&encode_registers(&registers))
+                }
+                _ => reply("E01"),
+            },
+            "G" => {
+                let Event::Registers(mut registers) = self.command(Command::ReadRegisters)? else {
+                    return reply("E01");
+                };
+                if decode_registers(body, &mut registers).is_none() {
+                    return reply("E01");
+                }
+                match self.command(Command::WriteRegisters(registers))? {
+                    Event::Ack => reply("OK"),
+                    _ => reply("E01"),
+                }
+            }
+            "m" => {
+                let Some((address, len)) = parse_range(body) else {
+                    return reply("E01");
+                };
+                match self.command(Command::ReadMemory { address, len })? {
+                    Event::Memory(bytes) if !bytes.is_empty() || len == 0 => reply(&hex(&bytes)),
+                    _ => reply("E14"),
+                }
+            }
+            "M" => {
+                let parsed = body.split_once(':').and_then(|(range, data)| {
+                    let (address, len) = parse_range(range)?;
+                    let bytes = unhex(data)?;
+                    (bytes.len() == len).then_some((address, bytes))
+                });
+                let Some((address, bytes)) = parsed else {
+                    return reply("E01");
+                };
+                match self.command(Command::WriteMemory { address, bytes })? {
+                    Event::Ack => reply("OK"),
+                    _ => reply("E14"),
+                }
+            }
+            "Z" | "z" => {
+                let mut fields = body.split(',');
+                let (Some("0"), Some(address)) = (fields.next(), fields.next()) else {
+                    // only software breakpoints are supported
+                    return reply("");
+                };
+                let Ok(address) = usize::from_str_radix(address, 16) else {
+                    return reply("E01");
+                };
+                let command = if kind == "Z" {
+                    Command::InsertBreakpoint(address)
+                } else {
+                    Command::RemoveBreakpoint(address)
+                };
+                match self.command(command)? {
+                    Event::Ack => reply("OK"),
+                    _ => reply("E14"),
+                }
+            }
+            "c" if body.is_empty() => Some(Next::Resume(Command::Continue)),
+            "s" if body.is_empty() => Some(Next::Resume(Command::Step)),
+            "D" => {
+                self.pipe.send(&Command::Detach);
+                Some(Next::Finish("OK".into()))
+            }
+            "k" => {
+                self.pipe.send(&Command::Kill);
+                None
+            }
+            "H" | "T" => reply("OK"),
+            "q" | "Q" => match packet {
+                _ if packet.starts_with("qSupported") => {
+                    reply("PacketSize=4000;QStartNoAckMode+;swbreak+")
+                }
+                "QStartNoAckMode" => {
+                    self.reply("OK");
+                    self.ack = false;
+                    Some(Next::Replied)
+                }
+                "qAttached" => reply("1"),
+                "qC" => reply("QC1"),
+                "qfThreadInfo" => reply("m1"),
+                "qsThreadInfo" => reply("l"),
+                _ => reply(""),
+            },
+            _ => reply(""),
+        }
+    }
+
+    /// Reads the next packet from gdb, acknowledging it; `None` means gdb disconnected or the VMM
+    /// is shutting down.
+    fn packet(&mut self) -> Option<String> {
+        loop {
+            // skip acks and interrupt requests (we are already stopped)
+            while self.byte()? != b'$' {}
+            let mut body = Vec::new();
+            loop {
+                match self.byte()? {
+                    b'#' => break,
+                    x => body.push(x),
+                }
+            }
+            let checksum = [self.byte()?, self.byte()?];
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum).ok()?, 16).ok();
+            let actual = body.iter().fold(0u8, |x, y| x.wrapping_add(*y));
+            if self.ack {
+                let ok = expected == Some(actual);
+                self.stream.write_all(if ok { b"+" } else { b"-" }).ok()?;
+                if !ok {
+                    continue;
+                }
+            }
+            return String::from_utf8(body).ok();
+        }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => return None,
+                Ok(_) => return Some(byte[0]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.shutdown.requested() {
+                        return None;
+                    }
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn reply(&mut self, body: &str) {
+        // gdb retransmits if it does not get an ack, so there is no need to wait for ours
+        let _ = self.stream.write_all(frame(body).as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        assert_eq!(frame(""), "$#00");
+    }
+
+    #[test]
+    fn test_registers_round_trip() {
+        let registers: Vec<u64> = (0..REGISTERS as u64).map(|x| x * 0x0101).collect();
+        let encoded = encode_registers(&registers);
+        assert_eq!(encoded.len(), (17 * 8 + 7 * 4) * 2);
+        // rbx comes second in gdb's order
+        assert_eq!(&encoded[16..32], "0303000000000000");
+        let mut decoded = vec![0; REGISTERS];
+        decode_registers(&encoded, &mut decoded).unwrap();
+        assert_eq!(decoded[..17], registers[..17]);
+        assert_eq!(decoded[RFLAGS], registers[RFLAGS] & 0xffff_ffff);
+    }
+}
@@ -0,0 +1,134 @@
+//! Recording and replaying the host's side of guest interactions.
+//!
+//! Every response the host sends to the guest (over the control, file, listener and stream pipes)
+//! and every clock reading the guest passes through the `CLOCK` hypercall is appended to the
+//! journal in the order it is produced.  Responses are keyed by the channel they were sent on,
+//! where a channel is named by its path of creation from the control pipe (e.g. `[0, 2]` is the
+//! third pipe opened by the first pipe opened by the control pipe); since the guest issues
+//! requests on a single channel in order, this makes replay independent of how host threads were
+//! scheduled while recording.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub type Channel = Vec<u32>;
+
+/// The channel on which clock readings are recorded.
+pub const CLOCK: &[u32] = &[u32::MAX];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    channel: Channel,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+enum Mode {
+    #[default]
+    Off,
+    Record(Mutex<BufWriter<File>>),
+    Replay(Mutex<HashMap<Channel, VecDeque<Vec<u8>>>>),
+}
+
+#[derive(Debug, Default)]
+pub struct Journal {
+    mode: Mode,
+}
+
+impl Journal {
+    pub fn record(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Journal {
+            mode: Mode::Record(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    pub fn replay(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut channels: HashMap<Channel, VecDeque<Vec<u8>>> = HashMap::new();
+        loop {
+            let mut length = [0; 8];
+            match file.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut bytes = vec![0; u64::from_le_bytes(length) as usize];
+            file.read_exact(&mut bytes)?;
+            let entry: Entry = postcard::from_bytes(&bytes)?;
+            channels
+                .entry(entry.channel)
+                .or_default()
+                .push_back(entry.payload);
+        }
+        Ok(Journal {
+            mode: Mode::Replay(Mutex::new(channels)),
+        })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// Whether the journal is recording or replaying.
+    pub fn is_active(&self) -> bool {
+        !matches!(self.mode, Mode::Off)
+    }
+
+    /// Appends a value to the journal, if recording.
+    pub fn append<T: Serialize>(&self, channel: &[u32], value: &T) {
+        let Mode::Record(file) = &self.mode else {
+            return;
+        };
+        let entry = Entry {
+            channel: channel.to_vec(),
+            payload: postcard::to_allocvec(value).unwrap(),
+        };
+        let bytes = postcard::to_allocvec(&entry).unwrap();
+        let mut file = file.lock().unwrap();
+        file.write_all(&(bytes.len() as u64).to_le_bytes()).unwrap();
+        file.write_all(&bytes).unwrap();
+        // flush eagerly so the log is usable even if the VMM dies
+        file.flush().unwrap();
+    }
+
+    /// Takes the next value recorded on a channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the journal is not replaying, or if the guest diverged from the recording.
+    pub fn next<T: DeserializeOwned>(&self, channel: &[u32]) -> T {
+        let Mode::Replay(channels) = &self.mode else {
+            panic!("journal is not replaying");
+        };
+        let payload = channels
+            .lock()
+            .unwrap()
+            .get_mut(channel)
+            .and_then(|x| x.pop_front())
+            .unwrap_or_else(|| panic!("replay log has no more entries for channel {channel:?}"));
+        postcard::from_bytes(&payload).expect("replay log entry did not match the request")
+    }
+
+    /// Produces a value by either replaying it or running `f` (and recording the result).
+    pub fn respond<T: Serialize + DeserializeOwned>(
+        &self,
+        channel: &[u32],
+        f: impl FnOnce() -> T,
+    ) -> T {
+        if self.is_replaying() {
+            self.next(channel)
+        } else {
+            let value = f();
+            self.append(channel, &value);
+            value
+        }
+    }
+}
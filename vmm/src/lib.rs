@@ -6,5 +6,11 @@
 #![feature(cstr_display)]
 
 pub mod comm;
+pub mod gdb;
+pub mod journal;
+pub mod memory;
+pub mod monitor;
 pub mod pipe;
 pub mod runtime;
+pub mod shutdown;
+pub mod snapshot;
@@ -1,17 +1,45 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
 
 use clap::Parser;
-use vmm::runtime::Runtime;
+use vmm::{journal::Journal, memory, runtime::Runtime, snapshot::Snapshot};
 
 #[derive(Parser, Debug)]
 struct Args {
     kernel: PathBuf,
     #[arg(short, long, env = "ARCA_SMP")]
     smp: Option<usize>,
+    /// Guest RAM, e.g. `4G` or `512MiB`; must be a power of two.
+    #[arg(short, long, env = "ARCA_MEMORY", value_parser = memory::parse_size, default_value = "16G")]
+    memory: usize,
+    /// Print allocator statistics on exit.
+    #[arg(long)]
+    stats: bool,
+    /// Record every host response to the guest in this log.
+    #[arg(long, value_name = "LOG", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Serve host responses from a log made with `--record` instead of the host.
+    #[arg(long, value_name = "LOG")]
+    replay: Option<PathBuf>,
+    /// Serve the guest's latest published counters as JSON on a Unix socket at this path.
+    #[arg(long, value_name = "SOCKET")]
+    monitor: Option<PathBuf>,
+    /// Append each set of counters the guest publishes to this file as a line of JSON.
+    #[arg(long, value_name = "FILE")]
+    stats_log: Option<PathBuf>,
+    /// Serve a gdb remote stub on this port for functions the guest runs under the debugger.
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+    /// Write a snapshot of the VM to this file whenever the VMM gets `SIGUSR1`.
+    #[arg(long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+    /// Resume the VM saved in this snapshot instead of booting the kernel; the snapshot's core
+    /// count, memory size and arguments are used.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record", "replay"])]
+    restore: Option<PathBuf>,
     argv: Vec<String>,
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<ExitCode> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let args = Args::parse();
@@ -20,11 +48,45 @@ fn main() -> anyhow::Result<()> {
         .or_else(|| std::thread::available_parallelism().ok().map(|x| x.get()))
         .unwrap_or(1);
 
+    let snapshot = args.restore.as_deref().map(Snapshot::open).transpose()?;
+    let (smp, ram) = match &snapshot {
+        Some(snapshot) => (snapshot.header.cores, snapshot.header.memory),
+        None => (smp, args.memory),
+    };
+
+    memory::validate(ram)?;
+    if args.stats {
+        memory::enable_stats();
+    }
+
     let bin = std::fs::read(args.kernel.clone())?;
-    let mut rt = Runtime::new(smp, 1 << 34, bin.into());
-    let mut argv = args.argv;
-    argv.insert(0, args.kernel.into_os_string().into_string().unwrap());
-    rt.run(argv);
+    let mut rt = Runtime::new(smp, ram, bin.into());
+    if let Some(log) = &args.record {
+        rt.set_journal(Journal::record(log)?);
+    } else if let Some(log) = &args.replay {
+        rt.set_journal(Journal::replay(log)?);
+    }
+    if let Some(path) = &args.monitor {
+        rt.monitor().serve(path)?;
+    }
+    if let Some(path) = &args.stats_log {
+        rt.monitor().log_to(path)?;
+    }
+    if let Some(port) = args.gdb {
+        rt.set_gdb(port);
+    }
+    if let Some(path) = args.snapshot {
+        rt.set_snapshot(path);
+    }
+    let code = match snapshot {
+        Some(snapshot) => rt.restore(snapshot)?,
+        None => {
+            let mut argv = args.argv;
+            argv.insert(0, args.kernel.into_os_string().into_string().unwrap());
+            rt.run(argv)
+        }
+    };
+    memory::print_stats();
 
-    Ok(())
+    Ok(ExitCode::from(code as u8))
 }
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use common::BuddyAllocator;
+
+/// The smallest amount of guest RAM the kernel can boot with.
+pub const MIN_MEMORY: usize = 1 << 26;
+
+static STATS: AtomicBool = AtomicBool::new(false);
+
+/// Parses a size like `4096`, `512M`, `16GiB` or `1T`; suffixes are binary (powers of 1024).
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(split);
+    let value: usize = digits.parse().map_err(|_| format!("invalid size {s:?}"))?;
+    let shift = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => return Err(format!("unknown size suffix {suffix:?}")),
+    };
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {s:?} is too large"))
+}
+
+pub fn format_size(size: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Checks that the host can back `size` bytes of guest RAM.
+///
+/// The guest's memory is mapped lazily, so asking for more than the host's physical memory is
+/// allowed but probably a mistake.
+pub fn validate(size: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        size.is_power_of_two(),
+        "guest memory must be a power of two (got {})",
+        format_size(size)
+    );
+    anyhow::ensure!(
+        size >= MIN_MEMORY,
+        "guest memory must be at least {} (got {})",
+        format_size(MIN_MEMORY),
+        format_size(size)
+    );
+    let (soft, _) = rlimit::Resource::AS.get()?;
+    anyhow::ensure!(
+        size as u64 <= soft,
+        "guest memory ({}) exceeds the address space limit ({})",
+        format_size(size),
+        format_size(soft as usize)
+    );
+    let info = nix::sys::sysinfo::sysinfo()?;
+    let physical = info.ram_total() as usize;
+    if size > physical {
+        log::warn!(
+            "guest memory ({}) exceeds host physical memory ({})",
+            format_size(size),
+            format_size(physical)
+        );
+    }
+    Ok(())
+}
+
+/// Prints allocator statistics when the VMM exits.
+pub fn enable_stats() {
+    STATS.store(true, Ordering::SeqCst);
+}
+
+pub fn print_stats() {
+    if !STATS.load(Ordering::SeqCst) {
+        return;
+    }
+    let used = BuddyAllocator.used_size();
+    let total = BuddyAllocator.total_size();
+    eprintln!(
+        "memory: {} used of {} ({:.2}%)",
+        format_size(used),
+        format_size(total),
+        BuddyAllocator.usage() * 100.
+    );
+    let mut requests = [0; 64];
+    BuddyAllocator.requests(&mut requests);
+    eprintln!("allocation requests by size class:");
+    for (level, count) in requests.iter().enumerate() {
+        if *count != 0 {
+            eprintln!("{:>12}: {count}", format_size(1 << level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("16GiB"), Ok(16 << 30));
+        assert_eq!(parse_size("1 t"), Ok(1 << 40));
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("G").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(3 << 30).is_err());
+        assert!(validate(1 << 20).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Host-side sink for the counters the guest publishes with the `STATS` hypercall.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::Write,
+    os::unix::net::UnixListener,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use common::StatsRecord;
+
+#[derive(Debug, Default)]
+pub struct Monitor {
+    latest: Mutex<Option<StatsRecord>>,
+    log: Mutex<Option<File>>,
+    /// Whether anything consumes the records, so the guest should publish them.
+    active: AtomicBool,
+}
+
+impl Monitor {
+    /// Appends every record the guest publishes to `path` as a line of JSON.
+    pub fn log_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        *self.log.lock().unwrap() = Some(File::create(path)?);
+        self.active.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Serves the most recent record (as a line of JSON) to each client that connects to a Unix
+    /// socket at `path`.
+    pub fn serve(self: &Arc<Self>, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        let monitor = self.clone();
+        std::thread::Builder::new()
+            .name("Arca monitor".into())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else {
+                        continue;
+                    };
+                    let line = match *monitor.latest.lock().unwrap() {
+                        Some(ref record) => to_json(record),
+                        None => "null".into(),
+                    };
+                    let _ = writeln!(stream, "{line}");
+                }
+            })?;
+        self.active.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn update(&self, record: StatsRecord) {
+        if let Some(log) = &mut *self.log.lock().unwrap() {
+            writeln!(log, "{}", to_json(&record)).unwrap();
+        }
+        *self.latest.lock().unwrap() = Some(record);
+    }
+}
+
+pub fn to_json(record: &StatsRecord) -> String {
+    let mut syscalls = String::new();
+    for (num, count) in record.syscalls.iter().enumerate() {
+        if *count != 0 {
+            if !syscalls.is_empty() {
+                syscalls.push(',');
+            }
+            write!(syscalls, "\"{num}\":{count}").unwrap();
+        }
+    }
+    format!(
+        "{{\"uptime_ns\":{},\"threads_outstanding\":{},\"arcas_forced\":{},\"memory_used\":{},\"memory_total\":{},\"syscalls\":{{{syscalls}}},\"syscalls_other\":{}}}",
+        record.uptime_ns,
+        record.threads_outstanding,
+        record.arcas_forced,
+        record.memory_used,
+        record.memory_total,
+        record.syscalls_other,
+    )
+}
@@ -1,22 +1,55 @@
 use common::pipe::Pipe as RawPipe;
 pub use common::pipe::{Error, Result};
 use std::marker::PhantomData;
+use std::sync::Arc;
 
+use crate::shutdown::Shutdown;
+use crate::snapshot::{Busy, Registration};
+
+/// A pipe to the guest; reads and writes fail with [`Error::Closed`] once the VMM is shutting down.
 #[derive(Debug)]
 pub struct GuestPipe {
     inner: RawPipe,
+    shutdown: Arc<Shutdown>,
+    service: Option<Registration>,
 }
 
 impl GuestPipe {
-    pub fn new(pipe: RawPipe) -> Self {
-        Self { inner: pipe }
+    pub fn new(pipe: RawPipe, shutdown: Arc<Shutdown>) -> Self {
+        Self {
+            inner: pipe,
+            shutdown,
+            service: None,
+        }
     }
 
-    pub fn read(&mut self, bytes: &mut [u8]) -> Result<usize> {
+    /// Includes this pipe in snapshots, which then wait for messages in flight on it.
+    pub fn register(mut self, service: Registration) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    fn wait_readable(&self) -> Result<()> {
         while !self.inner.can_read() {
+            if self.shutdown.requested() {
+                return Err(Error::Closed);
+            }
             // self.read_fd.read().unwrap();
             std::thread::yield_now();
         }
+        Ok(())
+    }
+
+    /// Starts transferring a message, holding off snapshots until the returned guard is dropped.
+    fn enter(&self) -> Result<Option<Busy>> {
+        match &self.service {
+            Some(service) => service.enter(&self.shutdown).map(Some).ok_or(Error::Closed),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read(&mut self, bytes: &mut [u8]) -> Result<usize> {
+        self.wait_readable()?;
         self.inner.read(bytes)
     }
 
@@ -36,6 +69,9 @@ impl GuestPipe {
 
     pub fn write(&mut self, bytes: &[u8]) -> Result<usize> {
         while !self.inner.can_write() {
+            if self.shutdown.requested() {
+                return Err(Error::Closed);
+            }
             // self.write_fd.read().unwrap();
             std::thread::yield_now();
         }
@@ -73,20 +109,38 @@ impl<S: serde::Serialize, R: for<'a> serde::Deserialize<'a>> TypedPipe<S, R> {
         }
     }
 
-    pub fn recv(&mut self) -> R {
+    /// Receives the next message, or `None` if the VMM is shutting down.
+    pub fn recv(&mut self) -> Option<R> {
+        if let Some(bytes) = self.pipe.service.as_mut().and_then(|x| x.replay()) {
+            return Some(postcard::from_bytes(&bytes).unwrap());
+        }
+        self.pipe.wait_readable().ok()?;
+        let _busy = self.pipe.enter().ok()?;
         let mut length = [0; 8];
-        self.pipe.read_exact(&mut length).unwrap();
+        self.pipe.read_exact(&mut length).ok()?;
         let length = usize::from_le_bytes(length);
         let mut bytes = vec![0; length];
-        self.pipe.read_exact(&mut bytes).unwrap();
-        postcard::from_bytes(&bytes).unwrap()
+        self.pipe.read_exact(&mut bytes).ok()?;
+        if let Some(service) = &self.pipe.service {
+            service.received(&bytes);
+        }
+        Some(postcard::from_bytes(&bytes).unwrap())
     }
 
+    /// Sends a message; it is dropped if the VMM is shutting down.
     pub fn send(&mut self, request: &S) {
         let bytes = postcard::to_allocvec(request).unwrap();
         let length = bytes.len().to_le_bytes();
-        self.pipe.write_exact(&length).unwrap();
-        self.pipe.write_exact(&bytes).unwrap();
+        let Ok(_busy) = self.pipe.enter() else {
+            return;
+        };
+        let _ = self
+            .pipe
+            .write_exact(&length)
+            .and_then(|_| self.pipe.write_exact(&bytes));
+        if let Some(service) = &self.pipe.service {
+            service.answered();
+        }
     }
 }
 
@@ -97,3 +151,4 @@ pub type ListenerPipe =
     TypedPipe<common::protocol::listener::Response, common::protocol::listener::Request>;
 pub type StreamPipe =
     TypedPipe<common::protocol::stream::Response, common::protocol::stream::Request>;
+pub type DebugPipe = TypedPipe<common::protocol::debug::Command, common::protocol::debug::Event>;
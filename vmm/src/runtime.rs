@@ -1,6 +1,7 @@
 use std::{
     collections::HashMap,
     io::{self, Read},
+    path::{Path, PathBuf},
     process::ExitCode,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -10,9 +11,17 @@ use std::{
     time::{Duration, Instant},
 };
 
+use crate::comm::{self, Context};
+use crate::journal::{self, Journal};
+use crate::monitor::Monitor;
+use crate::pipe::{ControlPipe, GuestPipe};
+use crate::shutdown::Shutdown;
+use crate::snapshot::{self, Gate, Header, Resource, Snapshot, VcpuState};
+
+use anyhow::{bail, Context as _};
 use common::{hypercall, BuddyAllocator};
 use elf::{endian::AnyEndian, segment::ProgramHeader, ElfBytes};
-use kvm_bindings::{kvm_userspace_memory_region, CpuId, KVM_MAX_CPUID_ENTRIES};
+use kvm_bindings::{kvm_irqchip, kvm_userspace_memory_region, KVM_MAX_CPUID_ENTRIES};
 use kvm_ioctls::{IoEventAddress, Kvm, NoDatamatch, VcpuExit, VcpuFd, VmFd};
 
 pub use common::mmap::Mmap;
@@ -21,14 +30,24 @@ use vmm_sys_util::eventfd::EventFd;
 
 const MEM_BASE: u64 = 0x1_0000_0000;
 
-fn new_cpu<'scope>(
-    i: usize,
-    scope: &'scope Scope<'scope, '_>,
-    vcpu_fd: VcpuFd,
-    elf: &'scope ElfBytes<AnyEndian>,
-    args: &[u64; 6],
-    cpuid: &CpuId,
-) -> ScopedJoinHandle<'scope, ()> {
+/// Host-side services the vCPU threads need when handling hypercalls.
+#[derive(Clone, Debug)]
+struct Host {
+    journal: Arc<Journal>,
+    monitor: Arc<Monitor>,
+    shutdown: Arc<Shutdown>,
+    gate: Arc<Gate>,
+    /// The MSRs saved in snapshots.
+    msrs: Arc<[u32]>,
+}
+
+struct Vcpu<'scope> {
+    thread: ScopedJoinHandle<'scope, ()>,
+    exit: Arc<AtomicBool>,
+}
+
+/// Sets up a vCPU to enter the kernel in long mode with `args`.
+fn boot_cpu(vcpu_fd: &VcpuFd, elf: &ElfBytes<AnyEndian>, args: &[u64; 6]) {
     // set up the CPU in long mode
     let mut vcpu_sregs = vcpu_fd.get_sregs().unwrap();
 
@@ -63,8 +82,6 @@ fn new_cpu<'scope>(
         | ExtendedFeatureEnableReg::LMA
         | ExtendedFeatureEnableReg::SCE;
 
-    vcpu_fd.set_tsc_khz(u32::MAX / 1000).unwrap();
-
     let code_segment = kvm_bindings::kvm_segment {
         base: 0,
         limit: 0xffffffff,
@@ -109,8 +126,6 @@ fn new_cpu<'scope>(
         padding: [0; 3],
     };
 
-    vcpu_fd.set_cpuid2(cpuid).unwrap();
-
     vcpu_fd.set_sregs(&vcpu_sregs).unwrap();
 
     let mut vcpu_regs = vcpu_fd.get_regs().unwrap();
@@ -133,20 +148,43 @@ fn new_cpu<'scope>(
     vcpu_regs.r9 = args[5];
     vcpu_regs.rflags = 2;
     vcpu_fd.set_regs(&vcpu_regs).unwrap();
+}
 
+fn spawn_cpu<'scope>(
+    i: usize,
+    scope: &'scope Scope<'scope, '_>,
+    vcpu_fd: VcpuFd,
+    elf: &'scope ElfBytes<AnyEndian>,
+    host: Host,
+) -> Vcpu<'scope> {
     // the Drop impl will wait for the child thread to exit, so we can pretend the allocator
     // lives forever
     let exit = Arc::new(AtomicBool::new(false));
     let flag = exit.clone();
-    std::thread::Builder::new()
+    let thread = std::thread::Builder::new()
         .name(format!("Arca vCPU {i}"))
         .spawn_scoped(scope, move || {
-            run_cpu(vcpu_fd, elf, flag);
+            run_cpu(i, vcpu_fd, elf, flag, host);
         })
-        .unwrap()
+        .unwrap();
+    Vcpu { thread, exit }
 }
 
-fn run_cpu(mut vcpu_fd: VcpuFd, elf: &ElfBytes<AnyEndian>, exit: Arc<AtomicBool>) {
+fn run_cpu(
+    i: usize,
+    mut vcpu_fd: VcpuFd,
+    elf: &ElfBytes<AnyEndian>,
+    exit: Arc<AtomicBool>,
+    host: Host,
+) {
+    let Host {
+        journal,
+        monitor,
+        shutdown,
+        gate,
+        msrs,
+    } = host;
+    let _vcpu = shutdown.register_vcpu();
     let lookup = |target| {
         let (symtab, strtab) = elf
             .symbol_table()
@@ -168,12 +206,25 @@ fn run_cpu(mut vcpu_fd: VcpuFd, elf: &ElfBytes<AnyEndian>, exit: Arc<AtomicBool>
     };
 
     while !exit.load(Ordering::Acquire) {
+        if gate.pausing() {
+            // finish the I/O the vCPU last exited for, so its saved state is consistent
+            vcpu_fd.set_kvm_immediate_exit(1);
+            let _ = vcpu_fd.run();
+            vcpu_fd.set_kvm_immediate_exit(0);
+            gate.park(i, VcpuState::save(&vcpu_fd, &msrs), &shutdown);
+            continue;
+        }
         vcpu_fd
             .set_mp_state(kvm_bindings::kvm_mp_state {
                 mp_state: kvm_bindings::KVM_MP_STATE_RUNNABLE,
             })
             .unwrap();
-        let vcpu_exit = vcpu_fd.run().expect("run failed");
+        let vcpu_exit = match vcpu_fd.run() {
+            Ok(vcpu_exit) => vcpu_exit,
+            // kicked by `Shutdown::kick`
+            Err(e) if e.errno() == libc::EINTR => continue,
+            Err(e) => panic!("run failed: {e}"),
+        };
         match vcpu_exit {
             VcpuExit::IoIn(addr, data) => match addr {
                 0xe9 => {
@@ -191,13 +242,8 @@ fn run_cpu(mut vcpu_fd: VcpuFd, elf: &ElfBytes<AnyEndian>, exit: Arc<AtomicBool>
                     let args = &[regs.rdi, regs.rsi, regs.rcx, regs.r10, regs.r8, regs.r9];
                     match code {
                         hypercall::EXIT => {
-                            // let code = args[0] as u8;
-                            std::process::exit(args[0] as i32);
-                            // if code == 0 {
-                            // ExitCode::from(args[0] as u8).exit_process();
-                            // } else {
-                            //     ExitCode::from(args[0] as u8).exit_process();
-                            // }
+                            shutdown.request(args[0] as i32);
+                            return;
                         }
                         hypercall::LOG => {
                             let record: *const common::LogRecord =
@@ -290,6 +336,24 @@ fn run_cpu(mut vcpu_fd: VcpuFd, elf: &ElfBytes<AnyEndian>, exit: Arc<AtomicBool>
                                 regs.rax = mem.as_ptr() as u64;
                             }
                         }
+                        hypercall::CLOCK => {
+                            regs.rax = journal.respond(journal::CLOCK, || args[0]);
+                        }
+                        hypercall::STATS => {
+                            let record: *const common::StatsRecord =
+                                BuddyAllocator.from_offset(args[0] as usize - MEM_BASE as usize);
+                            monitor.update(unsafe { *record });
+                        }
+                        hypercall::FLAGS => {
+                            let mut flags = 0;
+                            if journal.is_active() {
+                                flags |= hypercall::flags::JOURNAL;
+                            }
+                            if monitor.is_active() {
+                                flags |= hypercall::flags::MONITOR;
+                            }
+                            regs.rax = flags;
+                        }
                         hypercall::NOTIFY_READ => {
                             todo!();
                             // read_fd.write(1).unwrap();
@@ -333,6 +397,13 @@ pub struct Runtime {
     vm: VmFd,
     cores: usize,
     elf: Arc<[u8]>,
+    journal: Arc<Journal>,
+    monitor: Arc<Monitor>,
+    gdb: Option<u16>,
+    /// The MSRs saved in snapshots.
+    msrs: Arc<[u32]>,
+    /// Where to write a snapshot when the VMM gets `SIGUSR1`.
+    snapshot: Option<PathBuf>,
 }
 
 impl Runtime {
@@ -378,11 +449,23 @@ impl Runtime {
         })
         .unwrap();
 
+        let supported = kvm.get_msr_index_list().unwrap();
+        let msrs = snapshot::MSRS
+            .iter()
+            .copied()
+            .filter(|x| supported.as_slice().contains(x))
+            .collect();
+
         let mut x = Self {
             kvm,
             vm,
             cores,
             elf: elf.clone(),
+            journal: Default::default(),
+            monitor: Default::default(),
+            gdb: None,
+            msrs,
+            snapshot: None,
         };
         let elf_bytes =
             ElfBytes::<AnyEndian>::minimal_parse(&elf).expect("could not read kernel elf file");
@@ -447,42 +530,57 @@ impl Runtime {
         }
     }
 
-    pub fn run(&mut self, argv: Vec<String>) {
-        let elf = ElfBytes::<AnyEndian>::minimal_parse(&self.elf)
-            .expect("could not read kernel elf file");
+    /// Records host interactions to (or replays them from) `journal`.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Arc::new(journal);
+    }
 
-        let allocator_raw = common::buddy::export();
-        let allocator_raw =
-            Box::into_raw_with_allocator(Box::new_in(allocator_raw, BuddyAllocator)).0;
+    /// Serves gdb on `port` whenever the guest forces a function under the debugger.
+    pub fn set_gdb(&mut self, port: u16) {
+        self.gdb = Some(port);
+    }
 
-        std::thread::scope(|s| {
-            let mut cpus = vec![];
+    /// Writes a snapshot of the VM to `path` whenever the VMM gets `SIGUSR1`.
+    pub fn set_snapshot(&mut self, path: PathBuf) {
+        snapshot::handle_signal();
+        self.snapshot = Some(path);
+    }
 
-            let (p, q) = common::pipe::pipe(8192);
-            // let read = EventFd::new(0).unwrap();
-            // let write = EventFd::new(0).unwrap();
-            // let read_fd = read.try_clone().unwrap();
-            // let write_fd = write.try_clone().unwrap();
-            let comm = s.spawn(move || {
-                crate::comm::control_thread(
-                    argv,
-                    crate::pipe::ControlPipe::new(crate::pipe::GuestPipe::new(q)),
-                );
-            });
+    /// The sink for counters published by the guest.
+    pub fn monitor(&self) -> &Arc<Monitor> {
+        &self.monitor
+    }
 
-            let (rx, tx) = p.into_inner();
-            let rx = rx.into_inner();
-            let tx = tx.into_inner();
-            let (rxp, rxn) = Arc::into_raw_with_allocator(rx).0.to_raw_parts();
-            let rxp = BuddyAllocator.to_offset(rxp);
-            let (txp, txn) = Arc::into_raw_with_allocator(tx).0.to_raw_parts();
-            let txp = BuddyAllocator.to_offset(txp);
+    /// Creates vCPU `i` with the host's CPUID and a fixed TSC frequency.
+    fn create_cpu(&self, i: usize) -> VcpuFd {
+        let vcpu_fd = self.vm.create_vcpu(i as u64).unwrap();
+        // TODO: ensure all needed features are present and disable unneeded ones
+        let kvm_cpuid = self.kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES).unwrap();
+        vcpu_fd.set_cpuid2(&kvm_cpuid).unwrap();
+        vcpu_fd.set_tsc_khz(u32::MAX / 1000).unwrap();
+        vcpu_fd
+    }
 
-            for i in 0..self.cores {
-                let vcpu_fd = self.vm.create_vcpu(i as u64).unwrap();
-                // TODO: ensure all needed features are present and disable unneeded ones
-                let kvm_cpuid = self.kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES).unwrap();
+    /// Runs the kernel until it exits, returning its exit code.
+    pub fn run(&mut self, argv: Vec<String>) -> i32 {
+        let elf = ElfBytes::<AnyEndian>::minimal_parse(&self.elf)
+            .expect("could not read kernel elf file");
 
+        let allocator_raw = common::buddy::export();
+        let allocator_raw =
+            Box::into_raw_with_allocator(Box::new_in(allocator_raw, BuddyAllocator)).0;
+        let allocator_raw_offset = BuddyAllocator.to_offset(allocator_raw);
+
+        let shutdown = Arc::new(Shutdown::default());
+        let gate = Arc::new(Gate::default());
+        let (p, q) = common::pipe::pipe(8192);
+        let guest = comm::decompose_pipe(p);
+        let service = gate.register(snapshot::mirror(&guest), Resource::Control, None, None);
+        let control = GuestPipe::new(q, shutdown.clone()).register(service);
+
+        let vcpus = (0..self.cores)
+            .map(|i| {
+                let vcpu_fd = self.create_cpu(i);
                 let mut msrs = kvm_bindings::Msrs::from_entries(&[kvm_bindings::kvm_msr_entry {
                     index: 0xC0000103,
                     ..Default::default()
@@ -494,29 +592,182 @@ impl Runtime {
                 x.data = i as u64;
                 vcpu_fd.set_msrs(&msrs).unwrap();
 
-                let allocator_raw_offset = BuddyAllocator.to_offset(allocator_raw);
-                cpus.push(new_cpu(
-                    i,
-                    s,
-                    vcpu_fd,
-                    // read.try_clone().unwrap(),
-                    // write.try_clone().unwrap(),
+                boot_cpu(
+                    &vcpu_fd,
                     &elf,
                     &[
                         self.cores as u64,
                         allocator_raw_offset as u64,
-                        rxp as u64,
-                        rxn as u64,
-                        txp as u64,
-                        txn as u64,
+                        guest.rx_ptr as u64,
+                        guest.rx_len as u64,
+                        guest.tx_ptr as u64,
+                        guest.tx_len as u64,
                     ],
-                    &kvm_cpuid,
-                ));
+                );
+                vcpu_fd
+            })
+            .collect();
+        self.execute(argv, control, vcpus, shutdown, gate)
+    }
+
+    /// Resumes the VM saved in `snapshot`, which must have been taken of the same kernel with
+    /// the same number of cores and amount of memory, and runs it until it exits.
+    pub fn restore(&mut self, mut snapshot: Snapshot) -> anyhow::Result<i32> {
+        let header = &mut snapshot.header;
+        if header.kernel != snapshot::hash(&self.elf) {
+            bail!("the snapshot was taken with a different kernel");
+        }
+        if header.cores != self.cores || header.memory != BuddyAllocator.len() {
+            bail!(
+                "the snapshot has {} cores and {} bytes of memory",
+                header.cores,
+                header.memory
+            );
+        }
+        if header.allocator != snapshot::allocator() {
+            bail!("the snapshot's allocator is laid out differently");
+        }
+        let argv = std::mem::take(&mut header.argv);
+        let services = std::mem::take(&mut header.services);
+        let states = std::mem::take(&mut header.vcpus);
+        for chip in header.irqchips.iter() {
+            self.vm.set_irqchip(chip)?;
+        }
+        self.vm.set_clock(&snapshot.header.clock)?;
+        snapshot.load_memory()?;
+
+        let vcpus = states
+            .iter()
+            .enumerate()
+            .map(|(i, state)| {
+                let vcpu_fd = self.create_cpu(i);
+                state
+                    .restore(&vcpu_fd)
+                    .with_context(|| format!("could not restore vCPU {i}"))?;
+                Ok(vcpu_fd)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let shutdown = Arc::new(Shutdown::default());
+        let gate = Arc::new(Gate::default());
+        let mut ctx = Context::new(self.journal.clone(), shutdown.clone(), gate.clone());
+        let mut control = None;
+        for service in services {
+            if let Resource::Control = service.resource {
+                let pipe = unsafe { comm::compose_pipe(&service.pipe) };
+                let service = gate.register(service.pipe, service.resource, None, service.pending);
+                control = Some(GuestPipe::new(pipe, shutdown.clone()).register(service));
+            } else {
+                unsafe { comm::restore(service, &mut ctx) };
+            }
+        }
+        let control = control.context("the snapshot has no control pipe")?;
+        Ok(self.execute(argv, control, vcpus, shutdown, gate))
+    }
+
+    /// Writes a snapshot of the running VM to `path`.
+    fn snapshot(
+        &self,
+        path: &Path,
+        argv: &[String],
+        gate: &Gate,
+        shutdown: &Shutdown,
+    ) -> anyhow::Result<()> {
+        if self.journal.is_active() {
+            bail!("cannot snapshot while recording or replaying");
+        }
+        snapshot::take(path, self.cores, gate, shutdown, |vcpus, services| {
+            let irqchips = (0..3)
+                .map(|chip_id| {
+                    let mut chip = kvm_irqchip {
+                        chip_id,
+                        ..Default::default()
+                    };
+                    self.vm.get_irqchip(&mut chip)?;
+                    Ok(chip)
+                })
+                .collect::<Result<_, kvm_ioctls::Error>>()?;
+            Ok(Header {
+                cores: self.cores,
+                memory: BuddyAllocator.len(),
+                kernel: snapshot::hash(&self.elf),
+                allocator: snapshot::allocator(),
+                argv: argv.to_vec(),
+                clock: self.vm.get_clock()?,
+                irqchips,
+                vcpus,
+                services,
+            })
+        })
+    }
+
+    /// Serves `control` and runs `vcpus` until the kernel exits, returning its exit code.
+    fn execute(
+        &mut self,
+        argv: Vec<String>,
+        control: GuestPipe,
+        vcpus: Vec<VcpuFd>,
+        shutdown: Arc<Shutdown>,
+        gate: Arc<Gate>,
+    ) -> i32 {
+        let elf = ElfBytes::<AnyEndian>::minimal_parse(&self.elf)
+            .expect("could not read kernel elf file");
+
+        std::thread::scope(|s| {
+            let ctx = Context::new(self.journal.clone(), shutdown.clone(), gate.clone());
+            let gdb = self.gdb;
+            let args = argv.clone();
+            let comm = s.spawn(move || {
+                comm::control_thread(args, gdb, ControlPipe::new(control), ctx);
+            });
+
+            let msrs: Arc<[u32]> = self.msrs.clone();
+            let cpus: Vec<_> = vcpus
+                .into_iter()
+                .enumerate()
+                .map(|(i, vcpu_fd)| {
+                    spawn_cpu(
+                        i,
+                        s,
+                        vcpu_fd,
+                        &elf,
+                        Host {
+                            journal: self.journal.clone(),
+                            monitor: self.monitor.clone(),
+                            shutdown: shutdown.clone(),
+                            gate: gate.clone(),
+                            msrs: msrs.clone(),
+                        },
+                    )
+                })
+                .collect();
+
+            while !cpus.iter().all(|cpu| cpu.thread.is_finished()) {
+                if shutdown.requested() {
+                    for cpu in &cpus {
+                        cpu.exit.store(true, Ordering::Release);
+                    }
+                    // a vCPU may have been between checking its flag and entering the guest, so
+                    // keep kicking until everyone has stopped
+                    shutdown.kick();
+                } else if snapshot::signalled() {
+                    if let Some(path) = &self.snapshot {
+                        match self.snapshot(path, &argv, &gate, &shutdown) {
+                            Ok(()) => log::info!("wrote snapshot to {}", path.display()),
+                            Err(e) => log::error!("could not write snapshot: {e:#}"),
+                        }
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(10));
             }
             for cpu in cpus {
-                cpu.join().unwrap();
+                cpu.thread.join().unwrap();
             }
+            // the guest can no longer answer, so make sure the control thread stops waiting
+            shutdown.request(0);
             comm.join().unwrap();
         });
+        shutdown.join_services();
+        shutdown.code()
     }
 }
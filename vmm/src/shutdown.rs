@@ -0,0 +1,110 @@
+//! Coordinated shutdown of the vCPU and host-service threads.
+//!
+//! Whoever sees the guest exit (a vCPU handling `hypercall::EXIT` or the control thread handling
+//! `Request::Exit`) calls [`Shutdown::request`]; [`Runtime::run`](crate::runtime::Runtime::run) then
+//! kicks the remaining vCPUs out of `KVM_RUN`, waits for every thread to return, and hands back the
+//! exit code.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Mutex, Once,
+    },
+    thread::JoinHandle,
+};
+
+use vmm_sys_util::signal::{register_signal_handler, SIGRTMIN};
+
+/// The signal used to interrupt a vCPU thread blocked in `KVM_RUN`.
+fn kick_signal() -> libc::c_int {
+    SIGRTMIN()
+}
+
+extern "C" fn handle_kick(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {}
+
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    code: AtomicI32,
+    vcpus: Mutex<Vec<libc::pthread_t>>,
+    services: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Shutdown {
+    /// Starts shutting down with `code`; only the first request's code is kept.
+    pub fn request(&self, code: i32) {
+        if self
+            .requested
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return;
+        }
+        self.code.store(code, Ordering::SeqCst);
+        self.kick();
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code.load(Ordering::SeqCst)
+    }
+
+    /// Registers the calling thread as a vCPU, so [`kick`](Self::kick) can interrupt it until the
+    /// returned registration is dropped, which must happen before the thread exits.
+    pub fn register_vcpu(&self) -> VcpuRegistration<'_> {
+        static HANDLER: Once = Once::new();
+        HANDLER.call_once(|| {
+            register_signal_handler(kick_signal(), handle_kick)
+                .expect("could not install vCPU kick handler");
+        });
+        self.vcpus
+            .lock()
+            .unwrap()
+            .push(unsafe { libc::pthread_self() });
+        VcpuRegistration(self)
+    }
+
+    /// Interrupts every vCPU thread so it notices the shutdown request.
+    pub fn kick(&self) {
+        for thread in self.vcpus.lock().unwrap().iter() {
+            unsafe {
+                libc::pthread_kill(*thread, kick_signal());
+            }
+        }
+    }
+
+    /// Tracks a host-service thread so it is joined before the VMM exits.
+    pub fn track(&self, thread: JoinHandle<()>) {
+        self.services.lock().unwrap().push(thread);
+    }
+
+    /// Joins every tracked host-service thread, including ones spawned while waiting.
+    pub fn join_services(&self) {
+        loop {
+            let Some(thread) = self.services.lock().unwrap().pop() else {
+                return;
+            };
+            if thread.join().is_err() {
+                log::error!("host service thread panicked");
+            }
+        }
+    }
+}
+
+/// A vCPU thread known to a [`Shutdown`]; dropping it stops the thread being kicked.
+#[derive(Debug)]
+pub struct VcpuRegistration<'a>(&'a Shutdown);
+
+impl Drop for VcpuRegistration<'_> {
+    fn drop(&mut self) {
+        let this = unsafe { libc::pthread_self() };
+        self.0
+            .vcpus
+            .lock()
+            .unwrap()
+            .retain(|&thread| unsafe { libc::pthread_equal(thread, this) } == 0);
+    }
+}
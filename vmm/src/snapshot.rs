@@ -0,0 +1,639 @@
+//! Saving a running VM to a file and resuming it later.
+//!
+//! A snapshot is taken while the guest is quiescent: host services finish the message they are
+//! transferring and then hold off (see [`Gate`]), and every vCPU is kicked out of `KVM_RUN` and
+//! parks after saving its state.  The file holds that state, the VM's interrupt controllers and
+//! clock, the non-zero pages of guest memory, and the host end of every open pipe along with what
+//! it was serving, so that [`Runtime::restore`](crate::runtime::Runtime::restore) can rebuild the
+//! VM and respawn its services.
+//!
+//! Files are reopened at the offset they had reached and listeners are bound again, but TCP
+//! streams and debugger sessions cannot outlive the host process: restored streams read as closed
+//! and restored debug sessions detach.  A request a service had read but not yet answered is
+//! served again once the VM is restored.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use common::protocol::control::PipeData;
+use kvm_bindings::{
+    kvm_clock_data, kvm_debugregs, kvm_irqchip, kvm_lapic_state, kvm_mp_state, kvm_msr_entry,
+    kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs, kvm_xsave, Msrs,
+};
+use kvm_ioctls::VcpuFd;
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown::Shutdown;
+
+const MAGIC: &[u8; 8] = b"ARCASNAP";
+const VERSION: u32 = 1;
+const PAGE_SIZE: usize = 4096;
+/// Marks the end of the memory runs.
+const END: u64 = u64::MAX;
+
+/// The MSRs saved with each vCPU (those KVM does not support are skipped).
+pub const MSRS: &[u32] = &[
+    0x10,       // TSC
+    0x1a0,      // MISC_ENABLE
+    0x277,      // PAT
+    0x6e0,      // TSC_DEADLINE
+    0xc0000081, // STAR
+    0xc0000082, // LSTAR
+    0xc0000083, // CSTAR
+    0xc0000084, // SFMASK
+    0xc0000100, // FS_BASE
+    0xc0000101, // GS_BASE
+    0xc0000102, // KERNEL_GS_BASE
+    0xc0000103, // TSC_AUX
+    0x4b564d00, // kvmclock wall clock
+    0x4b564d01, // kvmclock system time
+];
+
+/// The architectural state of one vCPU.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VcpuState {
+    regs: kvm_regs,
+    sregs: kvm_sregs,
+    xsave: kvm_xsave,
+    xcrs: kvm_xcrs,
+    lapic: kvm_lapic_state,
+    msrs: Vec<kvm_msr_entry>,
+    events: kvm_vcpu_events,
+    mp_state: kvm_mp_state,
+    debug_regs: kvm_debugregs,
+}
+
+impl VcpuState {
+    /// Saves the state of a vCPU which is not running, including the MSRs in `msrs`.
+    ///
+    /// Any I/O the vCPU last exited for must already have been completed (by re-entering it with
+    /// `immediate_exit` set).
+    pub fn save(vcpu: &VcpuFd, msrs: &[u32]) -> anyhow::Result<Self> {
+        let entries: Vec<_> = msrs
+            .iter()
+            .map(|&index| kvm_msr_entry {
+                index,
+                ..Default::default()
+            })
+            .collect();
+        let mut entries = Msrs::from_entries(&entries)?;
+        let read = vcpu.get_msrs(&mut entries)?;
+        if read != msrs.len() {
+            bail!("could only read {read} of {} MSRs", msrs.len());
+        }
+        Ok(VcpuState {
+            regs: vcpu.get_regs()?,
+            sregs: vcpu.get_sregs()?,
+            xsave: vcpu.get_xsave()?,
+            xcrs: vcpu.get_xcrs()?,
+            lapic: vcpu.get_lapic()?,
+            msrs: entries.as_slice().to_vec(),
+            events: vcpu.get_vcpu_events()?,
+            mp_state: vcpu.get_mp_state()?,
+            debug_regs: vcpu.get_debug_regs()?,
+        })
+    }
+
+    /// Loads this state into a freshly created vCPU with the same CPUID.
+    pub fn restore(&self, vcpu: &VcpuFd) -> anyhow::Result<()> {
+        // the APIC base is part of sregs, and the TSC deadline is only meaningful once the LAPIC
+        // is in TSC-deadline mode
+        vcpu.set_sregs(&self.sregs)?;
+        vcpu.set_regs(&self.regs)?;
+        unsafe { vcpu.set_xsave(&self.xsave)? };
+        vcpu.set_xcrs(&self.xcrs)?;
+        vcpu.set_lapic(&self.lapic)?;
+        let written = vcpu.set_msrs(&Msrs::from_entries(&self.msrs)?)?;
+        if written != self.msrs.len() {
+            bail!("could only write {written} of {} MSRs", self.msrs.len());
+        }
+        vcpu.set_vcpu_events(&self.events)?;
+        vcpu.set_mp_state(self.mp_state)?;
+        vcpu.set_debug_regs(&self.debug_regs)?;
+        Ok(())
+    }
+}
+
+/// What a host service was serving to the guest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Resource {
+    Control,
+    File {
+        path: PathBuf,
+        read: bool,
+        write: bool,
+        append: bool,
+        offset: u64,
+    },
+    Listener(SocketAddr),
+    Stream,
+    Debugger,
+}
+
+/// A host service and the host end of its pipe.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Service {
+    pub pipe: PipeData,
+    pub resource: Resource,
+    /// A request the service had read but not yet answered.
+    pub pending: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    service: Service,
+    /// A handle sharing the served file's offset, to find where it had got to.
+    file: Option<File>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: bool,
+    /// The number of services in the middle of transferring a message.
+    busy: usize,
+    next: u64,
+    services: BTreeMap<u64, Entry>,
+    parked: BTreeMap<usize, anyhow::Result<VcpuState>>,
+}
+
+/// Coordinates pausing the host services and vCPUs for a snapshot, and keeps track of the
+/// services so they can be saved.
+#[derive(Debug, Default)]
+pub struct Gate {
+    /// Mirrors `State::paused`, so vCPUs can check it cheaply on every exit.
+    pausing: AtomicBool,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// How often a blocked thread rechecks whether the VMM is shutting down.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl Gate {
+    /// Registers a service whose host end is `pipe`; it is forgotten when the registration is
+    /// dropped.  `pending` is a request to serve again before reading from the pipe.
+    pub fn register(
+        self: &Arc<Self>,
+        pipe: PipeData,
+        resource: Resource,
+        file: Option<File>,
+        pending: Option<Vec<u8>>,
+    ) -> Registration {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next;
+        state.next += 1;
+        state.services.insert(
+            id,
+            Entry {
+                service: Service {
+                    pipe,
+                    resource,
+                    pending: pending.clone(),
+                },
+                file,
+            },
+        );
+        Registration {
+            gate: self.clone(),
+            id,
+            replay: pending,
+        }
+    }
+
+    /// Starts transferring a message, waiting while the VM is paused; `None` means the VMM is
+    /// shutting down.
+    fn enter(self: &Arc<Self>, shutdown: &Shutdown) -> Option<Busy> {
+        let mut state = self.state.lock().unwrap();
+        while state.paused {
+            if shutdown.requested() {
+                return None;
+            }
+            state = self.changed.wait_timeout(state, POLL_INTERVAL).unwrap().0;
+        }
+        state.busy += 1;
+        Some(Busy(self.clone()))
+    }
+
+    /// Whether vCPUs should park.
+    pub fn pausing(&self) -> bool {
+        self.pausing.load(Ordering::Acquire)
+    }
+
+    /// Stops host services between messages, then asks vCPUs to park.
+    ///
+    /// A message in flight may need the guest to fill or drain its pipe before it completes, so
+    /// the vCPUs keep running until every transfer has finished.
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = true;
+        while state.busy > 0 {
+            state = self.changed.wait(state).unwrap();
+        }
+        self.pausing.store(true, Ordering::Release);
+    }
+
+    /// Hands over a vCPU's saved state and waits for the VM to resume.
+    pub fn park(&self, cpu: usize, saved: anyhow::Result<VcpuState>, shutdown: &Shutdown) {
+        let mut state = self.state.lock().unwrap();
+        state.parked.insert(cpu, saved);
+        self.changed.notify_all();
+        while state.paused && !shutdown.requested() {
+            state = self.changed.wait_timeout(state, POLL_INTERVAL).unwrap().0;
+        }
+    }
+
+    /// Waits for all `cores` vCPUs to park, calling `kick` until they do, and takes their state.
+    fn parked(
+        &self,
+        cores: usize,
+        shutdown: &Shutdown,
+        kick: impl Fn(),
+    ) -> anyhow::Result<Vec<VcpuState>> {
+        let mut state = self.state.lock().unwrap();
+        while state.parked.len() < cores {
+            if shutdown.requested() {
+                bail!("the VM shut down while pausing");
+            }
+            kick();
+            state = self.changed.wait_timeout(state, POLL_INTERVAL).unwrap().0;
+        }
+        std::mem::take(&mut state.parked)
+            .into_iter()
+            .map(|(i, x)| x.with_context(|| format!("could not save vCPU {i}")))
+            .collect()
+    }
+
+    /// The open services, with the offsets their files had reached.
+    fn services(&self) -> io::Result<Vec<Service>> {
+        let mut state = self.state.lock().unwrap();
+        let mut services = vec![];
+        for entry in state.services.values_mut() {
+            let mut resource = entry.service.resource.clone();
+            if let (Resource::File { offset, .. }, Some(file), None) =
+                (&mut resource, &mut entry.file, &entry.service.pending)
+            {
+                *offset = file.stream_position()?;
+            }
+            services.push(Service {
+                pipe: entry.service.pipe.clone(),
+                resource,
+                pending: entry.service.pending.clone(),
+            });
+        }
+        Ok(services)
+    }
+
+    /// Lets the vCPUs and host services continue.
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = false;
+        state.parked.clear();
+        self.pausing.store(false, Ordering::Release);
+        self.changed.notify_all();
+    }
+}
+
+/// A service's message transfer in progress; the VM cannot be paused until it is dropped.
+#[derive(Debug)]
+pub struct Busy(Arc<Gate>);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.busy -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+/// A service known to a [`Gate`].
+#[derive(Debug)]
+pub struct Registration {
+    gate: Arc<Gate>,
+    id: u64,
+    replay: Option<Vec<u8>>,
+}
+
+impl Registration {
+    pub fn enter(&self, shutdown: &Shutdown) -> Option<Busy> {
+        self.gate.enter(shutdown)
+    }
+
+    /// Takes the request left unanswered when the snapshot was taken, if it has not been served.
+    pub fn replay(&mut self) -> Option<Vec<u8>> {
+        self.replay.take()
+    }
+
+    /// Notes that `request` has been read and is being served.
+    pub fn received(&self, request: &[u8]) {
+        let mut state = self.gate.state.lock().unwrap();
+        let entry = state.services.get_mut(&self.id).unwrap();
+        entry.service.pending = Some(request.to_vec());
+        // the request has not touched the file yet, so this is where serving it again starts
+        if let (Resource::File { offset, .. }, Some(file)) =
+            (&mut entry.service.resource, &mut entry.file)
+        {
+            if let Ok(position) = file.stream_position() {
+                *offset = position;
+            }
+        }
+    }
+
+    /// Notes that the pending request has been answered.
+    pub fn answered(&self) {
+        let mut state = self.gate.state.lock().unwrap();
+        state.services.get_mut(&self.id).unwrap().service.pending = None;
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.gate.state.lock().unwrap().services.remove(&self.id);
+    }
+}
+
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_usr1(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// Asks for a snapshot whenever the VMM gets `SIGUSR1`.
+pub fn handle_signal() {
+    vmm_sys_util::signal::register_signal_handler(libc::SIGUSR1, handle_usr1)
+        .expect("could not install snapshot signal handler");
+}
+
+/// Whether a snapshot has been asked for since this was last called.
+pub fn signalled() -> bool {
+    SIGNALLED.swap(false, Ordering::SeqCst)
+}
+
+/// The other end of a pipe, e.g. the host end given the guest end.
+pub fn mirror(pipe: &PipeData) -> PipeData {
+    PipeData {
+        rx_ptr: pipe.tx_ptr,
+        rx_len: pipe.tx_len,
+        tx_ptr: pipe.rx_ptr,
+        tx_len: pipe.rx_len,
+    }
+}
+
+/// Everything in a snapshot but guest memory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub cores: usize,
+    pub memory: usize,
+    /// A hash of the kernel ELF, which must be the same on restore.
+    pub kernel: u64,
+    /// Where the buddy allocator keeps its state within guest memory.
+    pub allocator: [usize; 4],
+    pub argv: Vec<String>,
+    pub clock: kvm_clock_data,
+    pub irqchips: Vec<kvm_irqchip>,
+    pub vcpus: Vec<VcpuState>,
+    pub services: Vec<Service>,
+}
+
+/// Identifies the kernel a snapshot was taken of.
+pub fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The layout of the buddy allocator's state, which must match on restore.
+pub fn allocator() -> [usize; 4] {
+    let raw = common::buddy::export();
+    [
+        raw.inner_offset,
+        raw.inner_size,
+        raw.refcnt_offset,
+        raw.refcnt_size,
+    ]
+}
+
+/// Pauses the VM long enough to write a snapshot of it to `path`.
+pub fn take(
+    path: &Path,
+    cores: usize,
+    gate: &Gate,
+    shutdown: &Shutdown,
+    save: impl FnOnce(Vec<VcpuState>, Vec<Service>) -> anyhow::Result<Header>,
+) -> anyhow::Result<()> {
+    gate.pause();
+    let result = (|| {
+        let vcpus = gate.parked(cores, shutdown, || shutdown.kick())?;
+        let header = save(vcpus, gate.services()?)?;
+        write(path, &header)
+    })();
+    gate.resume();
+    result
+}
+
+/// Writes `header` and the non-zero pages of guest memory to `path`.
+fn write(path: &Path, header: &Header) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    let header = postcard::to_allocvec(header)?;
+    out.write_all(&(header.len() as u64).to_le_bytes())?;
+    out.write_all(&header)?;
+    let memory = unsafe {
+        std::slice::from_raw_parts(
+            common::BuddyAllocator.base() as *const u8,
+            common::BuddyAllocator.len(),
+        )
+    };
+    write_memory(&mut out, memory)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes the non-zero pages of `memory` as runs of `(offset, length, bytes)`.
+///
+/// Every page is read: `mincore` cannot tell a page that was never touched from one that has
+/// been swapped out, so there is no cheap way to skip the untouched ones.
+fn write_memory(out: &mut impl Write, memory: &[u8]) -> io::Result<()> {
+    let nonzero = |i: usize| {
+        memory[i * PAGE_SIZE..(i + 1) * PAGE_SIZE]
+            .iter()
+            .any(|&x| x != 0)
+    };
+    let pages = memory.len() / PAGE_SIZE;
+    let mut i = 0;
+    while i < pages {
+        if !nonzero(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < pages && nonzero(i) {
+            i += 1;
+        }
+        let bytes = &memory[start * PAGE_SIZE..i * PAGE_SIZE];
+        out.write_all(&((start * PAGE_SIZE) as u64).to_le_bytes())?;
+        out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        out.write_all(bytes)?;
+    }
+    out.write_all(&END.to_le_bytes())
+}
+
+/// Fills `memory` from runs written by [`write_memory`]; the pages between runs are zeroed.
+fn read_memory(input: &mut impl Read, memory: &mut [u8]) -> anyhow::Result<()> {
+    let mut word = [0; 8];
+    let mut next = |input: &mut dyn Read| -> io::Result<u64> {
+        input.read_exact(&mut word)?;
+        Ok(u64::from_le_bytes(word))
+    };
+    let mut zeroed = 0;
+    loop {
+        let offset = next(input)?;
+        if offset == END {
+            break;
+        }
+        let (offset, len) = (offset as usize, next(input)? as usize);
+        if offset < zeroed || offset.checked_add(len).is_none_or(|end| end > memory.len()) {
+            bail!("bad memory run at {offset:#x}");
+        }
+        zero(&mut memory[zeroed..offset]);
+        input.read_exact(&mut memory[offset..offset + len])?;
+        zeroed = offset + len;
+    }
+    zero(&mut memory[zeroed..]);
+    Ok(())
+}
+
+/// Zeroes `memory` without touching the pages that are already untouched.
+fn zero(memory: &mut [u8]) {
+    if memory.is_empty() {
+        return;
+    }
+    // dropping the pages of shared memory reads them back as zero; if that fails (or `memory`
+    // is not page-aligned, as in tests), write the zeroes instead
+    let aligned = (memory.as_ptr() as usize).is_multiple_of(PAGE_SIZE)
+        && memory.len().is_multiple_of(PAGE_SIZE);
+    let removed = aligned
+        && unsafe {
+            libc::madvise(
+                memory.as_mut_ptr() as *mut libc::c_void,
+                memory.len(),
+                libc::MADV_REMOVE,
+            ) == 0
+        };
+    if !removed {
+        memory.fill(0);
+    }
+}
+
+/// A snapshot file opened for restoring, positioned at its memory.
+pub struct Snapshot {
+    pub header: Header,
+    input: BufReader<File>,
+}
+
+impl Snapshot {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        let size = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{} is not a snapshot", path.display());
+        }
+        let mut word = [0; 4];
+        input.read_exact(&mut word)?;
+        let version = u32::from_le_bytes(word);
+        if version != VERSION {
+            bail!("unsupported snapshot version {version}");
+        }
+        let header = read_header(&mut input, size - (MAGIC.len() + word.len()) as u64)?;
+        Ok(Snapshot { header, input })
+    }
+
+    /// Overwrites guest memory with the snapshot's.
+    pub fn load_memory(&mut self) -> anyhow::Result<()> {
+        let memory = unsafe {
+            std::slice::from_raw_parts_mut(
+                common::BuddyAllocator.base() as *mut u8,
+                common::BuddyAllocator.len(),
+            )
+        };
+        read_memory(&mut self.input, memory)
+    }
+}
+
+/// Reads the length-prefixed header from `input`, which has `remaining` bytes left.
+///
+/// The length is checked against `remaining` before anything is allocated for it, so a corrupt
+/// or truncated file fails cleanly instead of asking for an absurd buffer.
+fn read_header(input: &mut impl Read, remaining: u64) -> anyhow::Result<Header> {
+    let mut length = [0; 8];
+    input.read_exact(&mut length)?;
+    let length = u64::from_le_bytes(length);
+    if length > remaining.saturating_sub(length.to_le_bytes().len() as u64) {
+        bail!("snapshot header of {length} bytes is longer than the file");
+    }
+    let mut header = vec![0; length as usize];
+    input.read_exact(&mut header)?;
+    Ok(postcard::from_bytes(&header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_runs() {
+        let mut memory = vec![0u8; PAGE_SIZE * 6];
+        memory[10] = 1;
+        memory[PAGE_SIZE + 20] = 2;
+        memory[PAGE_SIZE * 4] = 3;
+        let mut out = vec![];
+        write_memory(&mut out, &memory).unwrap();
+        // two runs, each with an offset and length, then the end marker
+        assert_eq!(out.len(), 16 + 2 * PAGE_SIZE + 16 + PAGE_SIZE + 8);
+
+        let mut restored = vec![0xffu8; memory.len()];
+        read_memory(&mut &out[..], &mut restored).unwrap();
+        assert_eq!(restored, memory);
+    }
+
+    /// Verifies a header length running past the end of the file is rejected before allocating.
+    #[test]
+    fn test_header_length() {
+        let mut file = u64::MAX.to_le_bytes().to_vec();
+        file.extend([0; 16]);
+        let error = read_header(&mut &file[..], file.len() as u64).unwrap_err();
+        assert!(error.to_string().contains("longer than the file"));
+
+        let file = 17u64.to_le_bytes();
+        assert!(read_header(&mut &file[..], file.len() as u64).is_err());
+    }
+
+    #[test]
+    fn test_mirror() {
+        let pipe = PipeData {
+            rx_ptr: 1,
+            rx_len: 2,
+            tx_ptr: 3,
+            tx_len: 4,
+        };
+        let host = mirror(&pipe);
+        assert_eq!((host.rx_ptr, host.rx_len), (3, 4));
+        assert_eq!((mirror(&host).rx_ptr, mirror(&host).tx_len), (1, 4));
+    }
+}